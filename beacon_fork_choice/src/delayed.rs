@@ -0,0 +1,146 @@
+//! Objects whose processing is delayed until a block is received or a slot is reached.
+//!
+//! Delayed objects come from the network, so the queues holding them are limited in size.
+//! See [`DelayLimits`].
+
+use core::mem;
+use std::collections::BTreeMap;
+
+use helper_functions::crypto;
+use transition_functions::blocks::block_signatures::VerifySignatures;
+use types::{
+    config::Config,
+    primitives::{Slot, H256},
+    types::{Attestation, BeaconBlock},
+};
+
+/// Where an object passed to [`Store`] came from.
+///
+/// [`Store`]: crate::Store
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Origin {
+    /// Produced or requested by the node itself. Not subject to [`DelayLimits::max_objects_per_peer`].
+    Own,
+    /// Received from the peer with the given key. Keys only need to be distinct between peers.
+    Peer(u64),
+}
+
+/// Limits on objects delayed by [`Store`].
+///
+/// Objects that would exceed the limits are rejected.
+///
+/// [`Store`]: crate::Store
+#[derive(Clone, Copy, Debug)]
+pub struct DelayLimits {
+    /// The maximum number of objects delayed until a block and, separately, until a slot.
+    pub max_objects_per_queue: usize,
+    /// The maximum number of objects from a single peer delayed for any reason.
+    pub max_objects_per_peer: usize,
+    /// Objects delayed for more slots than this are discarded.
+    pub max_age_slots: u64,
+    /// Blocks from more slots than this in the future are rejected instead of being delayed.
+    pub max_future_slots: u64,
+}
+
+impl Default for DelayLimits {
+    fn default() -> Self {
+        Self {
+            max_objects_per_queue: 4096,
+            max_objects_per_peer: 512,
+            max_age_slots: 64,
+            max_future_slots: 64,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum DelayedObject<C: Config> {
+    BeaconBlock(BeaconBlock<C>, VerifySignatures),
+    Attestation(Attestation<C>),
+}
+
+pub struct Delayed<C: Config> {
+    pub object: DelayedObject<C>,
+    pub origin: Origin,
+    /// `signed_root` for blocks and `hash_tree_root` for attestations.
+    pub root: H256,
+    /// The slot the object was first delayed in. Retrying an object does not reset its age.
+    pub delayed_at: Slot,
+}
+
+impl<C: Config> Delayed<C> {
+    pub fn new(object: DelayedObject<C>, origin: Origin, delayed_at: Slot) -> Self {
+        let root = match &object {
+            DelayedObject::BeaconBlock(block, _) => crypto::signed_root(block),
+            DelayedObject::Attestation(attestation) => crypto::hash_tree_root(attestation),
+        };
+        Self {
+            object,
+            origin,
+            root,
+            delayed_at,
+        }
+    }
+}
+
+pub struct DelayedQueue<K, C: Config> {
+    objects: BTreeMap<K, Vec<Delayed<C>>>,
+    len: usize,
+}
+
+impl<K: Ord, C: Config> DelayedQueue<K, C> {
+    pub fn new() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, key: K, delayed: Delayed<C>) {
+        self.objects.entry(key).or_default().push(delayed);
+        self.len += 1;
+    }
+
+    pub fn take(&mut self, key: &K) -> Vec<Delayed<C>> {
+        let objects = self.objects.remove(key).unwrap_or_default();
+        self.len -= objects.len();
+        objects
+    }
+
+    /// Removes and returns objects delayed before `slot`.
+    pub fn take_delayed_before(&mut self, slot: Slot) -> Vec<Delayed<C>> {
+        let mut expired = vec![];
+
+        for (key, objects) in mem::replace(&mut self.objects, BTreeMap::new()) {
+            let (mut old, new): (Vec<_>, Vec<_>) = objects
+                .into_iter()
+                .partition(|delayed| delayed.delayed_at < slot);
+            expired.append(&mut old);
+            if !new.is_empty() {
+                self.objects.insert(key, new);
+            }
+        }
+
+        self.len -= expired.len();
+        expired
+    }
+}
+
+impl<C: Config> DelayedQueue<Slot, C> {
+    /// Removes and returns objects delayed until `slot` or earlier slots.
+    pub fn take_until(&mut self, slot: Slot) -> Vec<Delayed<C>> {
+        let later_slots = self.objects.split_off(&(slot + 1));
+        let fulfilled_slots = mem::replace(&mut self.objects, later_slots);
+        let objects = fulfilled_slots
+            .into_iter()
+            .flat_map(|(_, objects)| objects)
+            .collect::<Vec<_>>();
+        self.len -= objects.len();
+        objects
+    }
+}
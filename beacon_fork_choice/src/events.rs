@@ -0,0 +1,52 @@
+//! Notifications about changes to the fork choice.
+//!
+//! See [`Store::subscribe`].
+//!
+//! [`Store::subscribe`]: crate::Store::subscribe
+
+use futures::sync::mpsc::UnboundedSender;
+use types::{
+    primitives::{Slot, H256},
+    types::Checkpoint,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The head changed. Preceded by [`Event::ChainReorg`] if the old head is not an ancestor of
+    /// the new one.
+    NewHead {
+        root: H256,
+        slot: Slot,
+    },
+    /// `depth` is the number of slots between `old_head` and the latest block it has in common
+    /// with `new_head`.
+    ChainReorg {
+        old_head: H256,
+        new_head: H256,
+        depth: u64,
+    },
+    Justified(Checkpoint),
+    Finalized(Checkpoint),
+}
+
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Vec<UnboundedSender<Event>>,
+}
+
+impl Subscribers {
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    pub fn add(&mut self, sender: UnboundedSender<Event>) {
+        self.senders.push(sender)
+    }
+
+    /// Sends `event` to every subscriber. Subscribers whose receivers have been dropped are
+    /// removed.
+    pub fn send(&mut self, event: Event) {
+        self.senders
+            .retain(|sender| sender.unbounded_send(event).is_ok())
+    }
+}
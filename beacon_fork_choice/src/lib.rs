@@ -1,54 +1,126 @@
 //! Based on the naive LMD-GHOST fork choice rule implementation in the specification:
 //! <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md>
 //!
+//! Proposer boost, equivocation handling and `should_update_justified_checkpoint` are taken from
+//! a later version of the specification:
+//! <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md>
+//!
 //! `assert`s from Python are represented by statements that either delay the processing of the
 //! offending object or return `Err`. All other operations that can raise exceptions in Python
 //! (like indexing into `dict`s) are represented by statements that panic on failure.
 
-use core::{cmp::Ordering, convert::TryInto as _, mem};
-use std::collections::{BTreeMap, HashMap};
+use core::{cmp::Ordering, convert::TryInto as _, time::Duration};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use anyhow::{ensure, Result};
 use error_utils::DebugAsError;
+use futures::sync::mpsc::{self, UnboundedReceiver};
 use helper_functions::{beacon_state_accessors, crypto, misc, predicates};
-use log::info;
+use log::{info, warn};
 use maplit::hashmap;
 use thiserror::Error;
-use transition_functions::process_slot;
+use transition_functions::{
+    blocks::block_signatures::VerifySignatures, observer::StateTransitionObserver, process_slot,
+};
+use typenum::Unsigned as _;
 use types::{
     config::Config,
-    primitives::{Epoch, Gwei, Slot, ValidatorIndex, H256},
-    types::{Attestation, BeaconBlock, Checkpoint},
+    primitives::{Epoch, Gwei, Slot, UnixSeconds, ValidatorIndex, H256},
+    types::{Attestation, AttesterSlashing, BeaconBlock, Checkpoint},
     BeaconState,
 };
 
+use crate::{
+    delayed::{Delayed, DelayedObject, DelayedQueue},
+    events::Subscribers,
+};
+
+pub use crate::{
+    delayed::{DelayLimits, Origin},
+    events::Event,
+};
+
+mod delayed;
+mod events;
+
+#[cfg(test)]
+mod spec_tests;
+
+/// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/networking/p2p-interface.md#configuration>
+pub const MAXIMUM_GOSSIP_CLOCK_DISPARITY: Duration = Duration::from_millis(500);
+
+/// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#configuration>
+///
+/// A percentage of the average weight of a committee.
+pub const PROPOSER_SCORE_BOOST: u64 = 40;
+
+/// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/validator.md#constants>
+const INTERVALS_PER_SLOT: u64 = 3;
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Error)]
 enum Error<C: Config> {
     #[error("slot {new_slot} is not later than {old_slot}")]
     SlotNotLater { old_slot: Slot, new_slot: Slot },
+    #[error("anchor block does not match anchor state (block_state_root: {block_state_root:?}, state_root: {state_root:?})")]
+    AnchorStateRootMismatch {
+        block_state_root: H256,
+        state_root: H256,
+    },
+    #[error("anchor block slot {block_slot} does not match anchor state slot {state_slot}")]
+    AnchorSlotMismatch { block_slot: Slot, state_slot: Slot },
     #[error("block is not a descendant of finalized block (block: {block:?}, finalized_block: {finalized_block:?})")]
     NotDescendantOfFinalized {
         block: BeaconBlock<C>,
         finalized_block: BeaconBlock<C>,
     },
+    #[error("block slot {block_slot} is too far in the future (current slot: {current_slot})")]
+    BlockTooFarInFuture {
+        block_slot: Slot,
+        current_slot: Slot,
+    },
+    #[error("too many delayed objects (origin: {origin:?})")]
+    DelayQueueFull { origin: Origin },
+    #[error("too many delayed objects from peer (origin: {origin:?})")]
+    TooManyDelayedObjectsFromPeer { origin: Origin },
+    #[error("attestation votes for a block from a later slot: {attestation:?}")]
+    AttestationForFutureBlock { attestation: Attestation<C> },
+    #[error("attestation target is not an ancestor of the block voted for: {attestation:?}")]
+    AttestationTargetInconsistent { attestation: Attestation<C> },
+    #[error("attestations in attester slashing are not slashable: {attester_slashing:?}")]
+    AttestationsNotSlashable {
+        attester_slashing: AttesterSlashing<C>,
+    },
 }
 
 /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#latestmessage>
 type LatestMessage = Checkpoint;
 
-#[allow(clippy::large_enum_variant)]
-#[derive(Debug)]
-enum DelayedObject<C: Config> {
-    BeaconBlock(BeaconBlock<C>),
-    Attestation(Attestation<C>),
+#[derive(Clone, Copy, Debug)]
+enum Delay {
+    UntilBlock(H256),
+    UntilSlot(Slot),
+}
+
+enum Readiness {
+    Ready,
+    Ignore,
+    Delay(Delay),
 }
 
 /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#store>
 pub struct Store<C: Config> {
+    // The specification stores the time in seconds. Milliseconds are needed to apply
+    // `maximum_clock_disparity`.
+    time_millis: u64,
+    genesis_time: UnixSeconds,
     slot: Slot,
     justified_checkpoint: Checkpoint,
     finalized_checkpoint: Checkpoint,
+    best_justified_checkpoint: Checkpoint,
+    // Zero when no block should be boosted, like `Root()` in the specification.
+    proposer_boost_root: H256,
+    equivocating_indices: HashSet<ValidatorIndex>,
     // `blocks` and `block_states` could be combined into a single map.
     // We've left them separate to match the specification more closely.
     blocks: HashMap<H256, BeaconBlock<C>>,
@@ -57,8 +129,33 @@ pub struct Store<C: Config> {
     latest_messages: HashMap<ValidatorIndex, LatestMessage>,
 
     // Extra fields used for delaying and retrying objects.
-    delayed_until_block: HashMap<H256, Vec<DelayedObject<C>>>,
-    delayed_until_slot: BTreeMap<Slot, Vec<DelayedObject<C>>>,
+    delayed_until_block: DelayedQueue<H256, C>,
+    delayed_until_slot: DelayedQueue<Slot, C>,
+    // Used to ignore objects that are already delayed.
+    delayed_roots: HashSet<H256>,
+    delayed_objects_per_origin: HashMap<Origin, usize>,
+    // Objects that are no longer delayed but have not been processed yet.
+    ready_objects: VecDeque<Delayed<C>>,
+    delay_limits: DelayLimits,
+
+    // Not part of the specification. Objects from slots that start within this much time of the
+    // current time are not delayed.
+    maximum_clock_disparity: Duration,
+
+    // Not part of the specification. Receives the steps of state transitions run by `on_block`.
+    state_transition_observer: Option<Box<dyn StateTransitionObserver<C> + Send>>,
+
+    // Not part of the specification. Used to send `Event`s.
+    subscribers: Subscribers,
+    // The head last reported in `Event::NewHead`.
+    reported_head: H256,
+    // Set when blocks or attestations are applied. Avoids running `Store::head_root` when
+    // nothing could have changed its result.
+    head_may_have_changed: bool,
+    // Set when a block is applied, the justified checkpoint changes or a new slot starts. Changes
+    // caused by attestations are only reported after one of those so that the head is not
+    // recomputed for every attestation.
+    head_report_due: bool,
 }
 
 impl<C: Config> Store<C> {
@@ -72,38 +169,138 @@ impl<C: Config> Store<C> {
             // Note that:
             // - `BeaconBlock.body.eth1_data` is not set to `state.latest_eth1_data`.
             // - `BeaconBlock.slot` is set to 0 even if `C::genesis_slot()` is not 0.
-            state_root: crypto::hash_tree_root(&genesis_state),
+            state_root: genesis_state.canonical_root(),
             ..BeaconBlock::default()
         };
 
-        let epoch = C::genesis_epoch();
-        let root = crypto::signed_root(&genesis_block);
+        Self::from_anchor(genesis_state, genesis_block, C::genesis_epoch())
+    }
+
+    /// Creates a `Store` that starts from a recent finalized state instead of the genesis state.
+    ///
+    /// Based on `get_forkchoice_store` from a later version of the specification:
+    /// <https://github.com/ethereum/eth2.0-specs/blob/579da6d2dc734b269dbf67aa1004b54bb9449784/specs/phase0/fork-choice.md#get_forkchoice_store>
+    ///
+    /// `anchor_block` must be the block that produced `anchor_state`. Blocks older than
+    /// `anchor_block` are not needed and will be ignored if received.
+    pub fn from_checkpoint(
+        anchor_state: BeaconState<C>,
+        anchor_block: BeaconBlock<C>,
+    ) -> Result<Self> {
+        let state_root = anchor_state.canonical_root();
+
+        ensure!(
+            anchor_block.slot == anchor_state.slot,
+            Error::<C>::AnchorSlotMismatch {
+                block_slot: anchor_block.slot,
+                state_slot: anchor_state.slot,
+            },
+        );
+
+        ensure!(
+            anchor_block.state_root == state_root,
+            Error::<C>::AnchorStateRootMismatch {
+                block_state_root: anchor_block.state_root,
+                state_root,
+            },
+        );
+
+        let epoch = misc::compute_epoch_at_slot::<C>(anchor_state.slot);
+
+        Ok(Self::from_anchor(anchor_state, anchor_block, epoch))
+    }
+
+    fn from_anchor(
+        anchor_state: BeaconState<C>,
+        anchor_block: BeaconBlock<C>,
+        epoch: Epoch,
+    ) -> Self {
+        let root = crypto::signed_root(&anchor_block);
         let checkpoint = Checkpoint { epoch, root };
 
+        let genesis_time = anchor_state.genesis_time;
+        let slot = anchor_state.slot;
+
         Self {
-            slot: genesis_state.slot,
+            time_millis: Self::compute_slot_start_millis(genesis_time, slot),
+            genesis_time,
+            slot,
             justified_checkpoint: checkpoint,
             finalized_checkpoint: checkpoint,
-            blocks: hashmap! {root => genesis_block},
-            block_states: hashmap! {root => genesis_state.clone()},
-            checkpoint_states: hashmap! {checkpoint => genesis_state},
+            best_justified_checkpoint: checkpoint,
+            proposer_boost_root: H256::zero(),
+            equivocating_indices: HashSet::new(),
+            blocks: hashmap! {root => anchor_block},
+            block_states: hashmap! {root => anchor_state.clone()},
+            checkpoint_states: hashmap! {checkpoint => anchor_state},
             latest_messages: hashmap! {},
 
-            delayed_until_slot: BTreeMap::new(),
-            delayed_until_block: HashMap::new(),
+            delayed_until_block: DelayedQueue::new(),
+            delayed_until_slot: DelayedQueue::new(),
+            delayed_roots: HashSet::new(),
+            delayed_objects_per_origin: HashMap::new(),
+            ready_objects: VecDeque::new(),
+            delay_limits: DelayLimits::default(),
+
+            maximum_clock_disparity: MAXIMUM_GOSSIP_CLOCK_DISPARITY,
+
+            state_transition_observer: None,
+
+            subscribers: Subscribers::default(),
+            reported_head: root,
+            head_may_have_changed: false,
+            head_report_due: false,
         }
     }
 
-    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#get_head>
+    /// Replaces the default allowance of [`MAXIMUM_GOSSIP_CLOCK_DISPARITY`].
+    pub fn set_maximum_clock_disparity(&mut self, maximum_clock_disparity: Duration) {
+        self.maximum_clock_disparity = maximum_clock_disparity;
+    }
+
+    pub fn set_delay_limits(&mut self, delay_limits: DelayLimits) {
+        self.delay_limits = delay_limits;
+    }
+
+    /// Makes `on_block` report every step of the state transitions it runs to `observer`.
+    pub fn set_state_transition_observer(
+        &mut self,
+        observer: Box<dyn StateTransitionObserver<C> + Send>,
+    ) {
+        self.state_transition_observer = Some(observer);
+    }
+
+    /// Returns a stream of [`Event`]s that occur after this call.
     ///
-    /// Unlike the `get_head` function in the specification, this returns the [`BeaconState`]
-    /// produced after processing the current head block.
+    /// The head is only compared with the previous one while there are subscribers, so it is
+    /// better to subscribe before processing any objects. The stream ends when `self` is dropped.
+    ///
+    /// The head is compared after blocks are applied, after the justified checkpoint changes and at
+    /// the start of every slot. Changes caused by attestations or attester slashings are reported
+    /// at the next of those points rather than immediately.
+    pub fn subscribe(&mut self) -> UnboundedReceiver<Event> {
+        if self.subscribers.is_empty() {
+            self.reported_head = self.head_root();
+            self.head_may_have_changed = false;
+            self.head_report_due = false;
+        }
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.add(sender);
+        receiver
+    }
+
+    /// Returns the [`BeaconState`] produced after processing the current head block.
     pub fn head_state(&self) -> &BeaconState<C> {
+        &self.block_states[&self.head_root()]
+    }
+
+    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#get_head>
+    pub fn head_root(&self) -> H256 {
         let mut current_root = self.justified_checkpoint.root;
 
         let justified_slot = Self::epoch_start_slot(self.justified_checkpoint.epoch);
 
-        let head_root = loop {
+        loop {
             let mut child_with_plurality = None;
 
             for (&root, block) in &self.blocks {
@@ -117,15 +314,32 @@ impl<C: Config> Store<C> {
                 Some((_, root)) => current_root = root,
                 None => break current_root,
             }
-        };
-
-        &self.block_states[&head_root]
+        }
     }
 
     /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#on_tick>
     ///
-    /// Unlike `on_tick` in the specification, this should be called at the start of a slot instead
-    /// of every second. The fork choice rule doesn't need a precise timestamp.
+    /// Unlike `on_tick` in the specification, this takes the time in milliseconds since the Unix
+    /// epoch. Times earlier than the current time of the store are ignored, because they can come
+    /// from clocks that are adjusted backwards.
+    pub fn on_tick(&mut self, unix_millis: u64) -> Result<()> {
+        if unix_millis <= self.time_millis {
+            return Ok(());
+        }
+        let previous_slot = self.slot;
+        self.time_millis = unix_millis;
+        self.slot = self.slot.max(self.slot_at_millis(unix_millis));
+        self.on_time_advanced(previous_slot);
+        Ok(())
+    }
+
+    /// Like [`Store::on_tick`], but with the time at which `slot` starts.
+    /// Meant for callers that are only notified of slots.
+    ///
+    /// Objects processed after this are treated as if they arrived at the start of `slot` until
+    /// the time is advanced again. Callers that receive objects at other times should pass the
+    /// time of arrival to [`Store::on_tick`] instead, or proposer boost will be applied to blocks
+    /// that arrive too late for it.
     pub fn on_slot(&mut self, slot: Slot) -> Result<()> {
         ensure!(
             self.slot < slot,
@@ -134,38 +348,255 @@ impl<C: Config> Store<C> {
                 new_slot: slot
             },
         );
+        let previous_slot = self.slot;
         self.slot = slot;
-        self.retry_delayed_until_slot(slot)
+        self.time_millis = self.time_millis.max(self.slot_start_millis(slot));
+        self.on_time_advanced(previous_slot);
+        Ok(())
     }
 
     /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#on_block>
-    pub fn on_block(&mut self, block: BeaconBlock<C>) -> Result<()> {
-        // The specification uses 2 different ways to calculate what appears to be the same value:
-        // - <https://github.com/ethereum/eth2.0-specs/blame/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#L155>
-        // - <https://github.com/ethereum/eth2.0-specs/blame/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#L159>
-        // We assume this is an oversight.
-        let finalized_slot = Self::epoch_start_slot(self.finalized_checkpoint.epoch);
-
-        // Ignore blocks from slots not later than the finalized block. Doing so ensures that:
-        // - The genesis block is accepted even though it does not represent a state transition.
-        // - Blocks that are already known and are received again are always accepted.
-        if block.slot <= finalized_slot {
-            return Ok(());
+    ///
+    /// `verify_signatures` is passed on to the state transition. If `block` is delayed, it is
+    /// remembered along with the block.
+    ///
+    /// Whether `block` is timely enough for proposer boost is determined by the current time of
+    /// the store. Callers should pass the time at which `block` arrived to [`Store::on_tick`]
+    /// before calling this.
+    ///
+    /// Objects that were waiting for `block` are processed before this returns. Errors caused by
+    /// them are logged rather than returned.
+    pub fn on_block(
+        &mut self,
+        block: BeaconBlock<C>,
+        verify_signatures: VerifySignatures,
+        origin: Origin,
+    ) -> Result<()> {
+        let object = DelayedObject::BeaconBlock(block, verify_signatures);
+        self.process(Delayed::new(object, origin, self.slot))?;
+        self.process_ready_objects();
+        self.report_head_change();
+        Ok(())
+    }
+
+    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#on_attestation>
+    pub fn on_attestation(&mut self, attestation: Attestation<C>, origin: Origin) -> Result<()> {
+        let object = DelayedObject::Attestation(attestation);
+        self.process(Delayed::new(object, origin, self.slot))
+    }
+
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#on_attester_slashing>
+    ///
+    /// Attester slashings included in blocks are handled by [`Store::on_block`]. Only slashings
+    /// received separately need to be passed to this.
+    pub fn on_attester_slashing(&mut self, attester_slashing: AttesterSlashing<C>) -> Result<()> {
+        ensure!(
+            predicates::is_slashable_attestation_data(
+                &attester_slashing.attestation_1.data,
+                &attester_slashing.attestation_2.data,
+            ),
+            Error::AttestationsNotSlashable { attester_slashing },
+        );
+
+        let state = &self.block_states[&self.justified_checkpoint.root];
+
+        predicates::validate_indexed_attestation(state, &attester_slashing.attestation_1)
+            .map_err(DebugAsError::new)?;
+        predicates::validate_indexed_attestation(state, &attester_slashing.attestation_2)
+            .map_err(DebugAsError::new)?;
+
+        self.add_equivocating_indices(&attester_slashing);
+        Ok(())
+    }
+
+    pub fn block(&self, root: H256) -> Option<&BeaconBlock<C>> {
+        self.blocks.get(&root)
+    }
+
+    /// Returns the state produced by the block with the given root.
+    pub fn block_state(&self, root: H256) -> Option<&BeaconState<C>> {
+        self.block_states.get(&root)
+    }
+
+    pub fn justified_checkpoint(&self) -> Checkpoint {
+        self.justified_checkpoint
+    }
+
+    pub fn finalized_checkpoint(&self) -> Checkpoint {
+        self.finalized_checkpoint
+    }
+
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#get_latest_attesting_balance>
+    ///
+    /// The extra `block` parameter is used to avoid a redundant block lookup.
+    fn latest_attesting_balance(&self, root: H256, block: &BeaconBlock<C>) -> Gwei {
+        let justified_state = &self.checkpoint_states[&self.justified_checkpoint];
+        let active_indices = beacon_state_accessors::get_active_validator_indices(
+            justified_state,
+            beacon_state_accessors::get_current_epoch(justified_state),
+        );
+
+        let attestation_score = active_indices
+            .into_iter()
+            .filter(|index| !self.equivocating_indices.contains(index))
+            .filter_map(|index| {
+                let latest_message = self.latest_messages.get(&index)?;
+                // The `Result::expect` call would be avoidable if there were a function like
+                // `beacon_state_accessors::get_active_validator_indices` that returned
+                // references to the validators in addition to their indices.
+                let index: usize = index
+                    .try_into()
+                    .expect("validator index should fit in usize");
+                let validator = &justified_state.validators[index];
+                if validator.slashed {
+                    return None;
+                }
+                let latest_message_block = &self.blocks[&latest_message.root];
+                if self.ancestor(latest_message.root, latest_message_block, block.slot) == root {
+                    Some(validator.effective_balance)
+                } else {
+                    None
+                }
+            })
+            .sum::<Gwei>();
+
+        if self.proposer_boost_root == H256::zero() {
+            return attestation_score;
         }
 
-        let parent_state = if let Some(state) = self.block_states.get(&block.parent_root) {
-            state
-        } else {
-            self.delay_until_block(block.parent_root, DelayedObject::BeaconBlock(block));
-            return Ok(());
+        let proposer_boost_block = &self.blocks[&self.proposer_boost_root];
+        if self.ancestor(self.proposer_boost_root, proposer_boost_block, block.slot) != root {
+            return attestation_score;
+        }
+
+        let total_active_balance =
+            beacon_state_accessors::get_total_active_balance(justified_state)
+                .expect("get_total_active_balance never returns Err");
+        let committee_weight = total_active_balance / C::SlotsPerEpoch::U64;
+        let proposer_score = committee_weight * PROPOSER_SCORE_BOOST / 100;
+
+        attestation_score + proposer_score
+    }
+
+    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#get_ancestor>
+    ///
+    /// The extra `block` parameter is used to avoid adding `block` to `self.blocks` before
+    /// verifying it. See <https://github.com/ethereum/eth2.0-specs/issues/1288>.
+    /// The parent of `block` must still be present in `self.blocks`, however.
+    fn ancestor(&self, root: H256, block: &BeaconBlock<C>, slot: Slot) -> H256 {
+        match block.slot.cmp(&slot) {
+            Ordering::Less => H256::zero(),
+            Ordering::Equal => root,
+            Ordering::Greater => {
+                let parent_root = block.parent_root;
+                let parent_block = &self.blocks[&block.parent_root];
+                self.ancestor(parent_root, parent_block, slot)
+            }
+        }
+    }
+
+    /// The specification uses 2 different ways to calculate what appears to be the same value:
+    /// - <https://github.com/ethereum/eth2.0-specs/blame/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#L155>
+    /// - <https://github.com/ethereum/eth2.0-specs/blame/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#L159>
+    ///
+    /// We assume this is an oversight. The two are equal when starting from genesis, but a
+    /// checkpoint `Store` may be anchored at a block that is not in the first slot of its epoch.
+    /// [`Store::ancestor`] only finds blocks whose slot matches exactly, so we use the slot of the
+    /// finalized block itself.
+    fn finalized_slot(&self) -> Slot {
+        self.blocks[&self.finalized_checkpoint.root].slot
+    }
+
+    /// Returns the time at which `slot` starts in milliseconds since the Unix epoch.
+    pub fn slot_start_millis(&self, slot: Slot) -> u64 {
+        Self::compute_slot_start_millis(self.genesis_time, slot)
+    }
+
+    // Slot `C::genesis_slot() + 1` starts at genesis to match the slot timer in `beacon_node`.
+    // The genesis slot itself starts before genesis, which cannot be represented if genesis is at
+    // the Unix epoch. The subtraction saturates in that case.
+    fn compute_slot_start_millis(genesis_time: UnixSeconds, slot: Slot) -> u64 {
+        let slot_millis = C::SecondsPerSlot::U64 * 1000;
+        let slots_since_genesis_slot = slot - C::genesis_slot();
+        (genesis_time * 1000 + slots_since_genesis_slot * slot_millis).saturating_sub(slot_millis)
+    }
+
+    fn slot_at_millis(&self, unix_millis: u64) -> Slot {
+        let genesis_millis = self.genesis_time * 1000;
+        if unix_millis < genesis_millis {
+            return C::genesis_slot();
+        }
+        let slot_millis = C::SecondsPerSlot::U64 * 1000;
+        C::genesis_slot() + 1 + (unix_millis - genesis_millis) / slot_millis
+    }
+
+    // The latest slot that objects may be from without being delayed.
+    fn slot_with_disparity(&self) -> Slot {
+        let disparity_millis = self.maximum_clock_disparity.as_millis() as u64;
+        let slot = self.slot_at_millis(self.time_millis + disparity_millis);
+        // `Store::on_slot` may have moved the slot past the one derived from the time.
+        slot.max(self.slot)
+    }
+
+    fn epoch_start_slot(epoch: Epoch) -> Slot {
+        misc::compute_start_slot_at_epoch::<C>(epoch)
+    }
+
+    fn process(&mut self, delayed: Delayed<C>) -> Result<()> {
+        let readiness = match &delayed.object {
+            DelayedObject::BeaconBlock(block, _) => self.block_readiness(block, delayed.root)?,
+            DelayedObject::Attestation(attestation) => self.attestation_readiness(attestation)?,
         };
 
-        if self.slot < block.slot {
-            self.delay_until_slot(block.slot, DelayedObject::BeaconBlock(block));
-            return Ok(());
+        match readiness {
+            Readiness::Ready => match delayed.object {
+                DelayedObject::BeaconBlock(block, verify_signatures) => {
+                    self.apply_block(block, delayed.root, verify_signatures)
+                }
+                DelayedObject::Attestation(attestation) => self.apply_attestation(&attestation),
+            },
+            Readiness::Ignore => Ok(()),
+            Readiness::Delay(delay) => self.delay(delayed, delay),
         }
+    }
 
-        let block_root = crypto::signed_root(&block);
+    fn block_readiness(&self, block: &BeaconBlock<C>, block_root: H256) -> Result<Readiness> {
+        // Ignore blocks from slots not later than the finalized block and blocks that are already
+        // known. Doing so ensures that:
+        // - The genesis block is accepted even though it does not represent a state transition.
+        // - Blocks that are received again are accepted without running the state transition.
+        if block.slot <= self.finalized_slot() || self.blocks.contains_key(&block_root) {
+            return Ok(Readiness::Ignore);
+        }
+
+        let current_slot = self.slot_with_disparity();
+
+        ensure!(
+            block.slot <= current_slot + self.delay_limits.max_future_slots,
+            Error::<C>::BlockTooFarInFuture {
+                block_slot: block.slot,
+                current_slot,
+            },
+        );
+
+        if !self.block_states.contains_key(&block.parent_root) {
+            return Ok(Readiness::Delay(Delay::UntilBlock(block.parent_root)));
+        }
+
+        if current_slot < block.slot {
+            return Ok(Readiness::Delay(Delay::UntilSlot(block.slot)));
+        }
+
+        Ok(Readiness::Ready)
+    }
+
+    fn apply_block(
+        &mut self,
+        block: BeaconBlock<C>,
+        block_root: H256,
+        verify_signatures: VerifySignatures,
+    ) -> Result<()> {
+        let finalized_slot = self.finalized_slot();
 
         ensure!(
             self.ancestor(block_root, &block, finalized_slot) == self.finalized_checkpoint.root,
@@ -175,56 +606,151 @@ impl<C: Config> Store<C> {
             },
         );
 
-        let mut state = parent_state.clone();
-        process_slot::state_transition(&mut state, &block, true);
-        let state = self.block_states.entry(block_root).or_insert(state);
+        let mut state = self.block_states[&block.parent_root].clone();
+        match &mut self.state_transition_observer {
+            Some(observer) => process_slot::state_transition_with_observer(
+                &mut state,
+                &block,
+                verify_signatures,
+                true,
+                observer.as_mut(),
+            ),
+            None => process_slot::state_transition(&mut state, &block, verify_signatures, true),
+        };
+        let justified_checkpoint = state.current_justified_checkpoint;
+        let finalized_checkpoint = state.finalized_checkpoint;
+        self.block_states.insert(block_root, state);
+
+        // The state transition has already validated the slashings.
+        for attester_slashing in block.body.attester_slashings.iter() {
+            self.add_equivocating_indices(attester_slashing);
+        }
+
+        // Add proposer score boost if the block is timely.
+        if self.is_before_attesting_interval(block.slot) {
+            self.proposer_boost_root = block_root;
+        }
 
         // Add `block` to `self.blocks` only when it's passed all checks.
         // See <https://github.com/ethereum/eth2.0-specs/issues/1288>.
         self.blocks.insert(block_root, block);
 
-        if self.justified_checkpoint.epoch < state.current_justified_checkpoint.epoch {
-            self.justified_checkpoint = state.current_justified_checkpoint;
+        if self.justified_checkpoint.epoch < justified_checkpoint.epoch {
+            if self.best_justified_checkpoint.epoch < justified_checkpoint.epoch {
+                self.best_justified_checkpoint = justified_checkpoint;
+            }
+            if self.should_update_justified_checkpoint(justified_checkpoint) {
+                self.update_justified_checkpoint(justified_checkpoint);
+            }
         }
 
-        if self.finalized_checkpoint.epoch < state.finalized_checkpoint.epoch {
-            self.finalized_checkpoint = state.finalized_checkpoint;
+        if self.finalized_checkpoint.epoch < finalized_checkpoint.epoch {
+            self.finalized_checkpoint = finalized_checkpoint;
+            self.subscribers
+                .send(Event::Finalized(finalized_checkpoint));
+
+            // Update justified if new justified is later than store justified or if store
+            // justified is not in chain with finalized checkpoint.
+            if self.justified_checkpoint != justified_checkpoint {
+                let finalized_slot = self.finalized_slot();
+                let store_justified_root = self.justified_checkpoint.root;
+                let store_justified_block = &self.blocks[&store_justified_root];
+                if self.justified_checkpoint.epoch < justified_checkpoint.epoch
+                    || self.ancestor(store_justified_root, store_justified_block, finalized_slot)
+                        != self.finalized_checkpoint.root
+                {
+                    self.update_justified_checkpoint(justified_checkpoint);
+                }
+            }
         }
 
-        self.retry_delayed_until_block(block_root)
+        self.head_may_have_changed = true;
+        self.head_report_due = true;
+
+        let objects = self.delayed_until_block.take(&block_root);
+        self.make_ready(objects);
+
+        Ok(())
     }
 
-    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#on_attestation>
-    pub fn on_attestation(&mut self, attestation: Attestation<C>) -> Result<()> {
+    // The time of the store is the time `on_tick` was last called with, which is the time the
+    // block arrived at if the caller follows the advice in the documentation of `on_block`.
+    fn is_before_attesting_interval(&self, block_slot: Slot) -> bool {
+        let time_into_slot_millis = self
+            .time_millis
+            .saturating_sub(self.slot_start_millis(block_slot));
+        let attesting_interval_millis = C::SecondsPerSlot::U64 * 1000 / INTERVALS_PER_SLOT;
+        self.slot == block_slot && time_into_slot_millis < attesting_interval_millis
+    }
+
+    /// Includes the checks on `beacon_block_root` from `validate_on_attestation` in
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#validate_on_attestation>.
+    /// Without them, latest messages could refer to blocks that are not in `self.blocks`.
+    fn attestation_readiness(&self, attestation: &Attestation<C>) -> Result<Readiness> {
         let target = attestation.data.target;
+        let beacon_block_root = attestation.data.beacon_block_root;
 
-        let base_state = if let Some(state) = self.block_states.get(&target.root) {
-            state
-        } else {
-            self.delay_until_block(target.root, DelayedObject::Attestation(attestation));
-            return Ok(());
-        };
+        if !self.block_states.contains_key(&target.root) {
+            return Ok(Readiness::Delay(Delay::UntilBlock(target.root)));
+        }
+
+        if !self.block_states.contains_key(&beacon_block_root) {
+            return Ok(Readiness::Delay(Delay::UntilBlock(beacon_block_root)));
+        }
+
+        ensure!(
+            self.blocks[&beacon_block_root].slot <= attestation.data.slot,
+            Error::AttestationForFutureBlock {
+                attestation: attestation.clone(),
+            },
+        );
 
         let target_epoch_start = Self::epoch_start_slot(target.epoch);
 
-        if self.slot < target_epoch_start {
-            self.delay_until_slot(target_epoch_start, DelayedObject::Attestation(attestation));
-            return Ok(());
+        ensure!(
+            self.latest_ancestor(beacon_block_root, target_epoch_start) == target.root,
+            Error::AttestationTargetInconsistent {
+                attestation: attestation.clone(),
+            },
+        );
+
+        let current_slot = self.slot_with_disparity();
+
+        if current_slot < target_epoch_start {
+            return Ok(Readiness::Delay(Delay::UntilSlot(target_epoch_start)));
         }
 
-        let target_state = self.checkpoint_states.entry(target).or_insert_with(|| {
-            let mut target_state = base_state.clone();
-            process_slot::process_slots(&mut target_state, target_epoch_start);
-            target_state
-        });
+        // Attestations can only affect the fork choice of subsequent slots.
+        if current_slot <= attestation.data.slot {
+            return Ok(Readiness::Delay(Delay::UntilSlot(
+                attestation.data.slot + 1,
+            )));
+        }
 
-        if self.slot <= attestation.data.slot {
-            self.delay_until_slot(
-                attestation.data.slot,
-                DelayedObject::Attestation(attestation),
-            );
-            return Ok(());
+        Ok(Readiness::Ready)
+    }
+
+    /// `get_ancestor` from
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#get_ancestor>.
+    ///
+    /// Unlike [`Store::ancestor`], this returns the latest block at or before `slot`, which is how
+    /// checkpoint roots are chosen when the first slot of an epoch is empty. Returns zero if the
+    /// block is older than any block in `self.blocks`.
+    fn latest_ancestor(&self, mut root: H256, slot: Slot) -> H256 {
+        while let Some(block) = self.blocks.get(&root) {
+            if block.slot <= slot {
+                return root;
+            }
+            root = block.parent_root;
         }
+        H256::zero()
+    }
+
+    fn apply_attestation(&mut self, attestation: &Attestation<C>) -> Result<()> {
+        let target = attestation.data.target;
+
+        self.store_checkpoint_state(target);
+        let target_state = &self.checkpoint_states[&target];
 
         let new_message = LatestMessage {
             epoch: target.epoch,
@@ -232,124 +758,653 @@ impl<C: Config> Store<C> {
         };
 
         let indexed_attestation =
-            beacon_state_accessors::get_indexed_attestation(target_state, &attestation)
+            beacon_state_accessors::get_indexed_attestation(target_state, attestation)
                 .map_err(DebugAsError::new)?;
 
         predicates::validate_indexed_attestation(target_state, &indexed_attestation)
             .map_err(DebugAsError::new)?;
 
         for index in indexed_attestation.attesting_indices.iter().copied() {
+            if self.equivocating_indices.contains(&index) {
+                continue;
+            }
             let old_message = self.latest_messages.entry(index).or_default();
             if old_message.epoch < new_message.epoch {
                 *old_message = new_message;
+                self.head_may_have_changed = true;
             }
         }
 
         Ok(())
     }
 
-    pub fn block(&self, root: H256) -> Option<&BeaconBlock<C>> {
-        self.blocks.get(&root)
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#store_target_checkpoint_state>
+    fn store_checkpoint_state(&mut self, checkpoint: Checkpoint) {
+        let base_state = &self.block_states[&checkpoint.root];
+        self.checkpoint_states.entry(checkpoint).or_insert_with(|| {
+            let mut checkpoint_state = base_state.clone();
+            let epoch_start = Self::epoch_start_slot(checkpoint.epoch);
+            if checkpoint_state.slot < epoch_start {
+                process_slot::process_slots(&mut checkpoint_state, epoch_start);
+            }
+            checkpoint_state
+        });
     }
 
-    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#get_latest_attesting_balance>
-    ///
-    /// The extra `block` parameter is used to avoid a redundant block lookup.
-    fn latest_attesting_balance(&self, root: H256, block: &BeaconBlock<C>) -> Gwei {
-        let justified_state = &self.checkpoint_states[&self.justified_checkpoint];
-        let active_indices = beacon_state_accessors::get_active_validator_indices(
-            justified_state,
-            beacon_state_accessors::get_current_epoch(justified_state),
+    /// <https://github.com/ethereum/consensus-specs/blob/v1.1.10/specs/phase0/fork-choice.md#should_update_justified_checkpoint>
+    fn should_update_justified_checkpoint(&self, new_justified_checkpoint: Checkpoint) -> bool {
+        if self.slot % C::SlotsPerEpoch::U64 < C::safe_slots_to_update_justified() {
+            return true;
+        }
+
+        // See `Store::finalized_slot` for why this uses the slot of the block rather than the
+        // start of the epoch.
+        let justified_slot = self.blocks[&self.justified_checkpoint.root].slot;
+        let new_justified_block = &self.blocks[&new_justified_checkpoint.root];
+
+        self.ancestor(
+            new_justified_checkpoint.root,
+            new_justified_block,
+            justified_slot,
+        ) == self.justified_checkpoint.root
+    }
+
+    fn update_justified_checkpoint(&mut self, checkpoint: Checkpoint) {
+        // `Store::latest_attesting_balance` needs the state of the justified checkpoint. The
+        // specification only stores it when processing attestations, but blocks can justify
+        // checkpoints that no attestation received by `self` targets.
+        self.store_checkpoint_state(checkpoint);
+        self.justified_checkpoint = checkpoint;
+        self.head_may_have_changed = true;
+        self.head_report_due = true;
+        self.subscribers.send(Event::Justified(checkpoint));
+    }
+
+    fn add_equivocating_indices(&mut self, attester_slashing: &AttesterSlashing<C>) {
+        let indices_1 = attester_slashing
+            .attestation_1
+            .attesting_indices
+            .iter()
+            .collect::<HashSet<_>>();
+
+        for index in attester_slashing.attestation_2.attesting_indices.iter() {
+            if indices_1.contains(index) && self.equivocating_indices.insert(*index) {
+                info!("validator {} equivocated", index);
+                self.head_may_have_changed = true;
+            }
+        }
+    }
+
+    // The parts of `on_tick` in the specification that are not specific to how time is
+    // represented.
+    fn on_time_advanced(&mut self, previous_slot: Slot) {
+        if previous_slot < self.slot {
+            self.head_report_due = true;
+
+            // Reset `proposer_boost_root` if this is a new slot.
+            if self.proposer_boost_root != H256::zero() {
+                self.proposer_boost_root = H256::zero();
+                self.head_may_have_changed = true;
+            }
+
+            // Update `justified_checkpoint` if a better checkpoint is known.
+            if self.slot % C::SlotsPerEpoch::U64 == 0
+                && self.justified_checkpoint.epoch < self.best_justified_checkpoint.epoch
+            {
+                self.update_justified_checkpoint(self.best_justified_checkpoint);
+            }
+        }
+
+        self.expire_and_retry_delayed();
+    }
+
+    fn delay(&mut self, delayed: Delayed<C>, delay: Delay) -> Result<()> {
+        if self.delayed_roots.contains(&delayed.root) {
+            info!("object already delayed: {:?}", delayed.object);
+            return Ok(());
+        }
+
+        let origin = delayed.origin;
+
+        let queue_len = match delay {
+            Delay::UntilBlock(_) => self.delayed_until_block.len(),
+            Delay::UntilSlot(_) => self.delayed_until_slot.len(),
+        };
+
+        ensure!(
+            queue_len < self.delay_limits.max_objects_per_queue,
+            Error::<C>::DelayQueueFull { origin },
         );
 
-        active_indices
-            .into_iter()
-            .filter_map(|index| {
-                let latest_message = self.latest_messages.get(&index)?;
-                let latest_message_block = &self.blocks[&latest_message.root];
-                if self.ancestor(latest_message.root, latest_message_block, block.slot) == root {
-                    // The `Result::expect` call would be avoidable if there were a function like
-                    // `beacon_state_accessors::get_active_validator_indices` that returned
-                    // references to the validators in addition to their indices.
-                    let index: usize = index
-                        .try_into()
-                        .expect("validator index should fit in usize");
-                    Some(justified_state.validators[index].effective_balance)
-                } else {
-                    None
-                }
-            })
-            .sum()
+        let origin_count = self.delayed_objects_per_origin.entry(origin).or_default();
+
+        if let Origin::Peer(_) = origin {
+            ensure!(
+                *origin_count < self.delay_limits.max_objects_per_peer,
+                Error::<C>::TooManyDelayedObjectsFromPeer { origin },
+            );
+        }
+
+        *origin_count += 1;
+        self.delayed_roots.insert(delayed.root);
+
+        info!("object delayed ({:?}): {:?}", delay, delayed.object);
+
+        match delay {
+            Delay::UntilBlock(block_root) => self.delayed_until_block.push(block_root, delayed),
+            Delay::UntilSlot(slot) => self.delayed_until_slot.push(slot, delayed),
+        }
+
+        Ok(())
     }
 
-    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_fork-choice.md#get_ancestor>
-    ///
-    /// The extra `block` parameter is used to avoid adding `block` to `self.blocks` before
-    /// verifying it. See <https://github.com/ethereum/eth2.0-specs/issues/1288>.
-    /// The parent of `block` must still be present in `self.blocks`, however.
-    fn ancestor(&self, root: H256, block: &BeaconBlock<C>, slot: Slot) -> H256 {
-        match block.slot.cmp(&slot) {
-            Ordering::Less => H256::zero(),
-            Ordering::Equal => root,
-            Ordering::Greater => {
-                let parent_root = block.parent_root;
-                let parent_block = &self.blocks[&block.parent_root];
-                self.ancestor(parent_root, parent_block, slot)
+    fn expire_and_retry_delayed(&mut self) {
+        let oldest_allowed = self.slot.saturating_sub(self.delay_limits.max_age_slots);
+        let mut expired = self.delayed_until_block.take_delayed_before(oldest_allowed);
+        expired.append(&mut self.delayed_until_slot.take_delayed_before(oldest_allowed));
+
+        for delayed in expired {
+            info!("delayed object expired: {:?}", delayed.object);
+            self.forget(&delayed);
+        }
+
+        let current_slot = self.slot_with_disparity();
+        let objects = self.delayed_until_slot.take_until(current_slot);
+        self.make_ready(objects);
+        self.process_ready_objects();
+        self.report_head_change();
+    }
+
+    fn make_ready(&mut self, objects: Vec<Delayed<C>>) {
+        for delayed in objects {
+            self.forget(&delayed);
+            self.ready_objects.push_back(delayed);
+        }
+    }
+
+    fn forget(&mut self, delayed: &Delayed<C>) {
+        self.delayed_roots.remove(&delayed.root);
+        if let Some(count) = self.delayed_objects_per_origin.get_mut(&delayed.origin) {
+            *count -= 1;
+            if *count == 0 {
+                self.delayed_objects_per_origin.remove(&delayed.origin);
             }
         }
     }
 
-    fn epoch_start_slot(epoch: Epoch) -> Slot {
-        misc::compute_start_slot_at_epoch::<C>(epoch)
+    fn report_head_change(&mut self) {
+        if self.subscribers.is_empty() || !self.head_report_due || !self.head_may_have_changed {
+            return;
+        }
+
+        self.head_may_have_changed = false;
+        self.head_report_due = false;
+
+        let old_head = self.reported_head;
+        let new_head = self.head_root();
+
+        if new_head == old_head {
+            return;
+        }
+
+        let common_ancestor = self.common_ancestor(old_head, new_head);
+
+        if common_ancestor != old_head {
+            let depth = self.blocks[&old_head].slot - self.blocks[&common_ancestor].slot;
+            info!(
+                "chain reorganized (old head: {:?}, new head: {:?}, depth: {})",
+                old_head, new_head, depth,
+            );
+            self.subscribers.send(Event::ChainReorg {
+                old_head,
+                new_head,
+                depth,
+            });
+        }
+
+        self.reported_head = new_head;
+        self.subscribers.send(Event::NewHead {
+            root: new_head,
+            slot: self.blocks[&new_head].slot,
+        });
     }
 
-    fn delay_until_block(&mut self, block_root: H256, object: DelayedObject<C>) {
-        info!("object delayed until block {:?}: {:?}", block_root, object);
-        self.delayed_until_block
-            .entry(block_root)
-            .or_default()
-            .push(object)
+    // Both blocks must descend from the finalized block, which they do if they have been the head.
+    fn common_ancestor(&self, mut root_1: H256, mut root_2: H256) -> H256 {
+        while root_1 != root_2 {
+            let block_1 = &self.blocks[&root_1];
+            let block_2 = &self.blocks[&root_2];
+            if block_2.slot <= block_1.slot {
+                root_1 = block_1.parent_root;
+            } else {
+                root_2 = block_2.parent_root;
+            }
+        }
+        root_1
+    }
+
+    // Objects are processed in a loop rather than recursively so that long chains of delayed
+    // objects cannot overflow the stack. Errors are logged because the objects are unrelated to
+    // whatever caused them to be retried.
+    fn process_ready_objects(&mut self) {
+        while let Some(delayed) = self.ready_objects.pop_front() {
+            info!("retrying delayed object: {:?}", delayed.object);
+            if let Err(error) = self.process(delayed) {
+                warn!("delayed object rejected: {}", error);
+            }
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Future as _, Stream as _};
+    use interop::{genesis, production};
+    use types::config::MinimalConfig;
 
-    fn delay_until_slot(&mut self, slot: Slot, object: DelayedObject<C>) {
-        info!("object delayed until slot {}: {:?}", slot, object);
-        self.delayed_until_slot
-            .entry(slot)
-            .or_default()
-            .push(object)
+    use super::*;
+
+    // Slot 1 starts at 100 seconds after the Unix epoch. Slots are 6 seconds long.
+    fn store() -> Store<MinimalConfig> {
+        Store::new(BeaconState {
+            genesis_time: 100,
+            ..BeaconState::default()
+        })
     }
 
-    fn retry_delayed_until_block(&mut self, block_root: H256) -> Result<()> {
-        if let Some(delayed_objects) = self.delayed_until_block.remove(&block_root) {
-            self.retry_delayed(delayed_objects)?;
+    fn orphan_block(parent_byte: u8) -> BeaconBlock<MinimalConfig> {
+        BeaconBlock {
+            slot: 1,
+            parent_root: H256::repeat_byte(parent_byte),
+            ..BeaconBlock::default()
         }
+    }
+
+    #[test]
+    fn slots_start_at_expected_times() {
+        let store = store();
+
+        assert_eq!(store.slot_start_millis(0), 94_000);
+        assert_eq!(store.slot_start_millis(1), 100_000);
+        assert_eq!(store.slot_start_millis(2), 106_000);
+        assert_eq!(store.slot_at_millis(99_999), 0);
+        assert_eq!(store.slot_at_millis(100_000), 1);
+        assert_eq!(store.slot_at_millis(105_999), 1);
+        assert_eq!(store.slot_at_millis(106_000), 2);
+    }
+
+    #[test]
+    fn on_tick_allows_for_clock_disparity() -> Result<()> {
+        let mut store = store();
+
+        store.on_tick(105_400)?;
+        assert_eq!(store.slot, 1);
+        assert_eq!(store.slot_with_disparity(), 1);
+
+        store.on_tick(105_500)?;
+        assert_eq!(store.slot, 1);
+        assert_eq!(store.slot_with_disparity(), 2);
+
+        store.set_maximum_clock_disparity(Duration::from_secs(0));
+        assert_eq!(store.slot_with_disparity(), 1);
+
         Ok(())
     }
 
-    fn retry_delayed_until_slot(&mut self, slot: Slot) -> Result<()> {
-        let later_slots = self.delayed_until_slot.split_off(&(slot + 1));
-        let fulfilled_slots = mem::replace(&mut self.delayed_until_slot, later_slots);
-        for (_, objects) in fulfilled_slots {
-            self.retry_delayed(objects)?;
+    #[test]
+    fn on_tick_ignores_earlier_times() -> Result<()> {
+        let mut store = store();
+
+        store.on_tick(112_000)?;
+        store.on_tick(101_000)?;
+
+        assert_eq!(store.time_millis, 112_000);
+        assert_eq!(store.slot, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn on_slot_rejects_slots_reached_by_on_tick() -> Result<()> {
+        let mut store = store();
+
+        store.on_tick(106_000)?;
+
+        assert!(store.on_slot(2).is_err());
+        store.on_slot(3)?;
+        assert_eq!(store.time_millis, 112_000);
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_delayed_objects_are_ignored() -> Result<()> {
+        let mut store = store();
+
+        store.on_block(orphan_block(1), VerifySignatures::None, Origin::Peer(0))?;
+        store.on_block(orphan_block(1), VerifySignatures::None, Origin::Peer(1))?;
+
+        assert_eq!(store.delayed_until_block.len(), 1);
+        assert_eq!(store.delayed_objects_per_origin.get(&Origin::Peer(1)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn delayed_objects_are_limited_per_peer() -> Result<()> {
+        let mut store = store();
+        store.set_delay_limits(DelayLimits {
+            max_objects_per_peer: 2,
+            ..DelayLimits::default()
+        });
+
+        store.on_block(orphan_block(1), VerifySignatures::None, Origin::Peer(0))?;
+        store.on_block(orphan_block(2), VerifySignatures::None, Origin::Peer(0))?;
+        let result = store.on_block(orphan_block(3), VerifySignatures::None, Origin::Peer(0));
+        assert!(result.is_err());
+
+        store.on_block(orphan_block(3), VerifySignatures::None, Origin::Peer(1))?;
+        store.on_block(orphan_block(4), VerifySignatures::None, Origin::Own)?;
+        store.on_block(orphan_block(5), VerifySignatures::None, Origin::Own)?;
+        store.on_block(orphan_block(6), VerifySignatures::None, Origin::Own)?;
+
+        assert_eq!(store.delayed_until_block.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn delayed_objects_are_limited_per_queue() -> Result<()> {
+        let mut store = store();
+        store.set_delay_limits(DelayLimits {
+            max_objects_per_queue: 1,
+            ..DelayLimits::default()
+        });
+
+        store.on_block(orphan_block(1), VerifySignatures::None, Origin::Own)?;
+        let result = store.on_block(orphan_block(2), VerifySignatures::None, Origin::Own);
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_too_far_in_the_future_are_rejected() {
+        let mut store = store();
+        let block = BeaconBlock {
+            slot: DelayLimits::default().max_future_slots + 1,
+            ..orphan_block(1)
+        };
+
+        assert!(store
+            .on_block(block, VerifySignatures::None, Origin::Peer(0))
+            .is_err());
+        assert_eq!(store.delayed_until_block.len(), 0);
+    }
+
+    #[test]
+    fn old_delayed_objects_expire() -> Result<()> {
+        let mut store = store();
+
+        store.on_block(orphan_block(1), VerifySignatures::None, Origin::Peer(0))?;
+        store.on_slot(DelayLimits::default().max_age_slots)?;
+        assert_eq!(store.delayed_until_block.len(), 1);
+
+        store.on_slot(DelayLimits::default().max_age_slots + 1)?;
+        assert_eq!(store.delayed_until_block.len(), 0);
+        assert!(store.delayed_roots.is_empty());
+        assert!(store.delayed_objects_per_origin.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn switching_to_another_branch_is_reported_as_a_reorg() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+        let events = store.subscribe();
+
+        // With no attestations, ties between branches are broken by root. Applying the block with
+        // the lower root first ensures the head switches branches.
+        let mut blocks = vec![
+            production::produce_block(&genesis_state, 1, &[])?,
+            production::produce_block(&genesis_state, 2, &[])?,
+        ];
+        blocks.sort_by_key(crypto::signed_root);
+        let old_head = crypto::signed_root(&blocks[0]);
+        let new_head = crypto::signed_root(&blocks[1]);
+        let old_head_slot = blocks[0].slot;
+        let new_head_slot = blocks[1].slot;
+
+        // Neither block is from the current slot, so neither gets the proposer boost.
+        store.on_slot(3)?;
+        for block in blocks {
+            store.on_block(block, VerifySignatures::None, Origin::Own)?;
         }
+        drop(store);
+
+        assert_eq!(
+            events.collect().wait(),
+            Ok(vec![
+                Event::NewHead {
+                    root: old_head,
+                    slot: old_head_slot,
+                },
+                Event::ChainReorg {
+                    old_head,
+                    new_head,
+                    depth: old_head_slot,
+                },
+                Event::NewHead {
+                    root: new_head,
+                    slot: new_head_slot,
+                },
+            ]),
+        );
         Ok(())
     }
 
-    // Delayed objects are retried recursively, thus a long chain of them could overflow the stack.
-    // It may be that in practice only one object will be delayed for a particular reason most of
-    // the time. In that case this function would effectively be tail-recursive. The same applies to
-    // slots in `Store::retry_delayed_until_slot`. The `tramp` crate may be of use in that scenario.
-    // Or `become`, if that ever gets implemented.
-    fn retry_delayed(&mut self, objects: Vec<DelayedObject<C>>) -> Result<()> {
-        for object in objects {
-            info!("retrying delayed object: {:?}", object);
-            match object {
-                DelayedObject::BeaconBlock(block) => self.on_block(block)?,
-                DelayedObject::Attestation(attestation) => self.on_attestation(attestation)?,
-            }
+    #[test]
+    fn head_changes_caused_by_attestations_are_reported_at_the_next_slot() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+        let events = store.subscribe();
+
+        let mut blocks = vec![
+            production::produce_block(&genesis_state, 1, &[])?,
+            production::produce_block(&genesis_state, 2, &[])?,
+        ];
+        blocks.sort_by_key(crypto::signed_root);
+        let voted_root = crypto::signed_root(&blocks[0]);
+        let voted_slot = blocks[0].slot;
+        let unvoted_root = crypto::signed_root(&blocks[1]);
+
+        store.on_slot(3)?;
+        for block in blocks {
+            store.on_block(block, VerifySignatures::None, Origin::Own)?;
         }
+        assert_eq!(store.reported_head, unvoted_root);
+
+        let voted_state = store
+            .block_state(voted_root)
+            .expect("block was applied")
+            .clone();
+        let validator_indices = (0..16).collect::<Vec<_>>();
+        for attestation in production::produce_attestations(
+            &voted_state,
+            voted_root,
+            voted_slot,
+            &validator_indices,
+        )? {
+            store.on_attestation(attestation, Origin::Own)?;
+        }
+        assert_eq!(store.head_root(), voted_root);
+        assert_eq!(store.reported_head, unvoted_root);
+
+        store.on_slot(4)?;
+        assert_eq!(store.reported_head, voted_root);
+        drop(store);
+
+        let events = events.collect().wait().expect("receivers do not fail");
+        assert_eq!(
+            events.last(),
+            Some(&Event::NewHead {
+                root: voted_root,
+                slot: voted_slot,
+            }),
+        );
         Ok(())
     }
-}
 
-// There used to be tests here but we were forced to omit them to save time.
+    #[test]
+    fn timely_blocks_are_boosted_until_the_next_slot() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+
+        let late_block = production::produce_block(&genesis_state, 1, &[])?;
+        let timely_block = production::produce_block(&genesis_state, 2, &[])?;
+        let timely_root = crypto::signed_root(&timely_block);
+
+        // Slots are 6 seconds long, so blocks received 2 seconds into their slot are too late.
+        store.on_slot(1)?;
+        store.on_tick(store.slot_start_millis(1) + 2000)?;
+        store.on_block(late_block, VerifySignatures::None, Origin::Own)?;
+        assert_eq!(store.proposer_boost_root, H256::zero());
+
+        store.on_slot(2)?;
+        store.on_block(timely_block, VerifySignatures::None, Origin::Own)?;
+        assert_eq!(store.proposer_boost_root, timely_root);
+
+        // Neither block has any attestations. Without the boost, the head would be whichever block
+        // has the higher root.
+        assert_eq!(store.head_root(), timely_root);
+
+        store.on_slot(3)?;
+        assert_eq!(store.proposer_boost_root, H256::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_arriving_between_attesting_interval_and_midpoint_are_not_boosted() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+        let block = production::produce_block(&genesis_state, 1, &[])?;
+
+        // The attesting interval ends 2 seconds into the slot. The midpoint is at 3 seconds.
+        store.on_slot(1)?;
+        store.on_tick(store.slot_start_millis(1) + 2500)?;
+        store.on_block(block, VerifySignatures::None, Origin::Own)?;
+
+        assert_eq!(store.proposer_boost_root, H256::zero());
+        Ok(())
+    }
+
+    #[test]
+    fn attestations_are_delayed_until_the_block_they_vote_for() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+
+        let block = production::produce_block(&genesis_state, 1, &[])?;
+        let block_root = crypto::signed_root(&block);
+        let mut block_state = genesis_state.clone();
+        process_slot::state_transition(&mut block_state, &block, VerifySignatures::None, true);
+
+        // The target is the genesis block, which the store already has.
+        let validator_indices = (0..16).collect::<Vec<_>>();
+        let attestation =
+            production::produce_attestations(&block_state, block_root, 1, &validator_indices)?
+                .remove(0);
+        assert_eq!(attestation.data.target.root, store.head_root());
+
+        store.on_slot(2)?;
+        store.on_attestation(attestation, Origin::Own)?;
+        assert!(store.latest_messages.is_empty());
+        assert_eq!(store.delayed_until_block.len(), 1);
+
+        store.on_block(block, VerifySignatures::None, Origin::Own)?;
+        assert!(!store.latest_messages.is_empty());
+        assert_eq!(store.head_root(), block_root);
+        Ok(())
+    }
+
+    #[test]
+    fn attestations_with_inconsistent_targets_are_rejected() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+
+        let block_1 = production::produce_block(&genesis_state, 1, &[])?;
+        let block_2 = production::produce_block(&genesis_state, 2, &[])?;
+        let root_1 = crypto::signed_root(&block_1);
+        let root_2 = crypto::signed_root(&block_2);
+
+        store.on_slot(3)?;
+        store.on_block(block_1, VerifySignatures::None, Origin::Own)?;
+        store.on_block(block_2, VerifySignatures::None, Origin::Own)?;
+        let block_state_2 = store
+            .block_state(root_2)
+            .expect("block was applied")
+            .clone();
+
+        // Both blocks are children of the genesis block, so `root_1` cannot be the target of a
+        // vote for `root_2`.
+        let validator_indices = (0..16).collect::<Vec<_>>();
+        let mut attestation =
+            production::produce_attestations(&block_state_2, root_2, 2, &validator_indices)?
+                .remove(0);
+        attestation.data.target.root = root_1;
+
+        assert!(store.on_attestation(attestation, Origin::Own).is_err());
+        assert!(store.latest_messages.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn attestations_by_equivocating_validators_are_ignored() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let mut store = Store::new(genesis_state.clone());
+        let genesis_root = store.head_root();
+
+        let block = production::produce_block(&genesis_state, 1, &[])?;
+        let block_root = crypto::signed_root(&block);
+        store.on_slot(1)?;
+        store.on_block(block, VerifySignatures::None, Origin::Own)?;
+        let block_state = store
+            .block_state(block_root)
+            .expect("block was applied")
+            .clone();
+
+        // The attestations differ only in `beacon_block_root`.
+        let validator_indices = (0..16).collect::<Vec<_>>();
+        let attestation_1 =
+            production::produce_attestations(&genesis_state, genesis_root, 1, &validator_indices)?
+                .remove(0);
+        let attestation_2 =
+            production::produce_attestations(&block_state, block_root, 1, &validator_indices)?
+                .remove(0);
+
+        let indexed_attestation_1 =
+            beacon_state_accessors::get_indexed_attestation(&block_state, &attestation_1)
+                .map_err(DebugAsError::new)?;
+        let indexed_attestation_2 =
+            beacon_state_accessors::get_indexed_attestation(&block_state, &attestation_2)
+                .map_err(DebugAsError::new)?;
+
+        let not_slashable = AttesterSlashing {
+            attestation_1: indexed_attestation_1.clone(),
+            attestation_2: indexed_attestation_1.clone(),
+        };
+        assert!(store.on_attester_slashing(not_slashable).is_err());
+
+        let equivocating_indices = indexed_attestation_1
+            .attesting_indices
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+
+        store.on_attester_slashing(AttesterSlashing {
+            attestation_1: indexed_attestation_1,
+            attestation_2: indexed_attestation_2,
+        })?;
+        assert_eq!(store.equivocating_indices, equivocating_indices);
+
+        store.on_slot(2)?;
+        store.on_attestation(attestation_2, Origin::Own)?;
+        assert!(store.latest_messages.is_empty());
+        Ok(())
+    }
+}
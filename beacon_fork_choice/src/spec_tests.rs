@@ -0,0 +1,493 @@
+//! A runner for fork choice tests in the format used by the specification tests:
+//! <https://github.com/ethereum/consensus-specs/tree/v1.1.10/tests/formats/fork_choice>
+//!
+//! The runner is used both for test vectors read from files and for scenarios built in code with
+//! blocks and attestations signed by interop validators. Scenarios are written to files in the same
+//! format and read back before running, so reading test vectors is tested even without them.
+
+use std::{
+    fs::{self, File},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Result;
+use helper_functions::crypto;
+use interop::{genesis, production};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ssz::{Decode, Encode};
+use transition_functions::blocks::block_signatures::VerifySignatures;
+use typenum::Unsigned as _;
+use types::{
+    config::{Config, MinimalConfig},
+    primitives::{Slot, UnixSeconds, ValidatorIndex, H256},
+    types::{Attestation, AttesterSlashing, BeaconBlock, Checkpoint},
+    BeaconState,
+};
+
+use crate::{Origin, Store};
+
+struct Case<C: Config> {
+    anchor_state: BeaconState<C>,
+    anchor_block: BeaconBlock<C>,
+    steps: Vec<Step<C>>,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Step<C: Config> {
+    /// The time in seconds since the Unix epoch, like `store.time` in the specification.
+    Tick(UnixSeconds),
+    Block(BeaconBlock<C>, Validity),
+    Attestation(Attestation<C>, Validity),
+    AttesterSlashing(AttesterSlashing<C>, Validity),
+    Checks(Checks),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Validity {
+    Valid,
+    Invalid,
+}
+
+impl From<bool> for Validity {
+    fn from(valid: bool) -> Self {
+        if valid {
+            Self::Valid
+        } else {
+            Self::Invalid
+        }
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct Checks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<UnixSeconds>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    head: Option<Head>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    justified_checkpoint: Option<Checkpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finalized_checkpoint: Option<Checkpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    best_justified_checkpoint: Option<Checkpoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proposer_boost_root: Option<H256>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+struct Head {
+    slot: Slot,
+    root: H256,
+}
+
+// Steps as they appear in `steps.yaml`. Objects are stored in separate files.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum StepFile {
+    Tick {
+        tick: UnixSeconds,
+    },
+    Block {
+        block: String,
+        #[serde(default = "valid_by_default")]
+        valid: bool,
+    },
+    Attestation {
+        attestation: String,
+        #[serde(default = "valid_by_default")]
+        valid: bool,
+    },
+    AttesterSlashing {
+        attester_slashing: String,
+        #[serde(default = "valid_by_default")]
+        valid: bool,
+    },
+    Checks {
+        checks: Checks,
+    },
+}
+
+fn valid_by_default() -> bool {
+    true
+}
+
+fn read_object<T: DeserializeOwned + Decode>(directory: &Path, name: &str) -> Result<T> {
+    file_formats::read(directory.join(name).with_extension("ssz_snappy"), None)
+}
+
+// Returns the name of the file without the extension, like the names in `steps.yaml`.
+fn write_object(
+    directory: &Path,
+    name: String,
+    object: &(impl Serialize + Encode),
+) -> Result<String> {
+    file_formats::write(
+        directory.join(&name).with_extension("ssz_snappy"),
+        None,
+        object,
+    )?;
+    Ok(name)
+}
+
+impl<C: Config + DeserializeOwned> Case<C> {
+    fn load(directory: &Path) -> Result<Self> {
+        let step_files: Vec<StepFile> =
+            serde_yaml::from_reader(File::open(directory.join("steps.yaml"))?)?;
+
+        let steps = step_files
+            .into_iter()
+            .map(|step_file| {
+                let step = match step_file {
+                    StepFile::Tick { tick } => Step::Tick(tick),
+                    StepFile::Block { block, valid } => {
+                        Step::Block(read_object(directory, &block)?, valid.into())
+                    }
+                    StepFile::Attestation { attestation, valid } => {
+                        Step::Attestation(read_object(directory, &attestation)?, valid.into())
+                    }
+                    StepFile::AttesterSlashing {
+                        attester_slashing,
+                        valid,
+                    } => Step::AttesterSlashing(
+                        read_object(directory, &attester_slashing)?,
+                        valid.into(),
+                    ),
+                    StepFile::Checks { checks } => Step::Checks(checks),
+                };
+                Ok(step)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            anchor_state: read_object(directory, "anchor_state")?,
+            anchor_block: read_object(directory, "anchor_block")?,
+            steps,
+        })
+    }
+
+    fn write(&self, directory: &Path) -> Result<()> {
+        write_object(directory, "anchor_state".to_owned(), &self.anchor_state)?;
+        write_object(directory, "anchor_block".to_owned(), &self.anchor_block)?;
+
+        let step_files = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let step_file = match step {
+                    Step::Tick(tick) => StepFile::Tick { tick: *tick },
+                    Step::Block(block, validity) => StepFile::Block {
+                        block: write_object(directory, format!("block_{}", index), block)?,
+                        valid: *validity == Validity::Valid,
+                    },
+                    Step::Attestation(attestation, validity) => StepFile::Attestation {
+                        attestation: write_object(
+                            directory,
+                            format!("attestation_{}", index),
+                            attestation,
+                        )?,
+                        valid: *validity == Validity::Valid,
+                    },
+                    Step::AttesterSlashing(attester_slashing, validity) => {
+                        StepFile::AttesterSlashing {
+                            attester_slashing: write_object(
+                                directory,
+                                format!("attester_slashing_{}", index),
+                                attester_slashing,
+                            )?,
+                            valid: *validity == Validity::Valid,
+                        }
+                    }
+                    Step::Checks(checks) => StepFile::Checks {
+                        checks: checks.clone(),
+                    },
+                };
+                Ok(step_file)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        serde_yaml::to_writer(File::create(directory.join("steps.yaml"))?, &step_files)?;
+
+        Ok(())
+    }
+
+    fn run(self, name: &str) {
+        let mut store = Store::new(self.anchor_state);
+
+        assert_eq!(
+            store.head_root(),
+            crypto::signed_root(&self.anchor_block),
+            "{}: anchor block does not match the block constructed by Store::new",
+            name,
+        );
+
+        for (index, step) in self.steps.into_iter().enumerate() {
+            let context = format!("{}, step {}", name, index);
+
+            match step {
+                Step::Tick(time) => store
+                    .on_tick(Self::store_millis(time))
+                    .expect("on_tick should never fail when time advances"),
+                Step::Block(block, validity) => check_validity(&context, validity, || {
+                    store.on_block(block, VerifySignatures::Individually, Origin::Own)
+                }),
+                Step::Attestation(attestation, validity) => {
+                    check_validity(&context, validity, || {
+                        store.on_attestation(attestation, Origin::Own)
+                    })
+                }
+                Step::AttesterSlashing(attester_slashing, validity) => {
+                    check_validity(&context, validity, || {
+                        store.on_attester_slashing(attester_slashing)
+                    })
+                }
+                Step::Checks(checks) => Self::check(&store, &checks, &context),
+            }
+        }
+    }
+
+    fn check(store: &Store<C>, checks: &Checks, context: &str) {
+        if let Some(time) = checks.time {
+            assert_eq!(store.time_millis, Self::store_millis(time), "{}", context);
+        }
+        if let Some(head) = &checks.head {
+            let root = store.head_root();
+            let slot = store.blocks[&root].slot;
+            assert_eq!(&Head { slot, root }, head, "{}", context);
+        }
+        if let Some(checkpoint) = checks.justified_checkpoint {
+            assert_eq!(store.justified_checkpoint, checkpoint, "{}", context);
+        }
+        if let Some(checkpoint) = checks.finalized_checkpoint {
+            assert_eq!(store.finalized_checkpoint, checkpoint, "{}", context);
+        }
+        if let Some(checkpoint) = checks.best_justified_checkpoint {
+            assert_eq!(store.best_justified_checkpoint, checkpoint, "{}", context);
+        }
+        if let Some(root) = checks.proposer_boost_root {
+            assert_eq!(store.proposer_boost_root, root, "{}", context);
+        }
+    }
+
+    // In the specification slot `GENESIS_SLOT` starts at `genesis_time`. `Store` follows
+    // `slot_timer` instead, where `GENESIS_SLOT + 1` does. Shifting times back by a slot makes
+    // both agree on which slot a time is in.
+    fn store_millis(time: UnixSeconds) -> u64 {
+        time.saturating_sub(C::SecondsPerSlot::U64) * 1000
+    }
+}
+
+// `Store::on_block` panics on some invalid blocks, because the state transition does.
+fn check_validity(context: &str, validity: Validity, process: impl FnOnce() -> Result<()>) {
+    let result = panic::catch_unwind(AssertUnwindSafe(process));
+    let actual = match result {
+        Ok(Ok(())) => Validity::Valid,
+        Ok(Err(_)) | Err(_) => Validity::Invalid,
+    };
+    assert_eq!(actual, validity, "{}", context);
+}
+
+// The test vectors are not part of the repository. To run this, extract a release of
+// <https://github.com/ethereum/eth2.0-spec-tests> that contains fork choice tests into
+// `eth2.0-spec-tests` in the root of the repository and pass `--ignored` to the test binary.
+//
+// Fork choice tests were first published after `BeaconBlock` was split into `SignedBeaconBlock`,
+// so the official releases cannot be decoded by `types` as they are. Test vectors regenerated
+// with the version of the specification this repository implements work as expected.
+#[test]
+#[ignore]
+fn fork_choice_spec_tests() -> Result<()> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../eth2.0-spec-tests/tests/minimal/phase0/fork_choice");
+
+    for handler in directory.read_dir()? {
+        for case in handler?.path().join("pyspec_tests").read_dir()? {
+            let path = case?.path();
+            Case::<MinimalConfig>::load(&path)?.run(&path.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+// The scenarios below use the same conventions as the specification tests.
+
+const VALIDATOR_COUNT: u64 = 64;
+const GENESIS_TIME: UnixSeconds = 0;
+
+struct Scenario {
+    anchor_state: BeaconState<MinimalConfig>,
+    steps: Vec<Step<MinimalConfig>>,
+}
+
+impl Scenario {
+    fn new() -> Self {
+        Self {
+            anchor_state: genesis::genesis_state(VALIDATOR_COUNT, GENESIS_TIME),
+            steps: vec![],
+        }
+    }
+
+    fn anchor_block(&self) -> BeaconBlock<MinimalConfig> {
+        BeaconBlock {
+            state_root: self.anchor_state.canonical_root(),
+            ..BeaconBlock::default()
+        }
+    }
+
+    fn anchor_root(&self) -> H256 {
+        crypto::signed_root(&self.anchor_block())
+    }
+
+    fn anchor_checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            epoch: MinimalConfig::genesis_epoch(),
+            root: self.anchor_root(),
+        }
+    }
+
+    fn tick_to_slot(&mut self, slot: Slot, seconds_into_slot: u64) {
+        let seconds_per_slot = <MinimalConfig as Config>::SecondsPerSlot::U64;
+        let time = GENESIS_TIME + slot * seconds_per_slot + seconds_into_slot;
+        self.steps.push(Step::Tick(time));
+    }
+
+    // Returns the block along with its post-state so that children and attestations can be
+    // produced on top of it.
+    fn block(
+        &mut self,
+        parent_state: &BeaconState<MinimalConfig>,
+        slot: Slot,
+    ) -> Result<(H256, BeaconState<MinimalConfig>)> {
+        let block = production::produce_block(parent_state, slot, &[])?;
+        let root = crypto::signed_root(&block);
+        let mut state = parent_state.clone();
+        transition_functions::process_slot::state_transition(
+            &mut state,
+            &block,
+            VerifySignatures::None,
+            true,
+        );
+        self.steps.push(Step::Block(block, Validity::Valid));
+        Ok((root, state))
+    }
+
+    fn attestations(
+        &mut self,
+        head_state: &BeaconState<MinimalConfig>,
+        head_root: H256,
+        slot: Slot,
+    ) -> Result<()> {
+        let validator_indices = (0..VALIDATOR_COUNT).collect::<Vec<ValidatorIndex>>();
+        let attestations =
+            production::produce_attestations(head_state, head_root, slot, &validator_indices)?;
+        for attestation in attestations {
+            self.steps
+                .push(Step::Attestation(attestation, Validity::Valid));
+        }
+        Ok(())
+    }
+
+    fn check_head(&mut self, root: H256, slot: Slot) {
+        self.steps.push(Step::Checks(Checks {
+            head: Some(Head { slot, root }),
+            ..Checks::default()
+        }));
+    }
+
+    // Runs the scenario as a test case read from files in the specification test format.
+    fn run(self, name: &str) -> Result<()> {
+        let anchor_block = self.anchor_block();
+        let case = Case {
+            anchor_state: self.anchor_state,
+            anchor_block,
+            steps: self.steps,
+        };
+
+        let directory = TemporaryDirectory::create(name)?;
+        case.write(&directory.0)?;
+        Case::<MinimalConfig>::load(&directory.0)?.run(name);
+
+        Ok(())
+    }
+}
+
+// Removes the directory when dropped, including when the test panics.
+struct TemporaryDirectory(PathBuf);
+
+impl TemporaryDirectory {
+    fn create(name: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_dir_all(&self.0) {
+            eprintln!("failed to remove {:?}: {}", self.0, error);
+        }
+    }
+}
+
+#[test]
+fn competing_forks_are_decided_by_attestations() -> Result<()> {
+    let mut scenario = Scenario::new();
+    let anchor_state = scenario.anchor_state.clone();
+
+    // Both blocks are received too late in slot 2 to be boosted.
+    scenario.tick_to_slot(2, 3);
+    let (root_1, state_1) = scenario.block(&anchor_state, 1)?;
+    let (root_2, state_2) = scenario.block(&anchor_state, 2)?;
+
+    // Without attestations, ties are broken in favor of the block with the higher root.
+    let (winner, loser, loser_state) = if root_1 < root_2 {
+        ((root_2, 2), (root_1, 1), state_1)
+    } else {
+        ((root_1, 1), (root_2, 2), state_2)
+    };
+    scenario.check_head(winner.0, winner.1);
+
+    scenario.attestations(&loser_state, loser.0, 2)?;
+    scenario.tick_to_slot(3, 0);
+    scenario.check_head(loser.0, loser.1);
+
+    scenario.run("competing_forks_are_decided_by_attestations")
+}
+
+#[test]
+fn heavier_fork_causes_reorg() -> Result<()> {
+    let mut scenario = Scenario::new();
+    let anchor_state = scenario.anchor_state.clone();
+    let anchor_checkpoint = scenario.anchor_checkpoint();
+
+    scenario.tick_to_slot(1, 0);
+    let (_, state_1) = scenario.block(&anchor_state, 1)?;
+    scenario.tick_to_slot(2, 0);
+    let (root_2, state_2) = scenario.block(&state_1, 2)?;
+    scenario.attestations(&state_2, root_2, 2)?;
+    scenario.tick_to_slot(3, 3);
+    scenario.check_head(root_2, 2);
+
+    // A late block on a fork starting at the anchor. The validators of slot 2 still outweigh it.
+    let (root_3, state_3) = scenario.block(&anchor_state, 3)?;
+    scenario.check_head(root_2, 2);
+
+    // Validators of 2 slots outweigh those of 1.
+    scenario.attestations(&state_3, root_3, 3)?;
+    scenario.tick_to_slot(4, 0);
+    scenario.attestations(&state_3, root_3, 4)?;
+    scenario.tick_to_slot(5, 0);
+    scenario.check_head(root_3, 3);
+
+    scenario.steps.push(Step::Checks(Checks {
+        justified_checkpoint: Some(anchor_checkpoint),
+        finalized_checkpoint: Some(anchor_checkpoint),
+        ..Checks::default()
+    }));
+
+    scenario.run("heavier_fork_causes_reorg")
+}
@@ -0,0 +1,246 @@
+//! Sources of time for [`slot_timer`].
+//!
+//! [`SystemClock`] is the real clock. The others are meant for local testnets, demos and tests:
+//! - [`AcceleratedClock`] makes time pass a fixed number of times faster than in reality.
+//! - [`ManualClock`] only moves when told to. It can be used to replay a recorded session
+//!   deterministically.
+//!
+//! [`slot_timer`]: crate::slot_timer
+
+use core::{num::NonZeroU32, time::Duration};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{Error, Result};
+use futures::{task::Task, Async, Poll, Stream};
+use tokio::timer::Interval;
+use types::primitives::UnixSeconds;
+
+use crate::fake_time::{FakeInstant, FakeSystemTime, InstantLike, SystemTimeLike, Timespec};
+
+pub trait Clock {
+    type Instant: InstantLike;
+    type SystemTime: SystemTimeLike;
+
+    /// Returns the current time as both an `Instant` and a `SystemTime`.
+    /// They should correspond to the same point in time.
+    fn now(&self) -> (Self::Instant, Self::SystemTime);
+
+    /// Returns a stream that produces an item at `start` and every `period` after that.
+    fn interval(
+        &self,
+        start: Self::Instant,
+        period: Duration,
+    ) -> Box<dyn Stream<Item = (), Error = Error> + Send>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    type Instant = Instant;
+    type SystemTime = SystemTime;
+
+    fn now(&self) -> (Instant, SystemTime) {
+        // We assume the `Instant` and `SystemTime` obtained here correspond to the same point in
+        // time. This is slightly inaccurate but the error will probably be negligible compared to
+        // clock differences between different nodes in the network.
+        (Instant::now(), SystemTime::now())
+    }
+
+    fn interval(
+        &self,
+        start: Instant,
+        period: Duration,
+    ) -> Box<dyn Stream<Item = (), Error = Error> + Send> {
+        Box::new(Interval::new(start, period).map(|_| ()).from_err())
+    }
+}
+
+/// A clock that runs `rate` times faster than the system clock.
+///
+/// Time is scaled relative to `origin`, so nodes started at different times with the same `origin`
+/// and `rate` agree on the current time. Nodes in a local testnet should use the genesis time.
+pub struct AcceleratedClock {
+    rate: NonZeroU32,
+    // An `Instant` and the simulated time that corresponds to it.
+    anchor_instant: Instant,
+    anchor_timespec: Timespec,
+}
+
+impl AcceleratedClock {
+    pub fn new(origin: UnixSeconds, rate: NonZeroU32) -> Result<Self> {
+        let anchor_instant = Instant::now();
+        let real_timespec = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+        let origin = Timespec::from_secs(origin);
+
+        let anchor_timespec = if origin <= real_timespec {
+            origin + (real_timespec - origin) * rate.get()
+        } else {
+            // `FakeSystemTime` cannot represent times before the Unix epoch.
+            origin
+                .checked_sub((origin - real_timespec) * rate.get())
+                .unwrap_or_default()
+        };
+
+        Ok(Self {
+            rate,
+            anchor_instant,
+            anchor_timespec,
+        })
+    }
+}
+
+impl Clock for AcceleratedClock {
+    type Instant = FakeInstant;
+    type SystemTime = FakeSystemTime;
+
+    fn now(&self) -> (FakeInstant, FakeSystemTime) {
+        let timespec = self.anchor_timespec + self.anchor_instant.elapsed() * self.rate.get();
+        (FakeInstant(timespec), FakeSystemTime(timespec))
+    }
+
+    fn interval(
+        &self,
+        start: FakeInstant,
+        period: Duration,
+    ) -> Box<dyn Stream<Item = (), Error = Error> + Send> {
+        let anchor_to_start = start
+            .0
+            .checked_sub(self.anchor_timespec)
+            .unwrap_or_default();
+        let real_start = self.anchor_instant + anchor_to_start / self.rate.get();
+        let real_period = period / self.rate.get();
+        SystemClock.interval(real_start, real_period)
+    }
+}
+
+/// A clock that only moves when [`ManualClock::advance`] is called.
+///
+/// Clones share the same time. Streams returned by [`Clock::interval`] produce every item the
+/// clock has advanced past, even if it advanced by several periods at once.
+#[derive(Clone)]
+pub struct ManualClock {
+    state: Arc<Mutex<ManualClockState>>,
+}
+
+struct ManualClockState {
+    now: Timespec,
+    // Tasks polling intervals that are waiting for the clock to advance.
+    waiting_tasks: Vec<Task>,
+}
+
+impl ManualClock {
+    pub fn new(now: Timespec) -> Self {
+        let state = ManualClockState {
+            now,
+            waiting_tasks: vec![],
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.lock();
+        state.now += duration;
+        for task in state.waiting_tasks.drain(..) {
+            task.notify();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<ManualClockState> {
+        self.state
+            .lock()
+            .expect("manual clock mutex should not be poisoned")
+    }
+}
+
+impl Clock for ManualClock {
+    type Instant = FakeInstant;
+    type SystemTime = FakeSystemTime;
+
+    fn now(&self) -> (FakeInstant, FakeSystemTime) {
+        let now = self.lock().now;
+        (FakeInstant(now), FakeSystemTime(now))
+    }
+
+    fn interval(
+        &self,
+        start: FakeInstant,
+        period: Duration,
+    ) -> Box<dyn Stream<Item = (), Error = Error> + Send> {
+        Box::new(ManualInterval {
+            clock: self.clone(),
+            next: start.0,
+            period,
+        })
+    }
+}
+
+struct ManualInterval {
+    clock: ManualClock,
+    next: Timespec,
+    period: Duration,
+}
+
+impl Stream for ManualInterval {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<()>, Error> {
+        let mut state = self.clock.lock();
+        if self.next <= state.now {
+            self.next += self.period;
+            Ok(Async::Ready(Some(())))
+        } else {
+            state.waiting_tasks.push(futures::task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future as _};
+
+    use super::*;
+
+    fn poll(stream: &mut impl Stream<Item = (), Error = Error>) -> Result<Async<Option<()>>> {
+        future::lazy(|| stream.poll()).wait()
+    }
+
+    #[test]
+    fn manual_interval_waits_for_clock_to_advance() -> Result<()> {
+        let clock = ManualClock::new(Timespec::from_secs(10));
+        let mut interval =
+            clock.interval(FakeInstant(Timespec::from_secs(12)), Duration::from_secs(3));
+
+        assert_eq!(poll(&mut interval)?, Async::NotReady);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(poll(&mut interval)?, Async::Ready(Some(())));
+        assert_eq!(poll(&mut interval)?, Async::NotReady);
+
+        // Items skipped over are still produced.
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(poll(&mut interval)?, Async::Ready(Some(())));
+        assert_eq!(poll(&mut interval)?, Async::Ready(Some(())));
+        assert_eq!(poll(&mut interval)?, Async::NotReady);
+
+        Ok(())
+    }
+
+    #[test]
+    fn accelerated_clock_runs_faster_than_system_clock() -> Result<()> {
+        let rate = NonZeroU32::new(1000).expect("1000 is not zero");
+        let clock = AcceleratedClock::new(0, rate)?;
+
+        let (_, before) = clock.now();
+        std::thread::sleep(Duration::from_millis(10));
+        let (_, after) = clock.now();
+
+        assert!(Duration::from_secs(10) <= after.duration_since(before)?);
+        Ok(())
+    }
+}
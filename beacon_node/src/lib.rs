@@ -0,0 +1,9 @@
+//! The beacon node is split into a library and a thin binary so that other crates (like the
+//! simulator) can run nodes in-process.
+
+pub mod clock;
+pub mod fake_time;
+pub mod node;
+pub mod reward_report;
+pub mod runtime_config;
+pub mod slot_timer;
@@ -1,23 +1,34 @@
-use std::{env, fs::File, process};
+use core::time::Duration;
+use std::{env, path::Path, process, thread};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
+use eth1::Eth1Follower;
 use eth2_network_libp2p::Qutex;
-use futures::{Future as _, Stream as _};
-use log::{error, Level};
+use futures::{Future as _, Stream};
+use log::{error, info, Level};
 use serde::de::DeserializeOwned;
+use thiserror::Error;
 use tokio::runtime::current_thread;
-use types::config::{Config, MainnetConfig, MinimalConfig};
+use transition_functions::observer::JsonLinesTracer;
+use types::{
+    beacon_state::BeaconState,
+    config::{Config, MainnetConfig, MinimalConfig},
+    primitives::H256,
+};
 
-use crate::{
+use beacon_node::{
+    clock::AcceleratedClock,
     node::Node,
-    runtime_config::{Preset, RuntimeConfig},
-    slot_timer::Tick,
+    reward_report::RewardReporter,
+    runtime_config::{CheckpointConfig, Preset, RuntimeConfig},
+    slot_timer::{self, Tick},
 };
 
-mod fake_time;
-mod node;
-mod runtime_config;
-mod slot_timer;
+#[derive(Debug, Error)]
+enum Error {
+    #[error("genesis state root mismatch (expected: {expected:?}, actual: {actual:?})")]
+    GenesisStateRootMismatch { expected: H256, actual: H256 },
+}
 
 fn main() {
     simple_logger::init_with_level(Level::Info).expect("logger was already initialized");
@@ -36,13 +47,77 @@ fn parse_args_and_run_node() -> Result<()> {
     }
 }
 
-fn run_node<C: Config + DeserializeOwned>(config: RuntimeConfig) -> Result<()> {
-    let genesis_state_file = File::open(config.genesis_state_path)?;
-    let genesis_state = serde_yaml::from_reader(genesis_state_file)?;
+fn run_node<C: Config + DeserializeOwned + Send>(config: RuntimeConfig) -> Result<()> {
+    let node = match config.checkpoint {
+        Some(CheckpointConfig {
+            state_path,
+            block_path,
+        }) => {
+            let state = read_state::<C>(state_path.as_path())?;
+            info!("loading checkpoint block from {:?}", block_path);
+            let block = file_formats::read(block_path, None)?;
+            Node::from_checkpoint(state, block, config.synced_block_signatures)?
+        }
+        None => {
+            let genesis_state = read_state::<C>(config.genesis_state_path.as_path())?;
+            if let Some(expected) = config.genesis_state_root {
+                let actual = genesis_state.canonical_root();
+                ensure!(
+                    actual == expected,
+                    Error::GenesisStateRootMismatch { expected, actual },
+                );
+            }
+            Node::new(genesis_state, config.synced_block_signatures)
+        }
+    };
+
+    let trusted_peers = config
+        .trusted_peers
+        .iter()
+        .map(|peer_id| eth2_network_libp2p::parse_peer_key(peer_id))
+        .collect::<Result<Vec<_>>>()?;
+
+    let node = node
+        .with_maximum_clock_disparity(Duration::from_millis(config.maximum_clock_disparity_millis))
+        .with_trusted_peers(trusted_peers);
+
+    // The node must use the same clock as the slot timer.
+    let genesis_time = node.head_state().genesis_time;
+    let (node, tick_stream): (_, Box<dyn Stream<Item = Tick, Error = anyhow::Error>>) =
+        match config.clock_rate {
+            Some(rate) => {
+                info!("running clock {} times faster than in reality", rate);
+                let clock = AcceleratedClock::new(genesis_time, rate)?;
+                let tick_stream = slot_timer::start_with_clock::<C, _>(&clock, genesis_time)?;
+                (node.with_clock(clock), Box::new(tick_stream))
+            }
+            None => (node, Box::new(slot_timer::start::<C>(genesis_time)?)),
+        };
+
+    let node = match config.reward_report {
+        Some(reward_report_config) => {
+            node.with_reward_reporter(RewardReporter::new(reward_report_config)?)
+        }
+        None => node,
+    };
 
-    let node = Node::new(genesis_state);
+    let mut node = match config.state_transition_trace {
+        Some(path) => {
+            info!("writing state transition trace to {:?}", path);
+            node.with_state_transition_observer(Box::new(JsonLinesTracer::create(path)?))
+        }
+        None => node,
+    };
 
-    let tick_stream = slot_timer::start::<C>(node.head_state().genesis_time)?;
+    // Subscribe before any objects are processed so that no head changes are missed.
+    let fork_choice_events = node.subscribe_to_fork_choice_events();
+
+    // The eth1 follower makes blocking HTTP requests, so it runs in its own thread.
+    // Nothing uses the eth1 data yet. It will be needed once the node starts proposing blocks.
+    if let Some(eth1_config) = config.eth1 {
+        let follower = Eth1Follower::<C>::new(eth1_config);
+        thread::spawn(move || follower.run());
+    }
 
     // In previous versions, `Node` would consume an `Iterator` of inputs and produce an `Iterator`
     // of outputs. This approach required no explicit synchronization, but made abstracting over
@@ -67,14 +142,37 @@ fn run_node<C: Config + DeserializeOwned>(config: RuntimeConfig) -> Result<()> {
         qutex.clone().lock().from_err().and_then(move |mut node| {
             match tick {
                 Tick::SlotStart(slot) => node.handle_slot_start(slot)?,
-                Tick::SlotMidpoint(slot) => node.handle_slot_midpoint(slot),
+                Tick::SlotMidpoint(slot) => node.handle_slot_midpoint(slot)?,
             }
             Ok(())
         })
     });
 
+    // The receiver never fails. The stream ends when the `Node` is dropped.
+    let log_fork_choice_events = fork_choice_events
+        .for_each(|event| {
+            info!("fork choice event: {:?}", event);
+            Ok(())
+        })
+        .map_err(|()| unreachable!("fork choice event receiver should not fail"));
+
     // Tokio timers fail when polled outside a task, so we need to start a Tokio runtime.
     // The single threaded runtime (`current_thread`) is enough as long as we do not use
     // `Future::wait`. `Future::wait` appears to park the thread indefinitely.
-    current_thread::block_on_all(run_network.join(handle_ticks).map(|_| ()))
+    current_thread::block_on_all(
+        run_network
+            .join3(handle_ticks, log_fork_choice_events)
+            .map(|_| ()),
+    )
+}
+
+fn read_state<C: Config + DeserializeOwned>(path: &Path) -> Result<BeaconState<C>> {
+    info!("loading state from {:?}", path);
+    let state: BeaconState<C> = file_formats::read(path, None)?;
+    info!(
+        "loaded state (slot: {}, hash_tree_root: {:?})",
+        state.slot,
+        state.canonical_root(),
+    );
+    Ok(state)
 }
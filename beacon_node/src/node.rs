@@ -2,11 +2,18 @@
 // responsibilities, such as accumulating unprocessed deposits, proposing beacon blocks, and
 // creating beacon attestations.
 
+use core::time::Duration;
+use std::collections::HashSet;
+
 use anyhow::Result;
-use beacon_fork_choice::Store;
-use eth2_network::{Networked, Status};
-use helper_functions::crypto;
-use log::info;
+use beacon_fork_choice::{Event, Origin, Store, MAXIMUM_GOSSIP_CLOCK_DISPARITY};
+use eth2_network::{Networked, PeerKey, Status, ATTESTATION_PROPAGATION_SLOT_RANGE};
+use futures::sync::mpsc::UnboundedReceiver;
+use log::{info, warn};
+use transition_functions::{
+    blocks::block_signatures::VerifySignatures, observer::StateTransitionObserver,
+};
+use typenum::Unsigned as _;
 use types::{
     beacon_state::BeaconState,
     config::Config,
@@ -14,52 +21,231 @@ use types::{
     types::{Attestation, BeaconBlock, Checkpoint},
 };
 
-pub struct Node<C: Config>(Store<C>);
+use crate::{
+    clock::{Clock, SystemClock},
+    fake_time::SystemTimeLike,
+    reward_report::RewardReporter,
+};
+
+// Returns the current time in milliseconds since the Unix epoch.
+type UnixMillisSource = Box<dyn Fn() -> Result<u64> + Send>;
+
+pub struct Node<C: Config> {
+    store: Store<C>,
+    // Objects are passed to `store` along with the time they arrive at, which is needed for
+    // proposer boost and for `maximum_clock_disparity` to have any effect.
+    unix_millis: UnixMillisSource,
+    maximum_clock_disparity: Duration,
+    // Gossiped blocks are verified in a batch unless configured otherwise.
+    gossiped_block_signatures: VerifySignatures,
+    // Only applies to blocks synced from `trusted_peers`. Blocks synced from other peers are
+    // verified in a batch.
+    synced_block_signatures: VerifySignatures,
+    trusted_peers: HashSet<PeerKey>,
+    reward_reporter: Option<RewardReporter>,
+}
 
 impl<C: Config> Node<C> {
-    pub fn new(beacon_state: BeaconState<C>) -> Self {
-        Self(Store::new(beacon_state))
+    pub fn new(beacon_state: BeaconState<C>, synced_block_signatures: VerifySignatures) -> Self {
+        Self {
+            store: Store::new(beacon_state),
+            unix_millis: unix_millis_source(SystemClock),
+            maximum_clock_disparity: MAXIMUM_GOSSIP_CLOCK_DISPARITY,
+            gossiped_block_signatures: VerifySignatures::Batch,
+            synced_block_signatures,
+            trusted_peers: HashSet::new(),
+            reward_reporter: None,
+        }
+    }
+
+    pub fn from_checkpoint(
+        beacon_state: BeaconState<C>,
+        beacon_block: BeaconBlock<C>,
+        synced_block_signatures: VerifySignatures,
+    ) -> Result<Self> {
+        let store = Store::from_checkpoint(beacon_state, beacon_block)?;
+        Ok(Self {
+            store,
+            unix_millis: unix_millis_source(SystemClock),
+            maximum_clock_disparity: MAXIMUM_GOSSIP_CLOCK_DISPARITY,
+            gossiped_block_signatures: VerifySignatures::Batch,
+            synced_block_signatures,
+            trusted_peers: HashSet::new(),
+            reward_reporter: None,
+        })
+    }
+
+    pub fn with_reward_reporter(mut self, reward_reporter: RewardReporter) -> Self {
+        self.reward_reporter = Some(reward_reporter);
+        self
+    }
+
+    /// Intended for simulations. Nodes connected to real peers should verify gossiped blocks.
+    pub fn with_gossiped_block_signatures(mut self, verify_signatures: VerifySignatures) -> Self {
+        self.gossiped_block_signatures = verify_signatures;
+        self
+    }
+
+    /// Makes blocks synced from `trusted_peers` be verified as specified by the
+    /// `synced_block_signatures` passed to the constructor. There are no trusted peers by default.
+    pub fn with_trusted_peers(mut self, trusted_peers: impl IntoIterator<Item = PeerKey>) -> Self {
+        self.trusted_peers = trusted_peers.into_iter().collect();
+        self
+    }
+
+    /// Replaces the system clock. The clock should be the one driving the slot timer.
+    pub fn with_clock<K: Clock + Send + 'static>(mut self, clock: K) -> Self {
+        self.unix_millis = unix_millis_source(clock);
+        self
+    }
+
+    pub fn with_maximum_clock_disparity(mut self, maximum_clock_disparity: Duration) -> Self {
+        self.maximum_clock_disparity = maximum_clock_disparity;
+        self.store
+            .set_maximum_clock_disparity(maximum_clock_disparity);
+        self
+    }
+
+    pub fn with_state_transition_observer(
+        mut self,
+        observer: Box<dyn StateTransitionObserver<C> + Send>,
+    ) -> Self {
+        self.store.set_state_transition_observer(observer);
+        self
+    }
+
+    /// See [`Store::subscribe`].
+    pub fn subscribe_to_fork_choice_events(&mut self) -> UnboundedReceiver<Event> {
+        self.store.subscribe()
     }
 
     pub fn head_state(&self) -> &BeaconState<C> {
-        self.0.head_state()
+        self.store.head_state()
+    }
+
+    pub fn head_root(&self) -> H256 {
+        self.store.head_root()
+    }
+
+    pub fn finalized_checkpoint(&self) -> Checkpoint {
+        self.store.finalized_checkpoint()
     }
 
     pub fn handle_slot_start(&mut self, slot: Slot) -> Result<()> {
         info!("slot {} started", slot);
-        self.0.on_slot(slot)
+        self.tick_at_least(self.store.slot_start_millis(slot))?;
+        if let Some(reward_reporter) = self.reward_reporter.as_mut() {
+            reward_reporter.report(&self.store)?;
+        }
+        Ok(())
     }
 
-    pub fn handle_slot_midpoint(&mut self, slot: Slot) {
+    pub fn handle_slot_midpoint(&mut self, slot: Slot) -> Result<()> {
         info!("slot {} midpoint", slot);
+        let half_slot_millis = C::SecondsPerSlot::U64 * 1000 / 2;
+        self.tick_at_least(self.store.slot_start_millis(slot) + half_slot_millis)
+    }
+
+    // Ticks from the slot timer may be handled slightly before the clock reaches them, so the
+    // store is advanced to at least the time of the tick.
+    fn tick_at_least(&mut self, tick_millis: u64) -> Result<()> {
+        let now_millis = (self.unix_millis)()?.max(tick_millis);
+        self.store.on_tick(now_millis)
+    }
+
+    // Advances `store` to the current time and returns it.
+    fn tick(&mut self) -> Result<u64> {
+        let now_millis = (self.unix_millis)()?;
+        self.store.on_tick(now_millis)?;
+        Ok(now_millis)
     }
+
+    // <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/networking/p2p-interface.md#global-topics>
+    fn is_from_future_slot(&self, slot: Slot, now_millis: u64) -> bool {
+        let disparity_millis = self.maximum_clock_disparity.as_millis() as u64;
+        now_millis + disparity_millis < self.store.slot_start_millis(slot)
+    }
+
+    fn is_past_propagation_range(&self, slot: Slot, now_millis: u64) -> bool {
+        let disparity_millis = self.maximum_clock_disparity.as_millis() as u64;
+        let end_millis = self
+            .store
+            .slot_start_millis(slot + ATTESTATION_PROPAGATION_SLOT_RANGE + 1);
+        end_millis + disparity_millis <= now_millis
+    }
+}
+
+fn unix_millis_source<K: Clock + Send + 'static>(clock: K) -> UnixMillisSource {
+    Box::new(move || {
+        let (_, now) = clock.now();
+        let unix_epoch_to_now = now.duration_since(K::SystemTime::UNIX_EPOCH)?;
+        Ok(unix_epoch_to_now.as_millis() as u64)
+    })
 }
 
 impl<C: Config> Networked<C> for Node<C> {
-    fn accept_beacon_block(&mut self, block: BeaconBlock<C>) -> Result<()> {
-        info!("received beacon block: {:?}", block);
-        self.0.on_block(block)
+    fn accept_beacon_block(&mut self, block: BeaconBlock<C>, peer: PeerKey) -> Result<()> {
+        info!("received beacon block from {:?}: {:?}", peer, block);
+        let now_millis = self.tick()?;
+        if self.is_from_future_slot(block.slot, now_millis) {
+            warn!("ignoring gossiped block from future slot {}", block.slot);
+            return Ok(());
+        }
+        self.store
+            .on_block(block, self.gossiped_block_signatures, Origin::Peer(peer.0))
     }
 
-    fn accept_beacon_attestation(&mut self, attestation: Attestation<C>) -> Result<()> {
-        info!("received beacon attestation: {:?}", attestation);
-        self.0.on_attestation(attestation)
+    fn accept_synced_beacon_block(&mut self, block: BeaconBlock<C>, peer: PeerKey) -> Result<()> {
+        info!("received synced beacon block from {:?}: {:?}", peer, block);
+        self.tick()?;
+        let verify_signatures = if self.trusted_peers.contains(&peer) {
+            self.synced_block_signatures
+        } else {
+            VerifySignatures::Batch
+        };
+        self.store
+            .on_block(block, verify_signatures, Origin::Peer(peer.0))
+    }
+
+    fn accept_beacon_attestation(
+        &mut self,
+        attestation: Attestation<C>,
+        peer: PeerKey,
+    ) -> Result<()> {
+        info!(
+            "received beacon attestation from {:?}: {:?}",
+            peer, attestation
+        );
+        let now_millis = self.tick()?;
+        let slot = attestation.data.slot;
+        if self.is_from_future_slot(slot, now_millis)
+            || self.is_past_propagation_range(slot, now_millis)
+        {
+            warn!("ignoring gossiped attestation from slot {}", slot);
+            return Ok(());
+        }
+        self.store.on_attestation(attestation, Origin::Peer(peer.0))
     }
 
     fn get_status(&self) -> Status {
-        let head_state = self.0.head_state();
+        // Fork choice is not cheap, so the head is only looked up once.
+        let head_root = self.store.head_root();
+        let head_state = self
+            .store
+            .block_state(head_root)
+            .expect("head block should have a state");
         let Checkpoint { epoch, root } = head_state.finalized_checkpoint;
         Status {
             fork_version: head_state.fork.current_version,
             finalized_root: root,
             finalized_epoch: epoch,
-            head_root: crypto::hash_tree_root(head_state),
+            head_root,
             head_slot: head_state.slot,
         }
     }
 
     fn get_beacon_block(&self, root: H256) -> Option<&BeaconBlock<C>> {
-        self.0.block(root)
+        self.store.block(root)
     }
 }
 
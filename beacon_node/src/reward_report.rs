@@ -0,0 +1,151 @@
+//! Reporting of balance changes of selected validators in epoch transitions.
+//!
+//! An epoch is reported once the chain of the head contains a block from a later epoch. The
+//! changes are computed by advancing a copy of the state of the last block before the epoch
+//! boundary in that chain, which repeats the epoch transition done by the block after it.
+//! This duplicates work done by the fork choice store, but keeps the store unaware of reporting.
+//!
+//! Every epoch is reported at most once. If the head later switches to a chain with a different
+//! block before the boundary, the report is not corrected.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write as _},
+};
+
+use anyhow::Result;
+use beacon_fork_choice::Store;
+use log::info;
+use serde::Serialize;
+use transition_functions::{process_slot, rewards_and_penalties::rewards_and_penalties::Deltas};
+use typenum::Unsigned as _;
+use types::{
+    config::Config,
+    primitives::{Epoch, ValidatorIndex, H256},
+};
+
+use crate::runtime_config::RewardReportConfig;
+
+#[derive(Serialize)]
+struct Record {
+    epoch: Epoch,
+    validator_index: ValidatorIndex,
+    rewards: u64,
+    penalties: u64,
+    #[serde(flatten)]
+    deltas: Deltas,
+}
+
+pub struct RewardReporter {
+    validator_indices: Vec<ValidatorIndex>,
+    output: Option<BufWriter<File>>,
+    last_reported_epoch: Option<Epoch>,
+}
+
+impl RewardReporter {
+    pub fn new(config: RewardReportConfig) -> Result<Self> {
+        let output = match config.output_path {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Some(BufWriter::new(file))
+            }
+            None => None,
+        };
+        Ok(Self {
+            validator_indices: config.validator_indices,
+            output,
+            last_reported_epoch: None,
+        })
+    }
+
+    /// Reports the balance changes in epoch transitions in the chain of the head of `store` that
+    /// have not been reported yet. Only the latest one is reported the first time this is called.
+    pub fn report<C: Config>(&mut self, store: &Store<C>) -> Result<()> {
+        let head_root = store.head_root();
+        let head_epoch = match store.block(head_root) {
+            Some(block) => block.slot / C::SlotsPerEpoch::U64,
+            None => return Ok(()),
+        };
+
+        let last_ended_epoch = match head_epoch.checked_sub(1) {
+            Some(epoch) => epoch,
+            None => return Ok(()),
+        };
+
+        let first_epoch = match self.last_reported_epoch {
+            Some(epoch) => epoch + 1,
+            None => last_ended_epoch,
+        };
+
+        for epoch in first_epoch..=last_ended_epoch {
+            if let Some(deltas) = canonical_deltas(store, head_root, epoch) {
+                self.write(epoch, deltas.as_slice())?;
+            }
+            self.last_reported_epoch = Some(epoch);
+        }
+
+        if let Some(output) = self.output.as_mut() {
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, epoch: Epoch, deltas: &[Deltas]) -> Result<()> {
+        for validator_index in self.validator_indices.iter().copied() {
+            let deltas = match deltas.get(validator_index as usize) {
+                Some(deltas) => *deltas,
+                None => continue,
+            };
+
+            let record = Record {
+                epoch,
+                validator_index,
+                rewards: deltas.rewards(),
+                penalties: deltas.penalties(),
+                deltas,
+            };
+
+            info!(
+                    "balance changes in epoch {} (validator_index: {}, rewards: {}, penalties: {}, deltas: {:?})",
+                    epoch, validator_index, record.rewards, record.penalties, deltas,
+                );
+
+            if let Some(output) = self.output.as_mut() {
+                serde_json::to_writer(&mut *output, &record)?;
+                writeln!(output)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Returns `None` if the transition out of `epoch` happened before any state in `store`.
+fn canonical_deltas<C: Config>(
+    store: &Store<C>,
+    head_root: H256,
+    epoch: Epoch,
+) -> Option<Vec<Deltas>> {
+    let next_epoch_start = (epoch + 1) * C::SlotsPerEpoch::U64;
+
+    let mut root = head_root;
+    loop {
+        let block = store.block(root)?;
+        if block.slot < next_epoch_start {
+            break;
+        }
+        root = block.parent_root;
+    }
+
+    // The state of a checkpoint the store was started from may be past the block.
+    let mut state = store.block_state(root)?.clone();
+    if next_epoch_start <= state.slot {
+        return None;
+    }
+
+    process_slot::process_slots_with_deltas(&mut state, next_epoch_start)
+        .into_iter()
+        .find(|(transitioned_epoch, _)| *transitioned_epoch == epoch)
+        .map(|(_, deltas)| deltas)
+}
@@ -1,9 +1,14 @@
+use core::num::NonZeroU32;
 use std::path::PathBuf;
 
 use anyhow::{ensure, Result};
+use beacon_fork_choice::MAXIMUM_GOSSIP_CLOCK_DISPARITY;
+use eth1::Eth1Config;
 use eth2_network_libp2p::NetworkConfig;
 use serde::Deserialize;
 use thiserror::Error;
+use transition_functions::blocks::block_signatures::VerifySignatures;
+use types::primitives::{ValidatorIndex, H256};
 
 #[derive(Debug, Error)]
 enum Error {
@@ -21,11 +26,55 @@ pub enum Preset {
     Minimal,
 }
 
+/// A finalized state and the block that produced it, used to start the node without replaying
+/// the chain from genesis.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    pub state_path: PathBuf,
+    pub block_path: PathBuf,
+}
+
+/// Validators whose balance changes in epoch transitions are reported.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewardReportConfig {
+    pub validator_indices: Vec<ValidatorIndex>,
+    /// If present, the changes are also appended to this file as JSON lines.
+    pub output_path: Option<PathBuf>,
+}
+
+/// The format of files is determined from their extensions.
+/// See [`file_formats::Format::from_path`] for the list of supported ones.
 #[derive(Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct RuntimeConfig {
     pub preset: Preset,
     pub genesis_state_path: PathBuf,
+    /// If present, the root of the genesis state is checked against it.
+    pub genesis_state_root: Option<H256>,
+    /// If present, the node starts from the checkpoint instead of the genesis state.
+    pub checkpoint: Option<CheckpointConfig>,
+    /// If present, the node follows the deposit contract on the eth1 chain.
+    pub eth1: Option<Eth1Config>,
+    /// How signatures in blocks received from `trusted_peers` in `BlocksByRange` responses are
+    /// verified. Weaker modes speed up syncing. Blocks synced from other peers are always verified
+    /// in a batch.
+    pub synced_block_signatures: VerifySignatures,
+    /// Base58-encoded libp2p peer IDs.
+    pub trusted_peers: Vec<String>,
+    /// If present, balance changes of the listed validators are logged after every epoch.
+    pub reward_report: Option<RewardReportConfig>,
+    /// If present, the changes made by every step of every state transition run for incoming
+    /// blocks are written to this file as JSON lines. Slows down block processing considerably.
+    pub state_transition_trace: Option<PathBuf>,
+    /// If present, time passes this many times faster than in reality, counting from genesis.
+    /// Only useful in local testnets where every node uses the same rate.
+    pub clock_rate: Option<NonZeroU32>,
+    /// Objects from slots that start within this many milliseconds are processed immediately
+    /// instead of being delayed until their slot. Gossiped objects from slots that start later
+    /// are ignored.
+    pub maximum_clock_disparity_millis: u64,
     #[serde(flatten)]
     pub network: NetworkConfig,
 }
@@ -35,6 +84,15 @@ impl Default for RuntimeConfig {
         Self {
             preset: Preset::Mainnet,
             genesis_state_path: "genesis-state.yaml".into(),
+            genesis_state_root: None,
+            checkpoint: None,
+            eth1: None,
+            synced_block_signatures: VerifySignatures::Batch,
+            trusted_peers: vec![],
+            reward_report: None,
+            state_transition_trace: None,
+            clock_rate: None,
+            maximum_clock_disparity_millis: MAXIMUM_GOSSIP_CLOCK_DISPARITY.as_millis() as u64,
             network: NetworkConfig::default(),
         }
     }
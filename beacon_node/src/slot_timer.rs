@@ -1,12 +1,15 @@
 //! A [`Stream`] that produces beacon chain slots.
 //!
+//! Time is taken from the system clock by default. [`start_with_clock`] accepts any [`Clock`],
+//! which makes it possible to run the timer faster than in reality or advance it manually.
+//!
 //! # Implementation
 //!
-//! This is implemented using [`Interval`]. Some subtleties to keep in mind:
+//! With the system clock, this is implemented using [`Interval`]. Some subtleties to keep in mind:
 //!
 //! - The API of [`Interval`] (as well as other timer utilities in [`tokio::timer`]) uses
 //!   [`Instant`]s. [`Instant`]s are opaque. There is no way to directly convert a timestamp
-//!   (of any kind, not just Unix time) to an [`Instant`]. The hack in [`start`] may result in
+//!   (of any kind, not just Unix time) to an [`Instant`]. The hack in [`SystemClock`] may result in
 //!   unexpected behavior in extreme conditions.
 //!
 //! - An [`Interval`] may produce items late, but the delays do not accumulate. The interval of time
@@ -57,7 +60,9 @@
 //! [`Interval`]:               tokio::timer::Interval
 //! [`Stream`]:                 futures::Stream
 //!
-//! [`start`]: crate::slot_timer::start
+//! [`Clock`]:            crate::clock::Clock
+//! [`SystemClock`]:      crate::clock::SystemClock
+//! [`start_with_clock`]: crate::slot_timer::start_with_clock
 //!
 //! [`clokwerk`]:      https://crates.io/crates/clokwerk
 //! [`job_scheduler`]: https://crates.io/crates/job_scheduler
@@ -66,21 +71,21 @@
 //! [`white_rabbit`]:  https://crates.io/crates/white_rabbit
 
 use core::{iter, mem, time::Duration};
-use std::time::{Instant, SystemTime};
 
 use anyhow::{Error, Result};
 use futures::{stream, Stream};
-use tokio::timer::Interval;
 use typenum::Unsigned as _;
 use types::{
     config::Config,
     primitives::{Slot, UnixSeconds},
 };
 
-use crate::fake_time::{InstantLike, SystemTimeLike};
+use crate::{
+    clock::{Clock, SystemClock},
+    fake_time::{InstantLike, SystemTimeLike},
+};
 
-#[derive(Clone, Copy)]
-#[cfg_attr(test, derive(PartialEq, Eq, Debug))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Tick {
     SlotStart(Slot),
     SlotMidpoint(Slot),
@@ -94,7 +99,8 @@ impl Tick {
         }))
     }
 
-    fn next(self) -> Self {
+    /// Returns the tick that follows `self`. Ticks occur every half slot.
+    pub fn next(self) -> Self {
         match self {
             Self::SlotStart(slot) => Self::SlotMidpoint(slot),
             // This will overflow in the far future.
@@ -106,23 +112,30 @@ impl Tick {
 pub fn start<C: Config>(
     genesis_unix_time: UnixSeconds,
 ) -> Result<impl Stream<Item = Tick, Error = Error>> {
-    // We assume the `Instant` and `SystemTime` obtained here correspond to the same point in time.
-    // This is slightly inaccurate but the error will probably be negligible compared to clock
-    // differences between different nodes in the network.
+    start_with_clock::<C, _>(&SystemClock, genesis_unix_time)
+}
+
+/// Like [`start`], but with time provided by `clock`.
+pub fn start_with_clock<C: Config, K: Clock>(
+    clock: &K,
+    genesis_unix_time: UnixSeconds,
+) -> Result<impl Stream<Item = Tick, Error = Error>> {
+    let (now_instant, now_system_time) = clock.now();
     let (next_tick, instant) =
-        next_tick_with_instant::<C, _, _>(Instant::now(), SystemTime::now(), genesis_unix_time)?;
+        next_tick_with_instant::<C, _, _>(now_instant, now_system_time, genesis_unix_time)?;
 
     let half_slot_duration = Duration::from_secs(C::SecondsPerSlot::U64) / 2;
 
-    let slot_stream = Interval::new(instant, half_slot_duration)
+    let slot_stream = clock
+        .interval(instant, half_slot_duration)
         .zip(next_tick.stream())
-        .map(|(_, tick)| tick)
-        .from_err();
+        .map(|(_, tick)| tick);
 
     Ok(slot_stream)
 }
 
-fn next_tick_with_instant<C: Config, I: InstantLike, S: SystemTimeLike>(
+/// Returns the next tick after `now_system_time` and the instant at which it occurs.
+pub fn next_tick_with_instant<C: Config, I: InstantLike, S: SystemTimeLike>(
     now_instant: I,
     now_system_time: S,
     genesis_unix_time: UnixSeconds,
@@ -183,7 +196,12 @@ mod tests {
     use types::config::MinimalConfig;
     use void::ResultVoidExt as _;
 
-    use crate::fake_time::{FakeInstant, FakeSystemTime, Timespec};
+    use std::time::SystemTime;
+
+    use crate::{
+        clock::ManualClock,
+        fake_time::{FakeInstant, FakeSystemTime, Timespec},
+    };
 
     use super::*;
 
@@ -233,6 +251,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn manual_clock_drives_tick_stream() -> Result<()> {
+        let clock = ManualClock::new(Timespec::from_secs(776));
+        let mut tick_stream = start_with_clock::<MinimalConfig, _>(&clock, 777)?;
+
+        let mut assert_poll = |expected_async| {
+            future::lazy(|| tick_stream.poll())
+                .inspect(|actual_async| assert_eq!(actual_async, &expected_async))
+                .wait()
+        };
+
+        assert_poll(Async::NotReady)?;
+        clock.advance(Duration::from_secs(1));
+        assert_poll(Async::Ready(Some(Tick::SlotStart(1))))?;
+        assert_poll(Async::NotReady)?;
+        clock.advance(Duration::from_secs(6));
+        assert_poll(Async::Ready(Some(Tick::SlotMidpoint(1))))?;
+        assert_poll(Async::Ready(Some(Tick::SlotStart(2))))?;
+        assert_poll(Async::NotReady)?;
+
+        Ok(())
+    }
+
     #[test_case(100, Tick::SlotStart(1),    777; "0th slot start before genesis")]
     #[test_case(777, Tick::SlotStart(1),    777; "0th slot start at genesis")]
     #[test_case(778, Tick::SlotMidpoint(1), 780; "0th slot midpoint 1 second after genesis")]
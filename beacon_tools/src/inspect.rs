@@ -0,0 +1,299 @@
+//! Decoding, printing and converting objects of any of the types in `types`.
+//!
+//! Besides the formats supported by `file_formats`, objects can be read from hex strings and from
+//! SSZ compressed using the Snappy frame format, which is how gossip payloads appear in logs.
+
+use std::{
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Error as AnyhowError, Result};
+use file_formats::Format;
+use serde::{de::DeserializeOwned, Serialize};
+use ssz::{Decode, Encode};
+use thiserror::Error;
+use tree_hash::{SignedRoot, TreeHash};
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    primitives::H256,
+    types::{
+        Attestation, AttestationData, AttesterSlashing, BeaconBlock, BeaconBlockBody,
+        BeaconBlockHeader, Checkpoint, Deposit, DepositData, Eth1Data, Fork, HistoricalBatch,
+        IndexedAttestation, PendingAttestation, ProposerSlashing, Validator, VoluntaryExit,
+    },
+};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("unknown encoding {0:?} (supported encodings: yaml, json, ssz, ssz_snappy, ssz_snappy_framed)")]
+    UnknownEncoding(String),
+    #[error("unknown type {0:?}")]
+    UnknownType(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Format(Format),
+    /// SSZ compressed using the Snappy frame format.
+    SszSnappyFramed,
+}
+
+impl FromStr for Encoding {
+    type Err = AnyhowError;
+
+    fn from_str(string: &str) -> Result<Self> {
+        match string {
+            "yaml" => Ok(Encoding::Format(Format::Yaml)),
+            "json" => Ok(Encoding::Format(Format::Json)),
+            "ssz" => Ok(Encoding::Format(Format::Ssz)),
+            "ssz_snappy" => Ok(Encoding::Format(Format::SszSnappy)),
+            "ssz_snappy_framed" => Ok(Encoding::SszSnappyFramed),
+            _ => Err(Error::UnknownEncoding(string.to_owned()).into()),
+        }
+    }
+}
+
+impl Encoding {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Format::from_path(path).map(Encoding::Format)
+    }
+
+    pub fn decode<T: DeserializeOwned + Decode>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Encoding::Format(format) => format.decode(bytes),
+            Encoding::SszSnappyFramed => {
+                let mut decompressed = vec![];
+                snap::Reader::new(bytes).read_to_end(&mut decompressed)?;
+                Format::Ssz.decode(decompressed.as_slice())
+            }
+        }
+    }
+
+    pub fn encode<T: Serialize + Encode>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Format(format) => format.encode(value),
+            Encoding::SszSnappyFramed => {
+                let mut compressed = vec![];
+                let mut writer = snap::Writer::new(&mut compressed);
+                writer.write_all(value.as_ssz_bytes().as_slice())?;
+                writer.flush()?;
+                drop(writer);
+                Ok(compressed)
+            }
+        }
+    }
+}
+
+pub enum Input {
+    /// Bytes encoded as a hexadecimal string with an optional `0x` prefix.
+    Hex(String),
+    File(PathBuf),
+}
+
+impl Input {
+    /// Reads an object from the input.
+    ///
+    /// If `encoding` is `None`, hex strings are assumed to contain SSZ and the encoding of files is
+    /// determined from their extensions.
+    pub fn read<T: DeserializeOwned + Decode>(&self, encoding: Option<Encoding>) -> Result<T> {
+        match self {
+            Input::Hex(string) => {
+                let bytes = hex::decode(string.trim_start_matches("0x"))?;
+                let encoding = encoding.unwrap_or(Encoding::Format(Format::Ssz));
+                encoding.decode(bytes.as_slice())
+            }
+            Input::File(path) => {
+                let encoding = match encoding {
+                    Some(encoding) => encoding,
+                    None => Encoding::from_path(path)?,
+                };
+                encoding.decode(std::fs::read(path)?.as_slice())
+            }
+        }
+    }
+}
+
+/// A type that can be inspected.
+pub trait Object: Serialize + DeserializeOwned + Encode + Decode + TreeHash {
+    fn hash_tree_root(&self) -> H256 {
+        H256::from_slice(self.tree_hash_root().as_slice())
+    }
+
+    /// The root covered by the signature in the object. `None` for types without signatures.
+    fn signed_root(&self) -> Option<H256>;
+}
+
+macro_rules! impl_object {
+    (@signed_root signed $value:expr) => {
+        Some(H256::from_slice(SignedRoot::signed_root($value).as_slice()))
+    };
+    (@signed_root unsigned $value:expr) => {
+        None
+    };
+    ($kind:ident [$($generics:tt)*] $($type:ty),* $(,)?) => {
+        $(
+            impl<$($generics)*> Object for $type {
+                fn signed_root(&self) -> Option<H256> {
+                    impl_object!(@signed_root $kind self)
+                }
+            }
+        )*
+    };
+}
+
+impl_object!(
+    signed [C: Config + Serialize + DeserializeOwned]
+    Attestation<C>,
+    BeaconBlock<C>,
+    IndexedAttestation<C>,
+);
+
+impl_object!(signed [] BeaconBlockHeader, DepositData, VoluntaryExit);
+
+impl_object!(
+    unsigned [C: Config + Serialize + DeserializeOwned]
+    AttesterSlashing<C>,
+    BeaconBlockBody<C>,
+    BeaconState<C>,
+    HistoricalBatch<C>,
+    PendingAttestation<C>,
+);
+
+impl_object!(
+    unsigned []
+    AttestationData,
+    Checkpoint,
+    Deposit,
+    Eth1Data,
+    Fork,
+    ProposerSlashing,
+    Validator,
+);
+
+/// Something to do with an object of a type chosen at runtime.
+pub trait Visitor {
+    type Output;
+
+    fn visit<T: Object>(self) -> Self::Output;
+}
+
+macro_rules! type_names {
+    ($($name:ident => $type:ty),* $(,)?) => {
+        /// The names of types that can be inspected. They match the names in the specification.
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum TypeName {
+            $($name,)*
+        }
+
+        impl FromStr for TypeName {
+            type Err = AnyhowError;
+
+            fn from_str(string: &str) -> Result<Self> {
+                match string {
+                    $(stringify!($name) => Ok(TypeName::$name),)*
+                    _ => Err(Error::UnknownType(string.to_owned()).into()),
+                }
+            }
+        }
+
+        impl TypeName {
+            pub fn visit<C: Config + Serialize + DeserializeOwned, V: Visitor>(
+                self,
+                visitor: V,
+            ) -> V::Output {
+                match self {
+                    $(TypeName::$name => visitor.visit::<$type>(),)*
+                }
+            }
+        }
+    };
+}
+
+type_names! {
+    Attestation => Attestation<C>,
+    AttestationData => AttestationData,
+    AttesterSlashing => AttesterSlashing<C>,
+    BeaconBlock => BeaconBlock<C>,
+    BeaconBlockBody => BeaconBlockBody<C>,
+    BeaconBlockHeader => BeaconBlockHeader,
+    BeaconState => BeaconState<C>,
+    Checkpoint => Checkpoint,
+    Deposit => Deposit,
+    DepositData => DepositData,
+    Eth1Data => Eth1Data,
+    Fork => Fork,
+    HistoricalBatch => HistoricalBatch<C>,
+    IndexedAttestation => IndexedAttestation<C>,
+    PendingAttestation => PendingAttestation<C>,
+    ProposerSlashing => ProposerSlashing,
+    Validator => Validator,
+    VoluntaryExit => VoluntaryExit,
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            epoch: 3,
+            root: H256::repeat_byte(0xCD),
+        }
+    }
+
+    #[test]
+    fn objects_survive_round_trip_through_every_encoding() -> Result<()> {
+        for encoding in &["yaml", "json", "ssz", "ssz_snappy", "ssz_snappy_framed"] {
+            let encoding = encoding.parse::<Encoding>()?;
+            let bytes = encoding.encode(&checkpoint())?;
+            let decoded: Checkpoint = encoding.decode(bytes.as_slice())?;
+            assert_eq!(decoded, checkpoint());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn hex_input_is_decoded_as_ssz() -> Result<()> {
+        let input = Input::Hex(format!("0x{}", hex::encode(checkpoint().as_ssz_bytes())));
+        let decoded: Checkpoint = input.read(None)?;
+        assert_eq!(decoded, checkpoint());
+        Ok(())
+    }
+
+    #[test]
+    fn only_signed_types_have_signed_roots() {
+        let block = BeaconBlock::<MinimalConfig>::default();
+
+        // `SignedRoot::signed_root` is also in scope.
+        assert!(Object::signed_root(&block).is_some());
+        assert_ne!(Object::signed_root(&block), Some(block.hash_tree_root()));
+        assert_eq!(Object::signed_root(&checkpoint()), None);
+    }
+
+    #[test]
+    fn type_names_select_types() -> Result<()> {
+        struct Root(Vec<u8>);
+
+        impl Visitor for Root {
+            type Output = Result<H256>;
+
+            fn visit<T: Object>(self) -> Result<H256> {
+                let value: T = Format::Ssz.decode(self.0.as_slice())?;
+                Ok(value.hash_tree_root())
+            }
+        }
+
+        let type_name = "Checkpoint".parse::<TypeName>()?;
+        let root = type_name.visit::<MinimalConfig, _>(Root(checkpoint().as_ssz_bytes()))?;
+
+        assert_eq!(type_name, TypeName::Checkpoint);
+        assert_eq!(root, checkpoint().hash_tree_root());
+        assert!("SignedBeaconBlock".parse::<TypeName>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+//! Tools for inspecting and debugging beacon chain objects outside of the node.
+
+pub mod inspect;
+pub mod state_diff;
+pub mod transition;
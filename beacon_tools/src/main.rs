@@ -0,0 +1,327 @@
+use std::{
+    fs,
+    io::{self, Write as _},
+    path::PathBuf,
+    process,
+    str::FromStr,
+};
+
+use anyhow::{ensure, Result};
+use beacon_tools::{
+    inspect::{Encoding, Input, Object, TypeName, Visitor},
+    state_diff, transition,
+};
+use file_formats::Format;
+use serde::{de::DeserializeOwned, Serialize};
+use structopt::StructOpt;
+use thiserror::Error;
+use transition_functions::blocks::block_signatures::VerifySignatures;
+use types::{
+    beacon_state::BeaconState,
+    config::{Config, MainnetConfig, MinimalConfig},
+    primitives::Slot,
+    types::BeaconBlock,
+};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("unknown preset {0:?} (supported presets: mainnet, minimal)")]
+    UnknownPreset(String),
+    #[error("no blocks or target slot given")]
+    NothingToApply,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Preset {
+    Mainnet,
+    Minimal,
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "mainnet" => Ok(Preset::Mainnet),
+            "minimal" => Ok(Preset::Minimal),
+            _ => Err(Error::UnknownPreset(string.to_owned())),
+        }
+    }
+}
+
+/// Tools for debugging beacon chain objects.
+///
+/// The format of files is determined from their extensions.
+/// Paths without an extension are assumed to contain YAML.
+#[derive(StructOpt)]
+enum Command {
+    /// Compares two states field by field.
+    ///
+    /// Exits with status 1 if the states differ.
+    Diff {
+        #[structopt(long, default_value = "mainnet")]
+        preset: Preset,
+        /// Print the differences as JSON instead of text.
+        #[structopt(long)]
+        json: bool,
+        left: PathBuf,
+        right: PathBuf,
+    },
+    /// Applies blocks to a state and processes empty slots after them.
+    ///
+    /// Prints the root of the post-state. Invalid blocks make the command fail.
+    Transition {
+        #[structopt(long, default_value = "mainnet")]
+        preset: Preset,
+        /// How signatures in blocks are verified: individually, batch, proposer-only or none.
+        #[structopt(
+            long,
+            default_value = "batch",
+            parse(try_from_str = transition::parse_verify_signatures)
+        )]
+        verify_signatures: VerifySignatures,
+        /// Do not check the state roots in blocks. Useful for blocks produced by hand.
+        #[structopt(long)]
+        skip_state_root_validation: bool,
+        /// Blocks to apply in the order they are given.
+        #[structopt(long = "block")]
+        blocks: Vec<PathBuf>,
+        /// Process empty slots up to this slot after applying the blocks.
+        #[structopt(long)]
+        slot: Option<Slot>,
+        /// Where to write the post-state.
+        #[structopt(long)]
+        output: Option<PathBuf>,
+        pre_state: PathBuf,
+    },
+    /// Prints an object along with its `hash_tree_root` and `signed_root`.
+    Inspect {
+        #[structopt(flatten)]
+        input: InputOptions,
+        /// Print the object as JSON instead of YAML.
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Converts an object to another encoding.
+    Convert {
+        #[structopt(flatten)]
+        input: InputOptions,
+        /// One of yaml, json, ssz, ssz_snappy or ssz_snappy_framed.
+        /// Determined from the extension of `output` by default.
+        #[structopt(long)]
+        output_encoding: Option<Encoding>,
+        output: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+struct InputOptions {
+    #[structopt(long, default_value = "mainnet")]
+    preset: Preset,
+    /// The name of the type as it appears in the specification, like `BeaconBlock`.
+    #[structopt(long = "type")]
+    type_name: TypeName,
+    /// One of yaml, json, ssz, ssz_snappy or ssz_snappy_framed.
+    /// Determined from the extension of `input` by default.
+    #[structopt(long)]
+    input_encoding: Option<Encoding>,
+    /// Treat `input` as a hex string instead of a path. Hex strings contain SSZ by default.
+    #[structopt(long)]
+    hex: bool,
+    input: String,
+}
+
+impl InputOptions {
+    fn visit<V: Visitor>(self, visitor: impl FnOnce(Input, Option<Encoding>) -> V) -> V::Output {
+        let input = if self.hex {
+            Input::Hex(self.input)
+        } else {
+            Input::File(self.input.into())
+        };
+        let visitor = visitor(input, self.input_encoding);
+        match self.preset {
+            Preset::Mainnet => self.type_name.visit::<MainnetConfig, _>(visitor),
+            Preset::Minimal => self.type_name.visit::<MinimalConfig, _>(visitor),
+        }
+    }
+}
+
+fn main() {
+    match run(Command::from_args()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{:?}", error);
+            process::exit(2);
+        }
+    }
+}
+
+/// Returns `false` if the command succeeded but found a difference.
+fn run(command: Command) -> Result<bool> {
+    match command {
+        Command::Diff {
+            preset,
+            json,
+            left,
+            right,
+        } => match preset {
+            Preset::Mainnet => diff::<MainnetConfig>(json, left, right),
+            Preset::Minimal => diff::<MinimalConfig>(json, left, right),
+        },
+        Command::Transition {
+            preset,
+            verify_signatures,
+            skip_state_root_validation,
+            blocks,
+            slot,
+            output,
+            pre_state,
+        } => {
+            let options = TransitionOptions {
+                verify_signatures,
+                validate_state_root: !skip_state_root_validation,
+                blocks,
+                slot,
+                output,
+                pre_state,
+            };
+            match preset {
+                Preset::Mainnet => run_transition::<MainnetConfig>(options)?,
+                Preset::Minimal => run_transition::<MinimalConfig>(options)?,
+            }
+            Ok(true)
+        }
+        Command::Inspect { input, json } => {
+            input.visit(|input, input_encoding| Inspect {
+                input,
+                input_encoding,
+                json,
+            })?;
+            Ok(true)
+        }
+        Command::Convert {
+            input,
+            output_encoding,
+            output,
+        } => {
+            input.visit(|input, input_encoding| Convert {
+                input,
+                input_encoding,
+                output_encoding,
+                output,
+            })?;
+            Ok(true)
+        }
+    }
+}
+
+fn diff<C: Config + DeserializeOwned>(json: bool, left: PathBuf, right: PathBuf) -> Result<bool> {
+    let left: BeaconState<C> = file_formats::read(left, None)?;
+    let right: BeaconState<C> = file_formats::read(right, None)?;
+    let diff = state_diff::diff_states(&left, &right);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print!("{}", diff);
+    }
+
+    Ok(diff.is_empty())
+}
+
+struct TransitionOptions {
+    verify_signatures: VerifySignatures,
+    validate_state_root: bool,
+    blocks: Vec<PathBuf>,
+    slot: Option<Slot>,
+    output: Option<PathBuf>,
+    pre_state: PathBuf,
+}
+
+fn run_transition<C: Config + Serialize + DeserializeOwned>(
+    options: TransitionOptions,
+) -> Result<()> {
+    let TransitionOptions {
+        verify_signatures,
+        validate_state_root,
+        blocks,
+        slot,
+        output,
+        pre_state,
+    } = options;
+
+    ensure!(!blocks.is_empty() || slot.is_some(), Error::NothingToApply);
+
+    let mut state: BeaconState<C> = file_formats::read(pre_state, None)?;
+    let blocks = blocks
+        .into_iter()
+        .map(|path| file_formats::read(path, None))
+        .collect::<Result<Vec<BeaconBlock<C>>>>()?;
+
+    transition::apply(
+        &mut state,
+        blocks.as_slice(),
+        slot,
+        verify_signatures,
+        validate_state_root,
+    )?;
+
+    println!("slot: {}", state.slot);
+    println!("state root: {:?}", state.canonical_root());
+
+    if let Some(output) = output {
+        file_formats::write(output, None, &state)?;
+    }
+
+    Ok(())
+}
+
+struct Inspect {
+    input: Input,
+    input_encoding: Option<Encoding>,
+    json: bool,
+}
+
+impl Visitor for Inspect {
+    type Output = Result<()>;
+
+    fn visit<T: Object>(self) -> Result<()> {
+        let value: T = self.input.read(self.input_encoding)?;
+        let format = if self.json {
+            Format::Json
+        } else {
+            Format::Yaml
+        };
+
+        println!("hash_tree_root: {:?}", value.hash_tree_root());
+        if let Some(signed_root) = value.signed_root() {
+            println!("signed_root: {:?}", signed_root);
+        }
+        io::stdout().write_all(format.encode(&value)?.as_slice())?;
+        println!();
+
+        Ok(())
+    }
+}
+
+struct Convert {
+    input: Input,
+    input_encoding: Option<Encoding>,
+    output_encoding: Option<Encoding>,
+    output: PathBuf,
+}
+
+impl Visitor for Convert {
+    type Output = Result<()>;
+
+    fn visit<T: Object>(self) -> Result<()> {
+        let value: T = self.input.read(self.input_encoding)?;
+        let output_encoding = match self.output_encoding {
+            Some(encoding) => encoding,
+            None => Encoding::from_path(self.output.as_path())?,
+        };
+        fs::write(self.output, output_encoding.encode(&value)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+//! Field-by-field comparison of two `BeaconState`s.
+//!
+//! Every top-level field that differs is reported along with the `hash_tree_root` of both
+//! versions. Fields that usually differ in only a few elements are also compared element by
+//! element, so that a divergence can be traced to individual validators, roots or checkpoints.
+
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use std::collections::BTreeMap;
+
+use itertools::{EitherOrBoth, Itertools as _};
+use serde::Serialize;
+use tree_hash::TreeHash;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    primitives::{Gwei, ValidatorIndex, H256},
+    types::{Checkpoint, Validator},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize)]
+pub struct Difference<T> {
+    pub left: T,
+    pub right: T,
+}
+
+impl<T: PartialEq> Difference<T> {
+    fn new(left: T, right: T) -> Option<Self> {
+        if left == right {
+            None
+        } else {
+            Some(Self { left, right })
+        }
+    }
+}
+
+impl Difference<Option<Gwei>> {
+    /// The change from `left` to `right`. Missing balances are treated as 0.
+    pub fn delta(&self) -> i128 {
+        i128::from(self.right.unwrap_or(0)) - i128::from(self.left.unwrap_or(0))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub struct FieldDifference {
+    pub name: &'static str,
+    pub roots: Difference<H256>,
+}
+
+/// A validator that differs or is only present in one of the states.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct ValidatorDifference {
+    pub validators: Difference<Option<Validator>>,
+    pub roots: Difference<Option<H256>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize)]
+pub struct CheckpointDifferences {
+    pub previous_justified_checkpoint: Option<Difference<Checkpoint>>,
+    pub current_justified_checkpoint: Option<Difference<Checkpoint>>,
+    pub finalized_checkpoint: Option<Difference<Checkpoint>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug, Serialize)]
+pub struct StateDiff {
+    pub root: Difference<H256>,
+    /// Top-level fields that differ in the order they are declared in `BeaconState`.
+    pub fields: Vec<FieldDifference>,
+    pub validators: BTreeMap<ValidatorIndex, ValidatorDifference>,
+    pub balances: BTreeMap<ValidatorIndex, Difference<Option<Gwei>>>,
+    pub block_roots: BTreeMap<usize, Difference<H256>>,
+    pub state_roots: BTreeMap<usize, Difference<H256>>,
+    pub randao_mixes: BTreeMap<usize, Difference<H256>>,
+    pub checkpoints: CheckpointDifferences,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+macro_rules! differing_fields {
+    ($left:expr, $right:expr, $($field:ident),* $(,)?) => {{
+        let mut fields = vec![];
+        $(
+            if let Some(roots) = Difference::new(root(&$left.$field), root(&$right.$field)) {
+                fields.push(FieldDifference {
+                    name: stringify!($field),
+                    roots,
+                });
+            }
+        )*
+        fields
+    }};
+}
+
+pub fn diff_states<C: Config>(left: &BeaconState<C>, right: &BeaconState<C>) -> StateDiff {
+    let fields = differing_fields!(
+        left,
+        right,
+        genesis_time,
+        slot,
+        fork,
+        latest_block_header,
+        block_roots,
+        state_roots,
+        historical_roots,
+        eth1_data,
+        eth1_data_votes,
+        eth1_deposit_index,
+        validators,
+        balances,
+        randao_mixes,
+        slashings,
+        previous_epoch_attestations,
+        current_epoch_attestations,
+        justification_bits,
+        previous_justified_checkpoint,
+        current_justified_checkpoint,
+        finalized_checkpoint,
+    );
+
+    let validators = list_differences(&left.validators, &right.validators)
+        .map(|(index, validators)| {
+            let roots = Difference {
+                left: validators.left.map(root),
+                right: validators.right.map(root),
+            };
+            let validators = Difference {
+                left: validators.left.cloned(),
+                right: validators.right.cloned(),
+            };
+            (index, ValidatorDifference { validators, roots })
+        })
+        .collect();
+
+    let balances = list_differences(&left.balances, &right.balances)
+        .map(|(index, balances)| {
+            let balances = Difference {
+                left: balances.left.copied(),
+                right: balances.right.copied(),
+            };
+            (index, balances)
+        })
+        .collect();
+
+    StateDiff {
+        root: Difference {
+            left: left.canonical_root(),
+            right: right.canonical_root(),
+        },
+        fields,
+        validators,
+        balances,
+        block_roots: root_differences(&left.block_roots, &right.block_roots),
+        state_roots: root_differences(&left.state_roots, &right.state_roots),
+        randao_mixes: root_differences(&left.randao_mixes, &right.randao_mixes),
+        checkpoints: CheckpointDifferences {
+            previous_justified_checkpoint: Difference::new(
+                left.previous_justified_checkpoint,
+                right.previous_justified_checkpoint,
+            ),
+            current_justified_checkpoint: Difference::new(
+                left.current_justified_checkpoint,
+                right.current_justified_checkpoint,
+            ),
+            finalized_checkpoint: Difference::new(
+                left.finalized_checkpoint,
+                right.finalized_checkpoint,
+            ),
+        },
+    }
+}
+
+fn root(value: &impl TreeHash) -> H256 {
+    H256::from_slice(value.tree_hash_root().as_slice())
+}
+
+fn list_differences<'a, T: PartialEq>(
+    left: &'a [T],
+    right: &'a [T],
+) -> impl Iterator<Item = (ValidatorIndex, Difference<Option<&'a T>>)> {
+    left.iter()
+        .zip_longest(right)
+        .enumerate()
+        .filter_map(|(index, elements)| {
+            let difference = match elements {
+                EitherOrBoth::Both(left, right) if left == right => return None,
+                EitherOrBoth::Both(left, right) => Difference {
+                    left: Some(left),
+                    right: Some(right),
+                },
+                EitherOrBoth::Left(left) => Difference {
+                    left: Some(left),
+                    right: None,
+                },
+                EitherOrBoth::Right(right) => Difference {
+                    left: None,
+                    right: Some(right),
+                },
+            };
+            Some((index as ValidatorIndex, difference))
+        })
+}
+
+fn root_differences(left: &[H256], right: &[H256]) -> BTreeMap<usize, Difference<H256>> {
+    left.iter()
+        .zip(right)
+        .enumerate()
+        .filter_map(|(index, (left, right))| Some((index, Difference::new(*left, *right)?)))
+        .collect()
+}
+
+macro_rules! write_validator_fields {
+    ($formatter:expr, $left:expr, $right:expr, $($field:ident),* $(,)?) => {
+        $(
+            if $left.$field != $right.$field {
+                writeln!(
+                    $formatter,
+                    "    {}: {:?} -> {:?}",
+                    stringify!($field),
+                    $left.$field,
+                    $right.$field,
+                )?;
+            }
+        )*
+    };
+}
+
+impl Display for StateDiff {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        if self.is_empty() {
+            return writeln!(
+                formatter,
+                "states are identical (root: {:?})",
+                self.root.left
+            );
+        }
+
+        writeln!(
+            formatter,
+            "state roots: {:?} -> {:?}",
+            self.root.left, self.root.right,
+        )?;
+
+        writeln!(formatter, "differing fields:")?;
+        for FieldDifference { name, roots } in &self.fields {
+            writeln!(
+                formatter,
+                "  {}: {:?} -> {:?}",
+                name, roots.left, roots.right
+            )?;
+        }
+
+        if !self.validators.is_empty() {
+            writeln!(formatter, "validators:")?;
+        }
+        for (index, ValidatorDifference { validators, roots }) in &self.validators {
+            writeln!(
+                formatter,
+                "  {}: {:?} -> {:?}",
+                index, roots.left, roots.right,
+            )?;
+            if let (Some(left), Some(right)) = (&validators.left, &validators.right) {
+                write_validator_fields!(
+                    formatter,
+                    left,
+                    right,
+                    pubkey,
+                    withdrawal_credentials,
+                    effective_balance,
+                    slashed,
+                    activation_eligibility_epoch,
+                    activation_epoch,
+                    exit_epoch,
+                    withdrawable_epoch,
+                );
+            }
+        }
+
+        if !self.balances.is_empty() {
+            writeln!(formatter, "balances:")?;
+        }
+        for (index, balances) in &self.balances {
+            writeln!(
+                formatter,
+                "  {}: {:?} -> {:?} ({:+})",
+                index,
+                balances.left,
+                balances.right,
+                balances.delta(),
+            )?;
+        }
+
+        for (name, roots) in &[
+            ("block_roots", &self.block_roots),
+            ("state_roots", &self.state_roots),
+            ("randao_mixes", &self.randao_mixes),
+        ] {
+            if !roots.is_empty() {
+                writeln!(formatter, "{}:", name)?;
+            }
+            for (index, roots) in roots.iter() {
+                writeln!(
+                    formatter,
+                    "  {}: {:?} -> {:?}",
+                    index, roots.left, roots.right,
+                )?;
+            }
+        }
+
+        let CheckpointDifferences {
+            previous_justified_checkpoint,
+            current_justified_checkpoint,
+            finalized_checkpoint,
+        } = &self.checkpoints;
+
+        for (name, checkpoints) in &[
+            (
+                "previous_justified_checkpoint",
+                previous_justified_checkpoint,
+            ),
+            ("current_justified_checkpoint", current_justified_checkpoint),
+            ("finalized_checkpoint", finalized_checkpoint),
+        ] {
+            if let Some(checkpoints) = checkpoints {
+                writeln!(
+                    formatter,
+                    "{}: {:?} -> {:?}",
+                    name, checkpoints.left, checkpoints.right,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    fn state() -> BeaconState<MinimalConfig> {
+        BeaconState {
+            validators: vec![Validator::default(); 4].into(),
+            balances: vec![32; 4].into(),
+            ..BeaconState::default()
+        }
+    }
+
+    #[test]
+    fn identical_states_have_no_differences() {
+        let diff = diff_states(&state(), &state());
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.root.left, diff.root.right);
+        assert_eq!(diff.validators, BTreeMap::new());
+    }
+
+    #[test]
+    fn differences_are_reported_by_index() {
+        let left = state();
+        let mut right = state();
+        right.validators[2].slashed = true;
+        right.balances[1] = 30;
+        right
+            .balances
+            .push(5)
+            .expect("state has room for more validators");
+        right.block_roots[3] = H256::repeat_byte(1);
+        right.finalized_checkpoint.epoch = 7;
+
+        let diff = diff_states(&left, &right);
+
+        assert_eq!(
+            diff.fields
+                .iter()
+                .map(|field| field.name)
+                .collect::<Vec<_>>(),
+            vec![
+                "block_roots",
+                "validators",
+                "balances",
+                "finalized_checkpoint"
+            ],
+        );
+        assert_eq!(diff.validators.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(
+            diff.balances
+                .iter()
+                .map(|(index, balances)| (*index, balances.delta()))
+                .collect::<Vec<_>>(),
+            vec![(1, -2), (4, 5)],
+        );
+        assert_eq!(
+            diff.block_roots.keys().copied().collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(diff.state_roots, BTreeMap::new());
+        assert_eq!(
+            diff.checkpoints
+                .finalized_checkpoint
+                .map(|checkpoints| checkpoints.right.epoch),
+            Some(7),
+        );
+        assert_eq!(diff.checkpoints.current_justified_checkpoint, None);
+    }
+}
@@ -0,0 +1,117 @@
+//! Running the state transition outside of the node.
+//!
+//! `transition_functions` panics on invalid blocks. The panics are caught here and turned into
+//! errors that say which block was rejected. The panic message itself is still printed by the
+//! default panic hook.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use anyhow::{ensure, Result};
+use thiserror::Error;
+use transition_functions::{blocks::block_signatures::VerifySignatures, process_slot};
+use types::{beacon_state::BeaconState, config::Config, primitives::Slot, types::BeaconBlock};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("unknown signature verification mode {0:?} (supported modes: individually, batch, proposer-only, none)")]
+    UnknownVerifySignatures(String),
+    #[error("block {index} (slot {slot}) is invalid")]
+    InvalidBlock { index: usize, slot: Slot },
+    #[error("processing slots up to {slot} failed")]
+    ProcessSlotsFailed { slot: Slot },
+    #[error("target slot {target_slot} is earlier than state slot {state_slot}")]
+    TargetSlotEarlier { target_slot: Slot, state_slot: Slot },
+}
+
+/// Parses the names used on the command line, which follow the same conventions as subcommands.
+pub fn parse_verify_signatures(string: &str) -> Result<VerifySignatures> {
+    match string {
+        "individually" => Ok(VerifySignatures::Individually),
+        "batch" => Ok(VerifySignatures::Batch),
+        "proposer-only" => Ok(VerifySignatures::ProposerOnly),
+        "none" => Ok(VerifySignatures::None),
+        _ => Err(Error::UnknownVerifySignatures(string.to_owned()).into()),
+    }
+}
+
+/// Applies `blocks` to `state` in order and then processes empty slots up to `slot` if present.
+pub fn apply<C: Config>(
+    state: &mut BeaconState<C>,
+    blocks: &[BeaconBlock<C>],
+    slot: Option<Slot>,
+    verify_signatures: VerifySignatures,
+    validate_state_root: bool,
+) -> Result<()> {
+    for (index, block) in blocks.iter().enumerate() {
+        catch_panic(state, |state| {
+            process_slot::state_transition(state, block, verify_signatures, validate_state_root);
+        })
+        .map_err(|()| Error::InvalidBlock {
+            index,
+            slot: block.slot,
+        })?;
+    }
+
+    if let Some(slot) = slot {
+        ensure!(
+            state.slot <= slot,
+            Error::TargetSlotEarlier {
+                target_slot: slot,
+                state_slot: state.slot,
+            },
+        );
+        catch_panic(state, |state| process_slot::process_slots(state, slot))
+            .map_err(|()| Error::ProcessSlotsFailed { slot })?;
+    }
+
+    Ok(())
+}
+
+// The state may be left partially updated after a panic, but it is not used after an error.
+fn catch_panic<C: Config>(
+    state: &mut BeaconState<C>,
+    process: impl FnOnce(&mut BeaconState<C>),
+) -> Result<(), ()> {
+    panic::catch_unwind(AssertUnwindSafe(|| process(state))).map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    #[test]
+    fn processes_slots_without_blocks() -> Result<()> {
+        let mut state = BeaconState::<MinimalConfig>::default();
+
+        apply(&mut state, &[], Some(3), VerifySignatures::None, true)?;
+
+        assert_eq!(state.slot, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_target_slot_in_the_past() {
+        let mut state = BeaconState::<MinimalConfig> {
+            slot: 5,
+            ..BeaconState::default()
+        };
+
+        assert!(apply(&mut state, &[], Some(3), VerifySignatures::None, true).is_err());
+    }
+
+    #[test]
+    fn reports_index_of_invalid_block() {
+        let mut state = BeaconState::<MinimalConfig>::default();
+        let block = BeaconBlock {
+            slot: 1,
+            ..BeaconBlock::default()
+        };
+
+        let error = apply(&mut state, &[block], None, VerifySignatures::None, true)
+            .expect_err("block should be rejected because its parent root is wrong");
+
+        assert_eq!(error.to_string(), "block 0 (slot 1) is invalid");
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+use thiserror::Error;
+use types::{primitives::H256, types::Eth1Data};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("eth1 block {actual} does not follow block {expected_parent}")]
+    NotConsecutive { expected_parent: u64, actual: u64 },
+}
+
+/// An eth1 block along with the state of the deposit contract after it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Eth1Block {
+    pub number: u64,
+    pub hash: H256,
+    pub timestamp: u64,
+    pub deposit_root: H256,
+    pub deposit_count: u64,
+}
+
+impl Eth1Block {
+    pub fn eth1_data(&self) -> Eth1Data {
+        Eth1Data {
+            deposit_root: self.deposit_root,
+            deposit_count: self.deposit_count,
+            block_hash: self.hash,
+        }
+    }
+}
+
+/// Consecutive eth1 blocks, starting from an arbitrary one.
+///
+/// Blocks are only ever appended. We assume that blocks beyond `ETH1_FOLLOW_DISTANCE` will not
+/// be reorganized, which is the same assumption the specification makes.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Eth1Block>,
+    numbers_by_hash: HashMap<H256, u64>,
+}
+
+impl BlockCache {
+    pub fn push(&mut self, block: Eth1Block) -> Result<()> {
+        if let Some(latest) = self.latest() {
+            ensure!(
+                latest.number + 1 == block.number,
+                Error::NotConsecutive {
+                    expected_parent: latest.number,
+                    actual: block.number,
+                },
+            );
+        }
+        self.numbers_by_hash.insert(block.hash, block.number);
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    pub fn latest(&self) -> Option<&Eth1Block> {
+        self.blocks.last()
+    }
+
+    pub fn get(&self, number: u64) -> Option<&Eth1Block> {
+        let first = self.blocks.first()?.number;
+        let offset = number.checked_sub(first)?;
+        self.blocks.get(offset as usize)
+    }
+
+    pub fn number_of(&self, hash: H256) -> Option<u64> {
+        self.numbers_by_hash.get(&hash).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
@@ -0,0 +1,223 @@
+use core::cmp::Ordering;
+
+use anyhow::{bail, ensure, Result};
+use error_utils::DebugAsError;
+use helper_functions::crypto;
+use merkle_proof::DepositTree;
+use ssz_types::FixedVector;
+use thiserror::Error;
+use types::{
+    primitives::H256,
+    types::{Deposit, DepositData},
+};
+
+use crate::deposit_log::DepositLog;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("deposit log out of order (expected index: {expected}, actual index: {actual})")]
+    OutOfOrder { expected: u64, actual: u64 },
+    #[error("conflicting deposit log with index {index}")]
+    Conflicting { index: u64 },
+    #[error("deposits {start}..{end} requested from a tree of {count} (known deposits: {known})")]
+    RangeNotAvailable {
+        start: u64,
+        end: u64,
+        count: u64,
+        known: u64,
+    },
+}
+
+/// All deposits made to the deposit contract, in the order they were made.
+#[derive(Default)]
+pub struct DepositCache {
+    logs: Vec<DepositLog>,
+    tree: DepositTree,
+}
+
+impl DepositCache {
+    /// Logs must be inserted in the order of their indices. Logs that have already been inserted
+    /// are ignored, which makes it safe to query overlapping ranges of eth1 blocks.
+    pub fn insert(&mut self, log: DepositLog) -> Result<()> {
+        let expected = self.len();
+        match log.index.cmp(&expected) {
+            Ordering::Less => {
+                ensure!(
+                    self.logs[log.index as usize] == log,
+                    Error::Conflicting { index: log.index },
+                );
+            }
+            Ordering::Equal => {
+                self.tree
+                    .push(crypto::hash_tree_root(&log.data))
+                    .map_err(DebugAsError::new)?;
+                self.logs.push(log);
+            }
+            Ordering::Greater => bail!(Error::OutOfOrder {
+                expected,
+                actual: log.index,
+            }),
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> u64 {
+        self.logs.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty()
+    }
+
+    /// Returns the number of deposits made in blocks up to and including `block_number`.
+    pub fn count_at_block(&self, block_number: u64) -> u64 {
+        let position = self
+            .logs
+            .binary_search_by(|log| {
+                if log.block_number <= block_number {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|position| position);
+        position as u64
+    }
+
+    /// Returns the root of the deposit tree containing the first `count` deposits, as returned by
+    /// `get_deposit_root` in the deposit contract.
+    pub fn root(&self, count: u64) -> Result<H256> {
+        ensure!(
+            count <= self.len(),
+            Error::RangeNotAvailable {
+                start: 0,
+                end: count,
+                count,
+                known: self.len(),
+            },
+        );
+        Ok(self.tree.root_at(count).map_err(DebugAsError::new)?)
+    }
+
+    /// Returns deposits `start..end` with proofs against the tree containing the first `count`
+    /// deposits.
+    pub fn deposits(&self, start: u64, end: u64, count: u64) -> Result<Vec<Deposit>> {
+        ensure!(
+            start <= end && end <= count && count <= self.len(),
+            Error::RangeNotAvailable {
+                start,
+                end,
+                count,
+                known: self.len(),
+            },
+        );
+
+        (start..end)
+            .map(|index| -> Result<_> {
+                let proof = self.tree.proof(index, count).map_err(DebugAsError::new)?;
+                Ok(Deposit {
+                    proof: FixedVector::from(proof),
+                    data: self.logs[index as usize].data.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn data(&self) -> impl Iterator<Item = &DepositData> {
+        self.logs.iter().map(|log| &log.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use helper_functions::predicates;
+    use ssz_types::VariableList;
+    use tree_hash::TreeHash as _;
+    use types::consts::DEPOSIT_CONTRACT_TREE_DEPTH;
+
+    use crate::mock_rpc;
+
+    use super::*;
+
+    // `2 ** DEPOSIT_CONTRACT_TREE_DEPTH`.
+    type DepositContractLimit = typenum::U4294967296;
+
+    fn cache_with_deposits(count: u64) -> Result<DepositCache> {
+        let mut cache = DepositCache::default();
+        for index in 0..count {
+            cache.insert(DepositLog {
+                block_number: index / 2,
+                index,
+                data: mock_rpc::deposit_data(index),
+            })?;
+        }
+        Ok(cache)
+    }
+
+    #[test]
+    fn root_matches_hash_tree_root_of_deposit_list() -> Result<()> {
+        let cache = cache_with_deposits(7)?;
+        for count in 0..=cache.len() {
+            let list = VariableList::<DepositData, DepositContractLimit>::from(
+                cache
+                    .data()
+                    .take(count as usize)
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(
+                cache.root(count)?,
+                H256::from_slice(list.tree_hash_root().as_slice()),
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn proofs_are_valid_against_every_later_root() -> Result<()> {
+        let cache = cache_with_deposits(6)?;
+        for count in 1..=cache.len() {
+            let root = cache.root(count)?;
+            for (index, deposit) in (0..).zip(cache.deposits(0, count, count)?) {
+                assert!(predicates::is_valid_merkle_branch(
+                    &crypto::hash_tree_root(&deposit.data),
+                    &deposit.proof,
+                    DEPOSIT_CONTRACT_TREE_DEPTH + 1,
+                    index,
+                    &root,
+                )
+                .expect("proof should have the right length"));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn count_at_block_includes_deposits_in_that_block() -> Result<()> {
+        let cache = cache_with_deposits(5)?;
+        assert_eq!(cache.count_at_block(0), 2);
+        assert_eq!(cache.count_at_block(1), 4);
+        assert_eq!(cache.count_at_block(2), 5);
+        assert_eq!(cache.count_at_block(100), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn insert_ignores_duplicates_and_rejects_gaps() -> Result<()> {
+        let mut cache = cache_with_deposits(2)?;
+        cache.insert(DepositLog {
+            block_number: 0,
+            index: 1,
+            data: mock_rpc::deposit_data(1),
+        })?;
+        assert_eq!(cache.len(), 2);
+        assert!(cache
+            .insert(DepositLog {
+                block_number: 5,
+                index: 3,
+                data: mock_rpc::deposit_data(3),
+            })
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{ensure, Result};
+use bls::{PublicKeyBytes, SignatureBytes};
+use error_utils::DebugAsError;
+use thiserror::Error;
+use types::{primitives::H256, types::DepositData};
+
+/// `keccak256("DepositEvent(bytes,bytes,bytes,bytes,bytes)")`.
+///
+/// See the [deposit contract].
+///
+/// [deposit contract]: https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/deposit_contract/contracts/validator_registration.v.py#L9-L15
+pub const DEPOSIT_EVENT_TOPIC: &str =
+    "0x649bbc62d0e31342afea4e5cd82d4049e7e1ee912fc0889aa790803be39038c5";
+
+const WORD_LENGTH: usize = 32;
+const FIELD_COUNT: usize = 5;
+
+const PUBKEY_LENGTH: usize = 48;
+const WITHDRAWAL_CREDENTIALS_LENGTH: usize = 32;
+const AMOUNT_LENGTH: usize = 8;
+const SIGNATURE_LENGTH: usize = 96;
+const INDEX_LENGTH: usize = 8;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("log data is too short ({length} bytes)")]
+    TooShort { length: usize },
+    #[error("field {field} has length {actual} (expected {expected})")]
+    UnexpectedFieldLength {
+        field: usize,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// A deposit made to the deposit contract along with its position in the eth1 chain.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DepositLog {
+    pub block_number: u64,
+    pub index: u64,
+    pub data: DepositData,
+}
+
+impl DepositLog {
+    /// Decodes the data of a `DepositEvent` log.
+    ///
+    /// The log data is the [ABI encoding] of 5 `bytes` values. Integers are stored as little
+    /// endian bytes, as in SSZ.
+    ///
+    /// [ABI encoding]: https://solidity.readthedocs.io/en/v0.5.15/abi-spec.html#use-of-dynamic-types
+    pub fn from_log_data(block_number: u64, data: &[u8]) -> Result<Self> {
+        let pubkey = abi_field(data, 0, PUBKEY_LENGTH)?;
+        let withdrawal_credentials = abi_field(data, 1, WITHDRAWAL_CREDENTIALS_LENGTH)?;
+        let amount = abi_field(data, 2, AMOUNT_LENGTH)?;
+        let signature = abi_field(data, 3, SIGNATURE_LENGTH)?;
+        let index = abi_field(data, 4, INDEX_LENGTH)?;
+
+        let data = DepositData {
+            pubkey: PublicKeyBytes::from_bytes(pubkey).map_err(DebugAsError::new)?,
+            withdrawal_credentials: H256::from_slice(withdrawal_credentials),
+            amount: u64_from_le_bytes(amount),
+            signature: SignatureBytes::from_bytes(signature).map_err(DebugAsError::new)?,
+        };
+
+        Ok(Self {
+            block_number,
+            index: u64_from_le_bytes(index),
+            data,
+        })
+    }
+}
+
+fn abi_field(data: &[u8], field: usize, expected: usize) -> Result<&[u8]> {
+    ensure!(
+        FIELD_COUNT * WORD_LENGTH <= data.len(),
+        Error::TooShort { length: data.len() },
+    );
+    let offset = abi_word(data, field * WORD_LENGTH)?;
+    let actual = abi_word(data, offset)?;
+    ensure!(
+        actual == expected,
+        Error::UnexpectedFieldLength {
+            field,
+            expected,
+            actual,
+        },
+    );
+    slice(data, offset + WORD_LENGTH, actual)
+}
+
+// Offsets and lengths are encoded as big endian 256 bit integers. Values that do not fit in the
+// last 8 bytes could never point to anything inside `data`, so we treat them as out of bounds.
+fn abi_word(data: &[u8], position: usize) -> Result<usize> {
+    let word = slice(data, position, WORD_LENGTH)?;
+    let (high, low) = word.split_at(WORD_LENGTH - 8);
+    ensure!(
+        high.iter().all(|byte| *byte == 0),
+        Error::TooShort { length: data.len() },
+    );
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(low);
+    Ok(u64::from_be_bytes(bytes) as usize)
+}
+
+fn slice(data: &[u8], start: usize, length: usize) -> Result<&[u8]> {
+    start
+        .checked_add(length)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| Error::TooShort { length: data.len() }.into())
+}
+
+fn u64_from_le_bytes(slice: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(slice);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock_rpc;
+
+    use super::*;
+
+    #[test]
+    fn decodes_encoded_deposit_event() -> Result<()> {
+        let data = mock_rpc::deposit_data(3);
+        let encoded = mock_rpc::encode_deposit_log_data(&data, 3);
+        let log = DepositLog::from_log_data(10, encoded.as_slice())?;
+        assert_eq!(
+            log,
+            DepositLog {
+                block_number: 10,
+                index: 3,
+                data,
+            },
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_truncated_deposit_event() {
+        let encoded = mock_rpc::encode_deposit_log_data(&mock_rpc::deposit_data(0), 0);
+        let truncated = &encoded[..encoded.len() - WORD_LENGTH];
+        assert!(DepositLog::from_log_data(0, truncated).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+use core::{cmp::Reverse, marker::PhantomData, time::Duration};
+use std::thread;
+
+use anyhow::Result;
+use ethereum_types::Address;
+use helper_functions::math;
+use log::{info, warn};
+use serde::Deserialize;
+use typenum::Unsigned as _;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    types::{Deposit, Eth1Data},
+};
+
+use crate::{
+    block_cache::{BlockCache, Eth1Block},
+    deposit_cache::DepositCache,
+    rpc::Client,
+};
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Eth1Config {
+    /// The URL of an eth1 node that serves the JSON-RPC API over HTTP.
+    pub endpoint: String,
+    pub deposit_contract_address: Address,
+    /// Logs are not requested for blocks before this one.
+    #[serde(default)]
+    pub deposit_contract_deploy_block: u64,
+    /// Some eth1 nodes limit the number of logs that can be returned by a single request.
+    #[serde(default = "default_blocks_per_log_query")]
+    pub blocks_per_log_query: u64,
+    #[serde(default = "default_update_interval_seconds")]
+    pub update_interval_seconds: u64,
+}
+
+fn default_blocks_per_log_query() -> u64 {
+    1000
+}
+
+fn default_update_interval_seconds() -> u64 {
+    14
+}
+
+/// Follows the deposit contract on the eth1 chain.
+///
+/// Only blocks at least `ETH1_FOLLOW_DISTANCE` behind the eth1 head are processed. Blocks that
+/// are too old to be voted for are not requested when the follower starts, but all deposits are.
+pub struct Eth1Follower<C: Config> {
+    config: Eth1Config,
+    client: Client,
+    deposits: DepositCache,
+    blocks: BlockCache,
+    next_log_block: u64,
+    eth1_head: Option<u64>,
+    // Numbers and timestamps of the eth1 heads seen by `update`, in the order they were seen.
+    heads: Vec<(u64, u64)>,
+    phantom: PhantomData<C>,
+}
+
+impl<C: Config> Eth1Follower<C> {
+    pub fn new(config: Eth1Config) -> Self {
+        let client = Client::new(config.endpoint.clone());
+        let next_log_block = config.deposit_contract_deploy_block;
+        Self {
+            config,
+            client,
+            deposits: DepositCache::default(),
+            blocks: BlockCache::default(),
+            next_log_block,
+            eth1_head: None,
+            heads: vec![],
+            phantom: PhantomData,
+        }
+    }
+
+    /// Calls [`Eth1Follower::update`] repeatedly. Errors are logged and otherwise ignored.
+    pub fn run(mut self) {
+        let interval = Duration::from_secs(self.config.update_interval_seconds);
+        loop {
+            match self.update() {
+                Ok(()) => info!(
+                    "eth1 follower updated (eth1 head: {:?}, latest block: {:?}, deposits: {})",
+                    self.eth1_head,
+                    self.blocks.latest().map(|block| block.number),
+                    self.deposits.len(),
+                ),
+                Err(error) => warn!("eth1 follower failed to update: {}", error),
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Requests new deposit logs and blocks from the eth1 node.
+    pub fn update(&mut self) -> Result<()> {
+        let eth1_head = self.client.block_number()?;
+        let follow_distance = C::eth1_follow_distance();
+
+        let last_block = match eth1_head.checked_sub(follow_distance) {
+            Some(number) if self.config.deposit_contract_deploy_block <= number => number,
+            _ => return self.set_eth1_head(eth1_head),
+        };
+
+        while self.next_log_block <= last_block {
+            let to = last_block.min(self.next_log_block + self.config.blocks_per_log_query - 1);
+            let logs = self.client.deposit_logs(
+                self.config.deposit_contract_address,
+                self.next_log_block,
+                to,
+            )?;
+            for log in logs {
+                self.deposits.insert(log)?;
+            }
+            self.next_log_block = to + 1;
+        }
+
+        let first_block = match self.blocks.latest() {
+            Some(block) => block.number + 1,
+            None => eth1_head
+                .saturating_sub(2 * follow_distance)
+                .max(self.config.deposit_contract_deploy_block),
+        };
+
+        for number in first_block..=last_block {
+            let header = self.client.block_by_number(number)?;
+            let deposit_count = self.deposits.count_at_block(number);
+            self.blocks.push(Eth1Block {
+                number,
+                hash: header.hash,
+                timestamp: header.timestamp,
+                deposit_root: self.deposits.root(deposit_count)?,
+                deposit_count,
+            })?;
+        }
+
+        self.set_eth1_head(eth1_head)
+    }
+
+    fn set_eth1_head(&mut self, eth1_head: u64) -> Result<()> {
+        if self.heads.last().map(|(number, _)| *number) != Some(eth1_head) {
+            let timestamp = self.client.block_by_number(eth1_head)?.timestamp;
+            self.heads.push((eth1_head, timestamp));
+        }
+        self.eth1_head = Some(eth1_head);
+        Ok(())
+    }
+
+    // The eth1 head at the start of the voting period of `state`, as far as `self` knows.
+    // Slot `C::genesis_slot() + 1` starts at `genesis_time`.
+    fn voting_period_head(&self, state: &BeaconState<C>) -> Option<u64> {
+        let seconds_per_slot = C::SecondsPerSlot::to_u64();
+        let period_start_slot = state.slot - state.slot % C::SlotsPerEth1VotingPeriod::to_u64();
+        let period_start_time = (state.genesis_time
+            + period_start_slot.saturating_sub(C::genesis_slot()) * seconds_per_slot)
+            .saturating_sub(seconds_per_slot);
+
+        self.heads
+            .iter()
+            .rev()
+            .find(|(_, timestamp)| *timestamp <= period_start_time)
+            .or_else(|| self.heads.first())
+            .map(|(number, _)| *number)
+    }
+
+    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/validator/0_beacon-chain-validator.md#eth1-data>
+    ///
+    /// Distances are measured from the eth1 head at the start of the voting period of `state`, as
+    /// in `get_eth1_data`. The head used is the latest one seen by [`Eth1Follower::update`] with a
+    /// timestamp no later than the start of the period, so nodes that follow the same eth1 chain
+    /// vote the same way regardless of when they call this. Periods that started before the first
+    /// update are treated as if they started at it.
+    ///
+    /// Blocks that are not in the cache are treated as if they did not exist. This only matters
+    /// shortly after the follower is started. If not even the block at `ETH1_FOLLOW_DISTANCE` is
+    /// available, `state.eth1_data` is returned, which amounts to voting for no change.
+    ///
+    /// Votes are compared by distance from the eth1 head rather than by their position in
+    /// `all_eth1_data`. The two are equivalent, but the former does not fail when a vote in
+    /// `new_eth1_data` is missing from `all_eth1_data`.
+    pub fn eth1_data_vote(&self, state: &BeaconState<C>) -> Eth1Data {
+        let follow_distance = C::eth1_follow_distance();
+
+        let eth1_head = match self.voting_period_head(state) {
+            Some(eth1_head) => eth1_head,
+            None => return state.eth1_data.clone(),
+        };
+
+        let distance_of = |eth1_data: &Eth1Data| {
+            let number = self.blocks.number_of(eth1_data.block_hash)?;
+            let block = self.blocks.get(number)?;
+            if block.eth1_data() == *eth1_data {
+                eth1_head.checked_sub(number)
+            } else {
+                None
+            }
+        };
+
+        let default_vote = match eth1_head
+            .checked_sub(follow_distance)
+            .and_then(|number| self.blocks.get(number))
+        {
+            Some(block) => block.eth1_data(),
+            None => return state.eth1_data.clone(),
+        };
+
+        let previous_eth1_distance = distance_of(&state.eth1_data).unwrap_or(u64::max_value());
+        let slots_per_period = C::SlotsPerEth1VotingPeriod::to_u64();
+        let tail_start = math::integer_squareroot(slots_per_period);
+
+        // Like the specification, this uses the position of a vote in `state.eth1_data_votes` as
+        // its slot in the voting period.
+        let valid_votes = state
+            .eth1_data_votes
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, vote)| {
+                let distance = distance_of(vote)?;
+                let period_tail = slot as u64 % slots_per_period >= tail_start;
+                let in_new_eth1_data = distance < 2 * follow_distance;
+                let in_all_eth1_data = distance < previous_eth1_distance;
+                if follow_distance <= distance
+                    && (in_new_eth1_data || (period_tail && in_all_eth1_data))
+                {
+                    Some((vote, distance))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        valid_votes
+            .iter()
+            .max_by_key(|(vote, distance)| {
+                let count = valid_votes
+                    .iter()
+                    .filter(|(other, _)| other == vote)
+                    .count();
+                (count, Reverse(*distance))
+            })
+            .map(|(vote, _)| (*vote).clone())
+            .unwrap_or(default_vote)
+    }
+
+    /// Returns the deposits that a block built on top of `state` must contain.
+    ///
+    /// See `process_operations` in the specification:
+    /// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_beacon-chain.md#operations>
+    pub fn deposits_for_block(&self, state: &BeaconState<C>) -> Result<Vec<Deposit>> {
+        let count = state.eth1_data.deposit_count;
+        let start = state.eth1_deposit_index;
+        let end = count.min(start + C::MaxDeposits::to_u64());
+        self.deposits.deposits(start, end, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssz_types::VariableList;
+    use types::config::MinimalConfig;
+
+    use crate::mock_rpc::{self, MockEth1};
+
+    use super::*;
+
+    const CHAIN_LENGTH: u64 = 40;
+
+    fn follower_for(mock: &MockEth1) -> Eth1Follower<MinimalConfig> {
+        Eth1Follower::new(Eth1Config {
+            endpoint: mock.endpoint(),
+            deposit_contract_address: mock_rpc::deposit_contract_address(),
+            deposit_contract_deploy_block: 2,
+            blocks_per_log_query: 5,
+            update_interval_seconds: 0,
+        })
+    }
+
+    fn mock_with_deposits() -> MockEth1 {
+        let mock = MockEth1::start();
+        for number in 0..CHAIN_LENGTH {
+            let deposit_count = match number {
+                3 => 2,
+                10 | 20 | 30 => 1,
+                _ => 0,
+            };
+            mock.push_block(deposit_count);
+        }
+        mock
+    }
+
+    #[test]
+    fn update_follows_deposit_contract() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let follow_distance = MinimalConfig::eth1_follow_distance();
+        let last_block = CHAIN_LENGTH - 1 - follow_distance;
+
+        assert_eq!(follower.eth1_head, Some(CHAIN_LENGTH - 1));
+        assert_eq!(
+            follower.blocks.latest().map(|block| block.number),
+            Some(last_block)
+        );
+        // The deposit in block 30 is beyond the follow distance.
+        assert_eq!(follower.deposits.len(), 4);
+
+        let block = follower
+            .blocks
+            .get(last_block)
+            .expect("block should be cached");
+        assert_eq!(block.hash, mock.block_hash(last_block));
+        assert_eq!(block.deposit_count, 4);
+        assert_eq!(block.deposit_root, follower.deposits.root(4)?);
+
+        mock.push_blocks(10);
+        follower.update()?;
+        assert_eq!(follower.deposits.len(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn eth1_data_vote_defaults_to_block_at_follow_distance() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let state = BeaconState::<MinimalConfig>::default();
+        let expected_block = CHAIN_LENGTH - 1 - MinimalConfig::eth1_follow_distance();
+
+        assert_eq!(
+            follower.eth1_data_vote(&state).block_hash,
+            mock.block_hash(expected_block),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn eth1_data_vote_follows_majority_of_valid_votes() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let eth1_data_at = |number| {
+            follower
+                .blocks
+                .get(number)
+                .expect("block should be cached")
+                .eth1_data()
+        };
+
+        let popular = eth1_data_at(15);
+        let unpopular = eth1_data_at(18);
+        let unknown = Eth1Data::default();
+
+        let state = BeaconState::<MinimalConfig> {
+            eth1_data_votes: VariableList::from(vec![
+                unknown.clone(),
+                unknown.clone(),
+                unknown,
+                popular.clone(),
+                unpopular,
+                popular.clone(),
+            ]),
+            ..BeaconState::default()
+        };
+
+        assert_eq!(follower.eth1_data_vote(&state), popular);
+        Ok(())
+    }
+
+    // Block 7 is exactly `2 * ETH1_FOLLOW_DISTANCE` blocks behind the head. Votes for it are only
+    // valid in the tail of the voting period. Block 15 is a valid vote in the whole period.
+    // The votes are preceded by `skipped_votes` votes for unknown blocks.
+    fn state_with_old_and_new_votes(
+        follower: &Eth1Follower<MinimalConfig>,
+        skipped_votes: usize,
+    ) -> (BeaconState<MinimalConfig>, Eth1Data, Eth1Data) {
+        let eth1_data_at = |number| {
+            follower
+                .blocks
+                .get(number)
+                .expect("block should be cached")
+                .eth1_data()
+        };
+
+        let old = eth1_data_at(7);
+        let new = eth1_data_at(15);
+
+        let mut votes = vec![Eth1Data::default(); skipped_votes];
+        votes.extend(vec![old.clone(), old.clone(), new.clone()]);
+
+        let state = BeaconState {
+            slot: <MinimalConfig as Config>::SlotsPerEth1VotingPeriod::to_u64() - 1,
+            eth1_data_votes: VariableList::from(votes),
+            ..BeaconState::default()
+        };
+
+        (state, old, new)
+    }
+
+    #[test]
+    fn eth1_data_vote_ignores_old_votes_outside_period_tail() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let (state, _, new) = state_with_old_and_new_votes(&follower, 0);
+
+        assert_eq!(follower.eth1_data_vote(&state), new);
+        Ok(())
+    }
+
+    #[test]
+    fn eth1_data_vote_accepts_old_votes_in_period_tail() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let slots_per_period = <MinimalConfig as Config>::SlotsPerEth1VotingPeriod::to_u64();
+        let tail_start = math::integer_squareroot(slots_per_period) as usize;
+        let (state, old, _) = state_with_old_and_new_votes(&follower, tail_start);
+
+        assert_eq!(follower.eth1_data_vote(&state), old);
+        Ok(())
+    }
+
+    #[test]
+    fn eth1_data_vote_is_relative_to_head_at_start_of_voting_period() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+        mock.push_blocks(10);
+        follower.update()?;
+
+        // Blocks in the mock chain are 14 seconds apart. The slot of the default state starts 6
+        // seconds before genesis, so the head at the start of the voting period is block 39 in the
+        // first state and block 49 in the second.
+        let follow_distance = MinimalConfig::eth1_follow_distance();
+        let earlier_state = BeaconState::<MinimalConfig> {
+            genesis_time: (CHAIN_LENGTH + 1) * 14,
+            ..BeaconState::default()
+        };
+        let later_state = BeaconState::<MinimalConfig> {
+            genesis_time: (CHAIN_LENGTH + 11) * 14,
+            ..BeaconState::default()
+        };
+
+        assert_eq!(
+            follower.eth1_data_vote(&earlier_state).block_hash,
+            mock.block_hash(CHAIN_LENGTH - 1 - follow_distance),
+        );
+        assert_eq!(
+            follower.eth1_data_vote(&later_state).block_hash,
+            mock.block_hash(CHAIN_LENGTH + 9 - follow_distance),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deposits_for_block_have_valid_proofs() -> Result<()> {
+        let mock = mock_with_deposits();
+        let mut follower = follower_for(&mock);
+        follower.update()?;
+
+        let mut state = BeaconState::<MinimalConfig>::default();
+        state.eth1_data = follower.eth1_data_vote(&state);
+        state.eth1_deposit_index = 1;
+
+        let deposits = follower.deposits_for_block(&state)?;
+        assert_eq!(deposits.len(), 3);
+
+        for (index, deposit) in (state.eth1_deposit_index..).zip(deposits) {
+            assert!(helper_functions::predicates::is_valid_merkle_branch(
+                &helper_functions::crypto::hash_tree_root(&deposit.data),
+                &deposit.proof,
+                types::consts::DEPOSIT_CONTRACT_TREE_DEPTH + 1,
+                index,
+                &state.eth1_data.deposit_root,
+            )
+            .expect("proof should have the right length"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+//! Following the deposit contract on the eth1 chain.
+//!
+//! Proposers need this to fill in `BeaconBlockBody.eth1_data` and `BeaconBlockBody.deposits`.
+//! See the [validator guide] for how eth1 data votes are chosen.
+//!
+//! [validator guide]: https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/validator/0_beacon-chain-validator.md#eth1-data
+
+pub use crate::{
+    block_cache::Eth1Block,
+    deposit_log::DepositLog,
+    follower::{Eth1Config, Eth1Follower},
+};
+
+mod block_cache;
+mod deposit_cache;
+mod deposit_log;
+mod follower;
+#[cfg(test)]
+mod mock_rpc;
+mod rpc;
@@ -0,0 +1,225 @@
+//! A stand-in for an eth1 node used in tests.
+//!
+//! The server implements just enough of HTTP and the JSON-RPC API to serve the requests made by
+//! `rpc::Client`. Every connection is closed after a single request.
+
+use std::{
+    io::{BufRead as _, BufReader, Read as _, Result as IoResult, Write as _},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use bls::{PublicKeyBytes, SignatureBytes};
+use ethereum_types::Address;
+use serde_json::{json, Value};
+use types::{primitives::H256, types::DepositData};
+
+use crate::deposit_log::DEPOSIT_EVENT_TOPIC;
+
+#[derive(Default)]
+struct Chain {
+    block_hashes: Vec<H256>,
+    deposit_logs: Vec<(u64, Vec<u8>)>,
+    deposit_count: u64,
+}
+
+pub struct MockEth1 {
+    endpoint: String,
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl MockEth1 {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock eth1 node");
+        let endpoint = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .expect("listener should have an address"),
+        );
+        let chain = Arc::new(Mutex::new(Chain::default()));
+        let server_chain = chain.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.expect("failed to accept connection");
+                handle_connection(stream, &server_chain).expect("failed to handle request");
+            }
+        });
+
+        Self { endpoint, chain }
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.clone()
+    }
+
+    /// Adds a block containing `deposit_count` deposits to the chain.
+    pub fn push_block(&self, deposit_count: u64) {
+        let mut chain = self.chain.lock().expect("mock eth1 node panicked");
+        let number = chain.block_hashes.len() as u64;
+        chain.block_hashes.push(H256::from_low_u64_be(number + 1));
+        for _ in 0..deposit_count {
+            let index = chain.deposit_count;
+            let data = encode_deposit_log_data(&deposit_data(index), index);
+            chain.deposit_logs.push((number, data));
+            chain.deposit_count += 1;
+        }
+    }
+
+    pub fn push_blocks(&self, count: u64) {
+        for _ in 0..count {
+            self.push_block(0);
+        }
+    }
+
+    pub fn block_hash(&self, number: u64) -> H256 {
+        self.chain
+            .lock()
+            .expect("mock eth1 node panicked")
+            .block_hashes[number as usize]
+    }
+}
+
+pub fn deposit_contract_address() -> Address {
+    Address::repeat_byte(0xDE)
+}
+
+pub fn deposit_data(index: u64) -> DepositData {
+    DepositData {
+        pubkey: PublicKeyBytes::from_bytes(&[index as u8; 48]).expect("length is correct"),
+        withdrawal_credentials: H256::from_low_u64_be(index),
+        amount: 32_000_000_000,
+        signature: SignatureBytes::from_bytes(&[0; 96]).expect("length is correct"),
+    }
+}
+
+pub fn encode_deposit_log_data(data: &DepositData, index: u64) -> Vec<u8> {
+    let fields = [
+        data.pubkey.as_bytes(),
+        data.withdrawal_credentials.as_bytes().to_vec(),
+        data.amount.to_le_bytes().to_vec(),
+        data.signature.as_bytes(),
+        index.to_le_bytes().to_vec(),
+    ];
+
+    let mut head = vec![];
+    let mut tail = vec![];
+
+    for field in &fields {
+        head.extend_from_slice(&abi_word(fields.len() * 32 + tail.len()));
+        tail.extend_from_slice(&abi_word(field.len()));
+        tail.extend_from_slice(field);
+        tail.resize((tail.len() + 31) / 32 * 32, 0);
+    }
+
+    head.extend(tail);
+    head
+}
+
+fn abi_word(value: usize) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+fn handle_connection(mut stream: TcpStream, chain: &Mutex<Chain>) -> IoResult<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut content_length = 0;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end().to_ascii_lowercase();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("content-length:") {
+            content_length = line["content-length:".len()..]
+                .trim()
+                .parse()
+                .expect("Content-Length should be a number");
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(body.as_mut_slice())?;
+
+    let request: Value = serde_json::from_slice(body.as_slice()).expect("request should be JSON");
+    let response = respond(&request, &chain.lock().expect("mock eth1 node panicked"));
+    let response = serde_json::to_vec(&response).expect("response should be serializable");
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n",
+        response.len(),
+    )?;
+    stream.write_all(response.as_slice())?;
+    stream.flush()
+}
+
+fn respond(request: &Value, chain: &Chain) -> Value {
+    let params = &request["params"];
+    let result = match request["method"].as_str() {
+        Some("eth_blockNumber") => json!(quantity(chain.block_hashes.len() as u64 - 1)),
+        Some("eth_getBlockByNumber") => {
+            let number = parse_quantity(&params[0]);
+            match chain.block_hashes.get(number as usize) {
+                Some(hash) => json!({
+                    "hash": hash,
+                    "number": quantity(number),
+                    "timestamp": quantity(number * 14),
+                }),
+                None => Value::Null,
+            }
+        }
+        Some("eth_getLogs") => {
+            let filter = &params[0];
+            let from = parse_quantity(&filter["fromBlock"]);
+            let to = parse_quantity(&filter["toBlock"]);
+            assert_eq!(filter["address"], json!(deposit_contract_address()));
+            assert_eq!(filter["topics"], json!([DEPOSIT_EVENT_TOPIC]));
+            let logs = chain
+                .deposit_logs
+                .iter()
+                .filter(|(number, _)| from <= *number && *number <= to)
+                .map(|(number, data)| {
+                    json!({
+                        "blockNumber": quantity(*number),
+                        "data": format!("0x{}", hex::encode(data)),
+                        "removed": false,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!(logs)
+        }
+        _ => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": {"code": -32601, "message": "method not found"},
+            })
+        }
+    };
+    json!({
+        "jsonrpc": "2.0",
+        "id": request["id"],
+        "result": result,
+    })
+}
+
+fn quantity(value: u64) -> String {
+    format!("{:#x}", value)
+}
+
+fn parse_quantity(value: &Value) -> u64 {
+    let string = value.as_str().expect("quantity should be a string");
+    u64::from_str_radix(&string[2..], 16).expect("quantity should be hexadecimal")
+}
@@ -0,0 +1,189 @@
+//! A minimal client for the [Ethereum JSON-RPC API].
+//!
+//! Only the methods needed to follow the deposit contract are implemented.
+//!
+//! [Ethereum JSON-RPC API]: https://github.com/ethereum/wiki/wiki/JSON-RPC
+
+use anyhow::{ensure, Result};
+use ethereum_types::Address;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use types::primitives::H256;
+
+use crate::deposit_log::{DepositLog, DEPOSIT_EVENT_TOPIC};
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("JSON-RPC error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("eth1 block {number} not found")]
+    MissingBlock { number: u64 },
+    #[error("invalid quantity: {quantity:?}")]
+    InvalidQuantity { quantity: String },
+    #[error("invalid data: {data:?}")]
+    InvalidData { data: String },
+}
+
+#[derive(Serialize)]
+struct Request {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: Value,
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    result: Value,
+    error: Option<ErrorObject>,
+}
+
+#[derive(Deserialize)]
+struct ErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RawBlock {
+    hash: H256,
+    number: String,
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawLog {
+    block_number: String,
+    data: String,
+    #[serde(default)]
+    removed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: H256,
+    pub timestamp: u64,
+}
+
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// <https://github.com/ethereum/wiki/wiki/JSON-RPC#eth_blocknumber>
+    pub fn block_number(&self) -> Result<u64> {
+        let number: String = self.call("eth_blockNumber", json!([]))?;
+        parse_quantity(number.as_str())
+    }
+
+    /// <https://github.com/ethereum/wiki/wiki/JSON-RPC#eth_getblockbynumber>
+    pub fn block_by_number(&self, number: u64) -> Result<BlockHeader> {
+        let raw: Option<RawBlock> = self.call(
+            "eth_getBlockByNumber",
+            json!([format_quantity(number), false]),
+        )?;
+        let raw = raw.ok_or(Error::MissingBlock { number })?;
+        Ok(BlockHeader {
+            number: parse_quantity(raw.number.as_str())?,
+            hash: raw.hash,
+            timestamp: parse_quantity(raw.timestamp.as_str())?,
+        })
+    }
+
+    /// Returns `DepositEvent` logs emitted by `address` in blocks `from..=to`.
+    ///
+    /// <https://github.com/ethereum/wiki/wiki/JSON-RPC#eth_getlogs>
+    pub fn deposit_logs(&self, address: Address, from: u64, to: u64) -> Result<Vec<DepositLog>> {
+        let raw_logs: Vec<RawLog> = self.call(
+            "eth_getLogs",
+            json!([{
+                "address": address,
+                "fromBlock": format_quantity(from),
+                "toBlock": format_quantity(to),
+                "topics": [DEPOSIT_EVENT_TOPIC],
+            }]),
+        )?;
+        raw_logs
+            .into_iter()
+            .filter(|raw| !raw.removed)
+            .map(|raw| {
+                let block_number = parse_quantity(raw.block_number.as_str())?;
+                let data = parse_data(raw.data.as_str())?;
+                DepositLog::from_log_data(block_number, data.as_slice())
+            })
+            .collect()
+    }
+
+    fn call<T: DeserializeOwned>(&self, method: &'static str, params: Value) -> Result<T> {
+        let request = Request {
+            jsonrpc: "2.0",
+            method,
+            params,
+            id: 1,
+        };
+        let response: Response = self
+            .http
+            .post(self.endpoint.as_str())
+            .json(&request)
+            .send()?
+            .error_for_status()?
+            .json()?;
+        if let Some(ErrorObject { code, message }) = response.error {
+            return Err(Error::Rpc { code, message }.into());
+        }
+        Ok(serde_json::from_value(response.result)?)
+    }
+}
+
+fn format_quantity(quantity: u64) -> String {
+    format!("{:#x}", quantity)
+}
+
+fn parse_quantity(quantity: &str) -> Result<u64> {
+    let invalid = || Error::InvalidQuantity {
+        quantity: quantity.to_owned(),
+    };
+    ensure!(quantity.starts_with("0x"), invalid());
+    u64::from_str_radix(&quantity[2..], 16).map_err(|_| invalid().into())
+}
+
+fn parse_data(data: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::InvalidData {
+        data: data.to_owned(),
+    };
+    ensure!(data.starts_with("0x"), invalid());
+    hex::decode(&data[2..]).map_err(|_| invalid().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantities_survive_round_trip() -> Result<()> {
+        for quantity in &[0, 1, 0x400, u64::max_value()] {
+            assert_eq!(
+                parse_quantity(format_quantity(*quantity).as_str())?,
+                *quantity,
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_quantity_requires_prefix() {
+        assert!(parse_quantity("400").is_err());
+    }
+}
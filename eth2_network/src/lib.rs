@@ -9,6 +9,9 @@
 //!
 //! [other types of objects]: https://github.com/ethereum/eth2.0-specs/blob/1f3a5b156f7a0e7616f7c8bc31e27fa4da392139/specs/networking/p2p-interface.md#message
 
+use core::hash::{Hash, Hasher as _};
+use std::collections::hash_map::DefaultHasher;
+
 use anyhow::Result;
 use types::{
     config::Config,
@@ -16,6 +19,9 @@ use types::{
     types::{Attestation, BeaconBlock},
 };
 
+/// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/networking/p2p-interface.md#configuration>
+pub const ATTESTATION_PROPAGATION_SLOT_RANGE: u64 = 32;
+
 #[derive(Clone, Copy, Debug)]
 pub struct Status {
     pub fork_version: Version,
@@ -25,6 +31,21 @@ pub struct Status {
     pub head_slot: Slot,
 }
 
+/// Identifies the peer an object was received from.
+///
+/// Each network protocol has its own peer identifiers. They are hashed into `PeerKey`s so that
+/// implementors of [`Networked`] do not depend on any particular protocol.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PeerKey(pub u64);
+
+impl PeerKey {
+    pub fn from_peer_id(peer_id: &impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        peer_id.hash(&mut hasher);
+        Self(hasher.finish())
+    }
+}
+
 pub trait Network<C: Config> {
     fn publish_beacon_block(&self, beacon_block: BeaconBlock<C>) -> Result<()>;
 
@@ -32,9 +53,26 @@ pub trait Network<C: Config> {
 }
 
 pub trait Networked<C: Config>: 'static {
-    fn accept_beacon_block(&mut self, beacon_block: BeaconBlock<C>) -> Result<()>;
+    /// Accepts a gossiped block. Implementations should ignore blocks from future slots, allowing
+    /// for some clock disparity, as described in the networking specification.
+    fn accept_beacon_block(&mut self, beacon_block: BeaconBlock<C>, peer: PeerKey) -> Result<()>;
+
+    /// Like [`Networked::accept_beacon_block`], but for blocks requested while syncing.
+    /// Implementations may choose to verify them less thoroughly.
+    fn accept_synced_beacon_block(
+        &mut self,
+        beacon_block: BeaconBlock<C>,
+        peer: PeerKey,
+    ) -> Result<()>;
 
-    fn accept_beacon_attestation(&mut self, attestation: Attestation<C>) -> Result<()>;
+    /// Accepts a gossiped attestation. Implementations should ignore attestations from slots
+    /// outside of the last [`ATTESTATION_PROPAGATION_SLOT_RANGE`] slots, allowing for some clock
+    /// disparity, as described in the networking specification.
+    fn accept_beacon_attestation(
+        &mut self,
+        attestation: Attestation<C>,
+        peer: PeerKey,
+    ) -> Result<()>;
 
     fn get_status(&self) -> Status;
 
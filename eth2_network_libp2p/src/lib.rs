@@ -10,7 +10,7 @@ use eth2_libp2p::{
     },
     Libp2pEvent, MessageId, PeerId, PubsubMessage, RPCEvent, Service, Topic, TopicHash,
 };
-use eth2_network::{Network, Networked, Status};
+use eth2_network::{Network, Networked, PeerKey, Status};
 use ethereum_types::H32;
 use fmt_extra::{AsciiStr, Hs};
 use futures::{
@@ -413,9 +413,11 @@ impl<C: Config, N: Networked<C>> EventHandler<C, N> {
                     peer_id, beacon_block,
                 );
 
-                Ok(Box::new(self.lock_networked().and_then(|mut networked| {
-                    networked.accept_beacon_block(beacon_block)
-                })))
+                let peer = PeerKey::from_peer_id(&peer_id);
+
+                Ok(Box::new(self.lock_networked().and_then(
+                    move |mut networked| networked.accept_synced_beacon_block(beacon_block, peer),
+                )))
             }
             RPCErrorResponse::Success(RPCResponse::BlocksByRoot(response_bytes)) => {
                 bail!(EventHandlerError::UnexpectedBlocksByRootResponse {
@@ -490,9 +492,11 @@ impl<C: Config, N: Networked<C>> EventHandler<C, N> {
 
                 info!("decoded gossiped beacon block: {:?}", beacon_block);
 
-                Ok(Box::new(self.lock_networked().and_then(|mut networked| {
-                    networked.accept_beacon_block(beacon_block)
-                })))
+                let peer = PeerKey::from_peer_id(&source);
+
+                Ok(Box::new(self.lock_networked().and_then(
+                    move |mut networked| networked.accept_beacon_block(beacon_block, peer),
+                )))
             }
             PubsubMessage::Attestation(bytes) => {
                 info!(
@@ -505,9 +509,11 @@ impl<C: Config, N: Networked<C>> EventHandler<C, N> {
 
                 info!("decoded gossiped beacon attestation: {:?}", attestation);
 
-                Ok(Box::new(self.lock_networked().and_then(|mut networked| {
-                    networked.accept_beacon_attestation(attestation)
-                })))
+                let peer = PeerKey::from_peer_id(&source);
+
+                Ok(Box::new(self.lock_networked().and_then(
+                    move |mut networked| networked.accept_beacon_attestation(attestation, peer),
+                )))
             }
             _ => bail!(EventHandlerError::UnsupportedGossipedObjectType {
                 message_id,
@@ -604,6 +610,12 @@ pub fn channel<C: Config>() -> (Sender<C>, Receiver<C>) {
     (Sender(sender), Receiver(receiver))
 }
 
+/// Converts a Base58-encoded peer ID into the [`PeerKey`] of objects received from the peer.
+pub fn parse_peer_key(peer_id: &str) -> Result<PeerKey> {
+    let peer_id = peer_id.parse::<PeerId>().map_err(DebugAsError::new)?;
+    Ok(PeerKey::from_peer_id(&peer_id))
+}
+
 pub fn run_network<C: Config, N: Networked<C>>(
     config: NetworkConfig,
     networked: Qutex<N>,
@@ -0,0 +1,170 @@
+//! Reading and writing objects stored in files.
+//!
+//! Objects can be stored in the same formats as in the [Ethereum 2.0 specification tests]:
+//! - YAML (`.yaml` or `.yml`).
+//! - JSON (`.json`). Not used in the specification tests, but supported by most other tools.
+//! - SSZ (`.ssz`).
+//! - SSZ compressed using the Snappy block format (`.ssz_snappy`).
+//!
+//! YAML is convenient for small objects that are edited by hand, but a mainnet-sized
+//! `BeaconState` takes up hundreds of megabytes as YAML and takes a long time to parse.
+//! SSZ files are several times smaller and decoding them is much faster.
+//!
+//! [Ethereum 2.0 specification tests]: https://github.com/ethereum/eth2.0-spec-tests
+
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use error_utils::DebugAsError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use ssz::{Decode, Encode};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error(
+        "cannot determine format of {path:?} from its extension \
+         (supported extensions: .yaml, .yml, .json, .ssz, .ssz_snappy)"
+    )]
+    UnknownExtension { path: PathBuf },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum Format {
+    Yaml,
+    Json,
+    Ssz,
+    SszSnappy,
+}
+
+impl Format {
+    /// Determines the format of a file from its extension.
+    ///
+    /// Paths without an extension are assumed to contain YAML. This lets us keep reading objects
+    /// from pipes and process substitutions (like `/dev/fd/63`), which is how YAML files used to
+    /// be passed to the beacon node before other formats were supported.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().map(OsStr::to_str) {
+            None | Some(Some("yaml")) | Some(Some("yml")) => Ok(Self::Yaml),
+            Some(Some("json")) => Ok(Self::Json),
+            Some(Some("ssz")) => Ok(Self::Ssz),
+            Some(Some("ssz_snappy")) => Ok(Self::SszSnappy),
+            Some(_) => bail!(Error::UnknownExtension {
+                path: path.to_owned(),
+            }),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned + Decode>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Self::Yaml => Ok(serde_yaml::from_slice(bytes)?),
+            Self::Json => Ok(serde_json::from_slice(bytes)?),
+            Self::Ssz => decode_ssz(bytes),
+            Self::SszSnappy => decode_ssz(snap::Decoder::new().decompress_vec(bytes)?.as_slice()),
+        }
+    }
+
+    pub fn encode<T: Serialize + Encode>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Yaml => Ok(serde_yaml::to_vec(value)?),
+            Self::Json => Ok(serde_json::to_vec_pretty(value)?),
+            Self::Ssz => Ok(value.as_ssz_bytes()),
+            Self::SszSnappy => {
+                Ok(snap::Encoder::new().compress_vec(value.as_ssz_bytes().as_slice())?)
+            }
+        }
+    }
+}
+
+/// Reads an object from a file.
+///
+/// If `format` is `None`, it is determined using [`Format::from_path`].
+pub fn read<T: DeserializeOwned + Decode>(
+    path: impl AsRef<Path>,
+    format: Option<Format>,
+) -> Result<T> {
+    let path = path.as_ref();
+    let format = match format {
+        Some(format) => format,
+        None => Format::from_path(path)?,
+    };
+    format.decode(fs::read(path)?.as_slice())
+}
+
+/// Writes an object to a file, replacing its contents if it already exists.
+///
+/// If `format` is `None`, it is determined using [`Format::from_path`].
+pub fn write<T: Serialize + Encode>(
+    path: impl AsRef<Path>,
+    format: Option<Format>,
+    value: &T,
+) -> Result<()> {
+    let path = path.as_ref();
+    let format = match format {
+        Some(format) => format,
+        None => Format::from_path(path)?,
+    };
+    fs::write(path, format.encode(value)?)?;
+    Ok(())
+}
+
+fn decode_ssz<T: Decode>(bytes: &[u8]) -> Result<T> {
+    T::from_ssz_bytes(bytes)
+        .map_err(DebugAsError::new)
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use types::{primitives::H256, types::Checkpoint};
+
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            epoch: 7,
+            root: H256::repeat_byte(0xAB),
+        }
+    }
+
+    #[test]
+    fn from_path_recognizes_extensions() -> Result<()> {
+        assert_eq!(Format::from_path("state.yaml".as_ref())?, Format::Yaml);
+        assert_eq!(Format::from_path("state.yml".as_ref())?, Format::Yaml);
+        assert_eq!(Format::from_path("state.json".as_ref())?, Format::Json);
+        assert_eq!(Format::from_path("state.ssz".as_ref())?, Format::Ssz);
+        assert_eq!(
+            Format::from_path("state.ssz_snappy".as_ref())?,
+            Format::SszSnappy,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_path_treats_paths_without_extension_as_yaml() -> Result<()> {
+        assert_eq!(Format::from_path("/dev/fd/63".as_ref())?, Format::Yaml);
+        Ok(())
+    }
+
+    #[test]
+    fn from_path_rejects_unknown_extensions() {
+        assert!(Format::from_path("state.toml".as_ref()).is_err());
+    }
+
+    #[test]
+    fn objects_survive_round_trip_through_every_format() -> Result<()> {
+        for format in [Format::Yaml, Format::Json, Format::Ssz, Format::SszSnappy]
+            .iter()
+            .copied()
+        {
+            let bytes = format.encode(&checkpoint())?;
+            let decoded: Checkpoint = format.decode(bytes.as_slice())?;
+            assert_eq!(decoded, checkpoint());
+        }
+        Ok(())
+    }
+}
@@ -5,15 +5,18 @@ use crate::predicates::is_active_validator;
 use ethereum_types::H256;
 use ssz_types::BitList;
 use std::cmp::max;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::convert::TryFrom;
+use std::ops::Range;
+use std::sync::Arc;
 use typenum::Unsigned as _;
 use types::beacon_state::BeaconState;
+use types::committee_cache::EpochCommittees;
 use types::config::Config;
 use types::consts::*;
 use types::helper_functions_types::Error;
 use types::primitives::*;
-use types::types::{Attestation, AttestationData, IndexedAttestation};
+use types::types::{Attestation, AttestationData, AttestationDuty, IndexedAttestation};
 
 pub fn get_current_epoch<C: Config>(state: &BeaconState<C>) -> Epoch {
     compute_epoch_at_slot::<C>(state.slot)
@@ -141,23 +144,44 @@ pub fn get_seed<C: Config>(
     Ok(H256::from_slice(&hash(&seed)))
 }
 
+/// Returns the committees of `epoch`, computing them if they are not cached yet.
+///
+/// The active validators are shuffled all at once instead of one committee member at a time.
+pub fn get_epoch_committees<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+) -> Result<Arc<EpochCommittees>, Error> {
+    let seed = get_seed(state, epoch, C::domain_attestation())?;
+
+    if let Some(committees) = state.committee_cache.get(epoch, seed) {
+        return Ok(committees);
+    }
+
+    let active_indices = get_active_validator_indices(state, epoch);
+    let committees_per_slot =
+        (active_indices.len() as u64 / C::SlotsPerEpoch::U64 / C::target_committee_size())
+            .min(C::max_committees_per_slot())
+            .max(1);
+    let mut shuffling = active_indices.clone();
+    shuffle_list::<C>(shuffling.as_mut_slice(), &seed);
+
+    let committees = Arc::new(EpochCommittees {
+        epoch,
+        seed,
+        active_indices,
+        shuffling,
+        committees_per_slot,
+    });
+    state.committee_cache.insert(committees.clone());
+    Ok(committees)
+}
+
 pub fn get_committee_count_at_slot<C: Config>(
     state: &BeaconState<C>,
     slot: Slot,
 ) -> Result<u64, Error> {
     let epoch = compute_epoch_at_slot::<C>(slot);
-    let active_count = get_active_validator_indices(state, epoch).len() as u64
-        / C::SlotsPerEpoch::U64
-        / C::target_committee_size();
-    let mut count = if C::max_committees_per_slot() < active_count {
-        C::max_committees_per_slot()
-    } else {
-        active_count
-    };
-
-    count = if 1 > count { 1 } else { count };
-
-    Ok(count)
+    Ok(get_epoch_committees(state, epoch)?.committees_per_slot)
 }
 
 pub fn get_beacon_committee<C: Config>(
@@ -166,53 +190,152 @@ pub fn get_beacon_committee<C: Config>(
     index: u64,
 ) -> Result<Vec<ValidatorIndex>, Error> {
     let epoch = compute_epoch_at_slot::<C>(slot);
-    let committees_per_slot = get_committee_count_at_slot(state, slot);
-    if committees_per_slot.is_err() {
-        return Err(committees_per_slot.err().expect("Should be error"));
+    let committees = get_epoch_committees(state, epoch)?;
+    if index >= committees.committees_per_slot {
+        return Err(Error::IndexOutOfRange);
     }
+    let range = committee_range::<C>(&committees, slot, index);
+    Ok(committees.shuffling[range].to_vec())
+}
 
-    let indices = get_active_validator_indices(state, epoch);
-    let seed = get_seed(state, epoch, C::domain_attestation());
-    if seed.is_err() {
-        return Err(seed.err().expect("Should be error"));
+/// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/validator/0_beacon-chain-validator.md#validator-assignments>
+///
+/// Returns the duties of `validator_indices` in the same order. Validators that are not active in
+/// `epoch` have no duties. All committees of `epoch` are computed at once.
+pub fn get_attestation_duties<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+    validator_indices: &[ValidatorIndex],
+) -> Result<Vec<Option<AttestationDuty>>, Error> {
+    if get_current_epoch(state) + 1 < epoch {
+        return Err(Error::EpochOutOfRange);
     }
 
-    let committees = committees_per_slot.expect("Expected seed");
-    let i = (slot % C::SlotsPerEpoch::U64) * committees + index;
-    let count = committees * C::SlotsPerEpoch::U64;
+    let committees = get_epoch_committees(state, epoch)?;
+    let mut duties = vec![None; validator_indices.len()];
+    let mut positions_in_output = HashMap::new();
+    for (position, validator_index) in validator_indices.iter().enumerate() {
+        positions_in_output
+            .entry(*validator_index)
+            .or_insert_with(Vec::new)
+            .push(position);
+    }
 
-    compute_committee::<C>(indices.as_slice(), &seed.expect("Expected seed"), i, count)
+    let start_slot = compute_start_slot_at_epoch::<C>(epoch);
+    for slot in start_slot..start_slot + C::SlotsPerEpoch::U64 {
+        for index in 0..committees.committees_per_slot {
+            let committee = &committees.shuffling[committee_range::<C>(&committees, slot, index)];
+            for (committee_position, validator_index) in committee.iter().enumerate() {
+                for position in positions_in_output
+                    .get(validator_index)
+                    .into_iter()
+                    .flatten()
+                {
+                    duties[*position] = Some(AttestationDuty {
+                        slot,
+                        index,
+                        committee_position,
+                        committee_len: committee.len(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(duties)
+}
+
+// See `compute_committee`.
+fn committee_range<C: Config>(
+    committees: &EpochCommittees,
+    slot: Slot,
+    index: u64,
+) -> Range<usize> {
+    let committees_per_slot = committees.committees_per_slot;
+    let i = (slot % C::SlotsPerEpoch::U64) * committees_per_slot + index;
+    let count = committees_per_slot * C::SlotsPerEpoch::U64;
+    let active_count = committees.shuffling.len() as u64;
+    let start = (active_count * i / count) as usize;
+    let end = (active_count * (i + 1) / count) as usize;
+    start..end
 }
 
 pub fn get_beacon_proposer_index<C: Config>(
     state: &BeaconState<C>,
 ) -> Result<ValidatorIndex, Error> {
-    let epoch = get_current_epoch(state);
-    let seed = get_seed(state, epoch, C::domain_beacon_proposer());
-    if seed.is_err() {
-        return Err(seed.err().expect("Should be error"));
+    let proposers = get_current_epoch_proposers(state)?;
+    Ok(proposers[(state.slot % C::SlotsPerEpoch::U64) as usize])
+}
+
+/// Returns the proposers of the slots in `epoch` in order of slots.
+///
+/// `epoch` must be the current or the next epoch. Proposers are selected based on effective
+/// balances, which may still change during epoch processing, so the proposers of the next epoch
+/// are only a prediction. Unlike the proposers of the current epoch, they are not cached.
+pub fn get_beacon_proposer_indices<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+) -> Result<Vec<ValidatorIndex>, Error> {
+    let current_epoch = get_current_epoch(state);
+    if epoch == current_epoch {
+        Ok(get_current_epoch_proposers(state)?.to_vec())
+    } else if epoch == current_epoch + 1 {
+        let seed = get_seed(state, epoch, C::domain_beacon_proposer())?;
+        compute_epoch_proposers(state, epoch, &seed)
+    } else {
+        Err(Error::EpochOutOfRange)
     }
+}
 
-    let indices = get_active_validator_indices(state, epoch);
+fn get_current_epoch_proposers<C: Config>(
+    state: &BeaconState<C>,
+) -> Result<Arc<Vec<ValidatorIndex>>, Error> {
+    let epoch = get_current_epoch(state);
+    let seed = get_seed(state, epoch, C::domain_beacon_proposer())?;
 
-    let mut seed_with_slot = [0; 40];
-    seed_with_slot[..32].copy_from_slice(seed?.as_bytes());
-    seed_with_slot[32..].copy_from_slice(&state.slot.to_le_bytes());
-    let seed = H256::from_slice(hash(&seed_with_slot).as_slice());
+    if let Some(proposers) = state.committee_cache.get_proposers(epoch, seed) {
+        return Ok(proposers);
+    }
 
-    compute_proposer_index(state, &indices, &seed)
+    let proposers = Arc::new(compute_epoch_proposers(state, epoch, &seed)?);
+    state
+        .committee_cache
+        .insert_proposers(epoch, seed, proposers.clone());
+    Ok(proposers)
+}
+
+fn compute_epoch_proposers<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+    seed: &H256,
+) -> Result<Vec<ValidatorIndex>, Error> {
+    let committees = get_epoch_committees(state, epoch)?;
+    let start_slot = compute_start_slot_at_epoch::<C>(epoch);
+
+    (start_slot..start_slot + C::SlotsPerEpoch::U64)
+        .map(|slot| {
+            let mut seed_with_slot = [0; 40];
+            seed_with_slot[..32].copy_from_slice(seed.as_bytes());
+            seed_with_slot[32..].copy_from_slice(&slot.to_le_bytes());
+            let seed = H256::from_slice(hash(&seed_with_slot).as_slice());
+            compute_proposer_index(state, &committees.active_indices, &seed)
+        })
+        .collect()
 }
 
 pub fn get_total_balance<C: Config>(
     state: &BeaconState<C>,
     indices: &[ValidatorIndex],
 ) -> Result<u64, Error> {
-    let mut balance: Gwei = 0;
-    for (i, v) in state.validators.iter().enumerate() {
-        if indices.contains(&(i as u64)) {
-            balance += v.effective_balance;
-        }
-    }
+    // Duplicate indices are counted once and indices out of range are ignored.
+    let balance: Gwei = indices
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|index| state.validators.get(index as usize))
+        .map(|validator| validator.effective_balance)
+        .sum();
     if balance > 1 {
         Ok(balance)
     } else {
@@ -287,6 +410,7 @@ pub fn get_attesting_indices<C: Config>(
 mod tests {
     use super::*;
     use ssz_types::{typenum, FixedVector, VariableList};
+    use typenum::Unsigned as _;
     use types::config::MinimalConfig;
     use types::types::Validator;
 
@@ -351,4 +475,170 @@ mod tests {
         assert_eq!(result.is_ok(), true);
         assert_eq!(result.expect("Expected success"), 1);
     }
+
+    fn state_with_validators(count: u64) -> BeaconState<MinimalConfig> {
+        let mut state = BeaconState::<MinimalConfig> {
+            slot: 21,
+            ..BeaconState::default()
+        };
+        for index in 0..count {
+            // Every tenth validator is not active yet.
+            let activation_epoch = if index % 10 == 0 { FAR_FUTURE_EPOCH } else { 0 };
+            let validator = Validator {
+                activation_epoch,
+                exit_epoch: FAR_FUTURE_EPOCH,
+                effective_balance: MinimalConfig::max_effective_balance(),
+                ..Validator::default()
+            };
+            state.validators.push(validator).expect("");
+        }
+        for (index, mix) in state.randao_mixes.iter_mut().enumerate() {
+            *mix = H256::from_low_u64_be(index as u64);
+        }
+        state
+    }
+
+    #[test]
+    fn test_get_beacon_committee_matches_compute_committee() {
+        let state = state_with_validators(300);
+        let current_epoch = get_current_epoch(&state);
+
+        for epoch in current_epoch - 1..=current_epoch + 1 {
+            let indices = get_active_validator_indices(&state, epoch);
+            let seed = get_seed(&state, epoch, MinimalConfig::domain_attestation()).expect("");
+            let start_slot = compute_start_slot_at_epoch::<MinimalConfig>(epoch);
+            let committees_per_slot = get_committee_count_at_slot(&state, start_slot).expect("");
+            let count = committees_per_slot * MinimalConfig::SlotsPerEpoch::U64;
+
+            assert_eq!(committees_per_slot, 4);
+
+            for slot in start_slot..start_slot + MinimalConfig::SlotsPerEpoch::U64 {
+                for index in 0..committees_per_slot {
+                    let i =
+                        (slot % MinimalConfig::SlotsPerEpoch::U64) * committees_per_slot + index;
+                    assert_eq!(
+                        get_beacon_committee(&state, slot, index),
+                        compute_committee::<MinimalConfig>(indices.as_slice(), &seed, i, count),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_beacon_proposer_index_matches_uncached() {
+        let mut state = state_with_validators(100);
+        for slot in 16..24 {
+            state.slot = slot;
+            let epoch = get_current_epoch(&state);
+            let seed = get_seed(&state, epoch, MinimalConfig::domain_beacon_proposer()).expect("");
+            let mut seed_with_slot = seed.as_bytes().to_vec();
+            seed_with_slot.extend_from_slice(&slot.to_le_bytes());
+            let expected = compute_proposer_index(
+                &state,
+                get_active_validator_indices(&state, epoch).as_slice(),
+                &H256::from_slice(hash(&seed_with_slot).as_slice()),
+            );
+            assert_eq!(get_beacon_proposer_index(&state), expected);
+        }
+    }
+
+    #[test]
+    fn test_get_epoch_committees_is_cached() {
+        let state = state_with_validators(50);
+        let first = get_epoch_committees(&state, 2).expect("");
+        let second = get_epoch_committees(&state, 2).expect("");
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // Committees are cached by seed, so changing the RANDAO mix the seed is derived from
+        // invalidates them.
+        let mut state = state.clone();
+        state.randao_mixes[0] = H256::repeat_byte(1);
+        let third = get_epoch_committees(&state, 2).expect("");
+        assert_ne!(first.seed, third.seed);
+        assert_eq!(first.active_indices, third.active_indices);
+    }
+
+    #[test]
+    fn test_get_beacon_proposer_indices_of_current_epoch() {
+        let mut state = state_with_validators(100);
+        let proposers = get_beacon_proposer_indices(&state, 2).expect("");
+        assert_eq!(proposers.len(), 8);
+
+        for (slot, proposer) in (16..24).zip(proposers) {
+            state.slot = slot;
+            assert_eq!(get_beacon_proposer_index(&state), Ok(proposer));
+        }
+    }
+
+    #[test]
+    fn test_get_beacon_proposer_indices_of_next_epoch() {
+        let mut state = state_with_validators(100);
+        let predicted = get_beacon_proposer_indices(&state, 3).expect("");
+
+        state.slot = 24;
+        assert_eq!(get_beacon_proposer_indices(&state, 3), Ok(predicted));
+    }
+
+    #[test]
+    fn test_get_beacon_proposer_indices_of_other_epochs() {
+        let state = state_with_validators(100);
+        assert_eq!(
+            get_beacon_proposer_indices(&state, 1),
+            Err(Error::EpochOutOfRange),
+        );
+        assert_eq!(
+            get_beacon_proposer_indices(&state, 4),
+            Err(Error::EpochOutOfRange),
+        );
+    }
+
+    #[test]
+    fn test_get_attestation_duties_match_committees() {
+        let state = state_with_validators(120);
+        let validator_indices = [0, 1, 17, 119, 1, 500];
+
+        for epoch in 1..=3 {
+            let duties = get_attestation_duties(&state, epoch, &validator_indices).expect("");
+            assert_eq!(duties.len(), validator_indices.len());
+
+            // Validator 0 is not active and validator 500 does not exist.
+            assert_eq!(duties[0], None);
+            assert_eq!(duties[5], None);
+            assert_eq!(duties[1], duties[4]);
+
+            for (validator_index, duty) in validator_indices.iter().zip(duties).skip(1).take(3) {
+                let duty = duty.expect("active validators should have duties");
+                let committee = get_beacon_committee(&state, duty.slot, duty.index).expect("");
+                assert_eq!(compute_epoch_at_slot::<MinimalConfig>(duty.slot), epoch);
+                assert_eq!(committee.len(), duty.committee_len);
+                assert_eq!(committee[duty.committee_position], *validator_index);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_attestation_duties_too_far_ahead() {
+        let state = state_with_validators(10);
+        assert_eq!(
+            get_attestation_duties(&state, 4, &[1]),
+            Err(Error::EpochOutOfRange),
+        );
+    }
+
+    #[test]
+    fn test_get_beacon_committee_index_out_of_range() {
+        let state = state_with_validators(300);
+        let committees_per_slot = get_committee_count_at_slot(&state, state.slot).expect("");
+
+        assert!(get_beacon_committee(&state, state.slot, committees_per_slot - 1).is_ok());
+        assert_eq!(
+            get_beacon_committee(&state, state.slot, committees_per_slot),
+            Err(Error::IndexOutOfRange),
+        );
+        assert_eq!(
+            get_beacon_committee(&state, state.slot, u64::max_value()),
+            Err(Error::IndexOutOfRange),
+        );
+    }
 }
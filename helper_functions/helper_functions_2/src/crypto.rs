@@ -1,5 +1,6 @@
 use bls::{
-    AggregatePublicKey, AggregateSignature, PublicKey, PublicKeyBytes, Signature, SignatureBytes,
+    AggregatePublicKey, AggregateSignature, G1Ref as _, PublicKey, PublicKeyBytes, Signature,
+    SignatureBytes,
 };
 
 use ring::digest::{digest, SHA256};
@@ -19,9 +20,19 @@ pub fn bls_verify(
     domain: u64,
 ) -> Result<bool, DecodeError> {
     let pk: PublicKey = pubkey.try_into()?;
+    bls_verify_decompressed(&pk, message, signature, domain)
+}
+
+/// Like `bls_verify` but takes a public key that is already decompressed, like `Validator.pubkey`.
+pub fn bls_verify_decompressed(
+    pubkey: &PublicKey,
+    message: &[u8],
+    signature: &SignatureBytes,
+    domain: u64,
+) -> Result<bool, DecodeError> {
     let sg: Signature = signature.try_into()?;
 
-    Ok(sg.verify(message, domain, &pk))
+    Ok(sg.verify(message, domain, pubkey))
 }
 
 pub fn bls_verify_multiple(
@@ -41,6 +52,62 @@ pub fn bls_verify_multiple(
     Ok(sg.verify_multiple(messages, domain, &[&apk]))
 }
 
+/// A signature together with the public key, message and domain it is verified against.
+pub struct SignatureSet {
+    signature: AggregateSignature,
+    pubkey: AggregatePublicKey,
+    message: H256,
+    domain: u64,
+}
+
+impl SignatureSet {
+    pub fn new(
+        signature: AggregateSignature,
+        pubkey: AggregatePublicKey,
+        message: H256,
+        domain: u64,
+    ) -> Self {
+        Self {
+            signature,
+            pubkey,
+            message,
+            domain,
+        }
+    }
+
+    pub fn single(signature: &Signature, pubkey: &PublicKey, message: H256, domain: u64) -> Self {
+        let mut aggregate_signature = AggregateSignature::new();
+        aggregate_signature.add(signature);
+        let mut aggregate_pubkey = AggregatePublicKey::new();
+        aggregate_pubkey.add(pubkey);
+        Self::new(aggregate_signature, aggregate_pubkey, message, domain)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.signature
+            .verify(self.message.as_bytes(), self.domain, &self.pubkey)
+    }
+}
+
+/// Verifies all signatures in `sets` with a single randomized batch check.
+///
+/// Returns `true` only if every signature is valid. This is considerably faster than calling
+/// `SignatureSet::is_valid` on each set but does not tell which signature is invalid.
+pub fn verify_signature_sets<'a>(sets: impl IntoIterator<Item = &'a SignatureSet>) -> bool {
+    let mut sets = sets.into_iter().peekable();
+    if sets.peek().is_none() {
+        return true;
+    }
+    bls::verify_signature_sets(sets.map(|set| {
+        bls::SignatureSet::single(
+            &set.signature,
+            set.pubkey.g1_ref(),
+            set.message.as_bytes().to_vec(),
+            set.domain,
+        )
+    }))
+}
+
 pub fn bls_aggregate_pubkeys(pubkeys: &[PublicKey]) -> AggregatePublicKey {
     let mut aggr_pk = AggregatePublicKey::new();
     for pk in pubkeys {
@@ -204,6 +271,40 @@ mod tests {
         .expect("Unexpected error"));
     }
 
+    fn signature_set(message: H256, domain: u64) -> SignatureSet {
+        let sk = SecretKey::random();
+        let pk = PublicKey::from_secret_key(&sk);
+        let signature = Signature::new(message.as_bytes(), domain, &sk);
+        SignatureSet::single(&signature, &pk, message, domain)
+    }
+
+    #[test]
+    fn test_verify_signature_sets() {
+        let sets = vec![
+            signature_set(H256::repeat_byte(1), 1),
+            signature_set(H256::repeat_byte(2), 2),
+            signature_set(H256::repeat_byte(3), 2),
+        ];
+        assert!(sets.iter().all(SignatureSet::is_valid));
+        assert!(verify_signature_sets(&sets));
+        assert!(verify_signature_sets(&[]));
+    }
+
+    #[test]
+    fn test_verify_signature_sets_invalid() {
+        let sk = SecretKey::random();
+        let pk = PublicKey::from_secret_key(&sk);
+        let message = H256::repeat_byte(4);
+        // Signed with a different domain.
+        let signature = Signature::new(message.as_bytes(), 3, &sk);
+        let invalid = SignatureSet::single(&signature, &pk, message, 4);
+
+        let sets = vec![signature_set(H256::repeat_byte(5), 4), invalid];
+        assert!(sets[0].is_valid());
+        assert!(!sets[1].is_valid());
+        assert!(!verify_signature_sets(&sets));
+    }
+
     #[test]
     fn test_bls_verify_invalid_sig() {
         // Create a valid public key first
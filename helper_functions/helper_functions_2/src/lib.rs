@@ -7,5 +7,6 @@ pub mod beacon_state_mutators;
 pub mod crypto;
 pub mod error;
 pub mod math;
+pub mod merkle_proofs;
 pub mod misc;
 pub mod predicates;
@@ -0,0 +1,592 @@
+//! Producing Merkle proofs about SSZ objects.
+//!
+//! Based on <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/light_client/merkle_proofs.md>.
+//!
+//! `TreeHash` only exposes the root of an object, so the structure of each type is described by
+//! implementing `MerkleTree` for it. Proofs produced here can be checked with
+//! `merkle_proof::verify_merkle_multiproof` (or `merkle_proof::verify_merkle_proof` for a single
+//! leaf).
+
+use crate::crypto::hash;
+use bls::{AggregateSignature, PublicKey, Signature};
+use ssz_types::{BitList, BitVector, FixedVector, VariableList};
+use tree_hash::{TreeHash, TreeHashType};
+use typenum::Unsigned;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    primitives::H256,
+    types::{
+        AttestationData, BeaconBlockHeader, Checkpoint, Eth1Data, Fork, PendingAttestation,
+        Validator,
+    },
+};
+
+#[derive(PartialEq, Debug)]
+pub enum ProofError {
+    UnknownField,
+    IndexOutOfRange,
+    NotAList,
+    NotComposite,
+    GeneralizedIndexOverflow,
+    PathThroughLength,
+}
+
+/// An element of a path to a node, like `state.validators[5].effective_balance`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PathElement<'a> {
+    Field(&'a str),
+    Index(usize),
+    /// The length mixed into the root of a list (`__len__` in the specification).
+    Length,
+}
+
+/// The shape of the Merkle tree of an SSZ object.
+pub trait MerkleTree {
+    /// The depth of the tree of chunks, not including the length mixed into lists.
+    fn chunk_depth(&self) -> usize;
+
+    /// The number of chunks that are not padding.
+    fn chunk_count(&self) -> usize;
+
+    fn chunk(&self, index: usize) -> H256;
+
+    /// The object whose root is chunk `index`, if it can be descended into.
+    fn child(&self, index: usize) -> Option<&dyn MerkleTree>;
+
+    /// The length mixed into the root. Only lists have one.
+    fn length(&self) -> Option<usize> {
+        None
+    }
+
+    /// The position of the chunk containing `element`. See `get_item_position`.
+    fn position(&self, element: PathElement) -> Result<usize, ProofError>;
+}
+
+/// A proof that `leaves` are at `indices` in the tree of some object.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MultiProof {
+    pub indices: Vec<usize>,
+    pub leaves: Vec<H256>,
+    /// Nodes at the indices returned by `merkle_proof::get_helper_indices`, without index 0.
+    pub proof: Vec<H256>,
+}
+
+// Leaves and opaque values. Their roots are computed by `TreeHash`.
+macro_rules! impl_leaf {
+    (impl $([$($generics: tt)*])? for $type: ty) => {
+        impl $(<$($generics)*>)? MerkleTree for $type {
+            fn chunk_depth(&self) -> usize {
+                0
+            }
+
+            fn chunk_count(&self) -> usize {
+                1
+            }
+
+            fn chunk(&self, _index: usize) -> H256 {
+                H256::from_slice(self.tree_hash_root().as_slice())
+            }
+
+            fn child(&self, _index: usize) -> Option<&dyn MerkleTree> {
+                None
+            }
+
+            fn position(&self, _element: PathElement) -> Result<usize, ProofError> {
+                Err(ProofError::NotComposite)
+            }
+        }
+    };
+}
+
+impl_leaf!(impl for u64);
+impl_leaf!(impl for bool);
+impl_leaf!(impl for H256);
+// `Fork` versions are packed into a single chunk.
+impl_leaf!(impl for [u8; 4]);
+impl_leaf!(impl for PublicKey);
+impl_leaf!(impl for Signature);
+impl_leaf!(impl for AggregateSignature);
+impl_leaf!(impl [N: Unsigned + Clone] for BitList<N>);
+impl_leaf!(impl [N: Unsigned + Clone] for BitVector<N>);
+
+macro_rules! impl_container {
+    (impl $([$($generics: tt)*])? for $type: ty { $($field: ident),* $(,)? }) => {
+        impl $(<$($generics)*>)? MerkleTree for $type {
+            fn chunk_depth(&self) -> usize {
+                depth_for(self.chunk_count())
+            }
+
+            fn chunk_count(&self) -> usize {
+                [$(stringify!($field)),*].len()
+            }
+
+            fn chunk(&self, index: usize) -> H256 {
+                let child = self.child(index).expect("containers have a child for every chunk");
+                subtree_root(child, child.chunk_depth(), 0)
+            }
+
+            fn child(&self, index: usize) -> Option<&dyn MerkleTree> {
+                let children: &[&dyn MerkleTree] = &[$(&self.$field),*];
+                children.get(index).copied()
+            }
+
+            fn position(&self, element: PathElement) -> Result<usize, ProofError> {
+                match element {
+                    PathElement::Field(name) => [$(stringify!($field)),*]
+                        .iter()
+                        .position(|field| *field == name)
+                        .ok_or(ProofError::UnknownField),
+                    PathElement::Index(_) => Err(ProofError::UnknownField),
+                    PathElement::Length => Err(ProofError::NotAList),
+                }
+            }
+        }
+    };
+}
+
+impl_container!(impl for Checkpoint { epoch, root });
+impl_container!(impl for Fork { previous_version, current_version, epoch });
+impl_container!(impl for Eth1Data { deposit_root, deposit_count, block_hash });
+impl_container!(impl for BeaconBlockHeader { slot, parent_root, state_root, body_root, signature });
+impl_container!(impl for AttestationData { slot, index, beacon_block_root, source, target });
+
+impl_container!(impl for Validator {
+    pubkey,
+    withdrawal_credentials,
+    effective_balance,
+    slashed,
+    activation_eligibility_epoch,
+    activation_epoch,
+    exit_epoch,
+    withdrawable_epoch,
+});
+
+impl_container!(impl [C: Config] for PendingAttestation<C> {
+    aggregation_bits,
+    data,
+    inclusion_delay,
+    proposer_index,
+});
+
+impl_container!(impl [C: Config] for BeaconState<C> {
+    genesis_time,
+    slot,
+    fork,
+    latest_block_header,
+    block_roots,
+    state_roots,
+    historical_roots,
+    eth1_data,
+    eth1_data_votes,
+    eth1_deposit_index,
+    validators,
+    balances,
+    randao_mixes,
+    slashings,
+    previous_epoch_attestations,
+    current_epoch_attestations,
+    justification_bits,
+    previous_justified_checkpoint,
+    current_justified_checkpoint,
+    finalized_checkpoint,
+});
+
+// Lists and vectors share everything except for the length mixed into lists.
+macro_rules! impl_sequence {
+    ($type: ident, $length: expr) => {
+        impl<T: TreeHash + MerkleTree, N: Unsigned> MerkleTree for $type<T, N> {
+            fn chunk_depth(&self) -> usize {
+                depth_for(chunks_for::<T>(N::to_usize()))
+            }
+
+            fn chunk_count(&self) -> usize {
+                chunks_for::<T>(self.len())
+            }
+
+            fn chunk(&self, index: usize) -> H256 {
+                if is_basic::<T>() {
+                    let packing_factor = T::tree_hash_packing_factor();
+                    let mut bytes = self
+                        .iter()
+                        .skip(index * packing_factor)
+                        .take(packing_factor)
+                        .flat_map(|element| element.tree_hash_packed_encoding())
+                        .collect::<Vec<_>>();
+                    bytes.resize(32, 0);
+                    H256::from_slice(bytes.as_slice())
+                } else {
+                    let child = &self[index];
+                    subtree_root(child, child.chunk_depth(), 0)
+                }
+            }
+
+            fn child(&self, index: usize) -> Option<&dyn MerkleTree> {
+                if is_basic::<T>() {
+                    None
+                } else {
+                    self.get(index).map(|child| child as &dyn MerkleTree)
+                }
+            }
+
+            fn length(&self) -> Option<usize> {
+                let length: fn(&Self) -> Option<usize> = $length;
+                length(self)
+            }
+
+            fn position(&self, element: PathElement) -> Result<usize, ProofError> {
+                match element {
+                    PathElement::Index(index) if index < N::to_usize() => {
+                        if is_basic::<T>() {
+                            Ok(index / T::tree_hash_packing_factor())
+                        } else {
+                            Ok(index)
+                        }
+                    }
+                    PathElement::Index(_) => Err(ProofError::IndexOutOfRange),
+                    PathElement::Field(_) => Err(ProofError::UnknownField),
+                    PathElement::Length => Err(ProofError::NotAList),
+                }
+            }
+        }
+    };
+}
+
+impl_sequence!(VariableList, |list| Some(list.len()));
+impl_sequence!(FixedVector, |_| None);
+
+/// <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/light_client/merkle_proofs.md#generalized-merkle-tree-index>
+pub fn get_generalized_index(
+    object: &dyn MerkleTree,
+    path: &[PathElement],
+) -> Result<usize, ProofError> {
+    let mut root = 1_usize;
+    let mut current = Some(object);
+
+    for element in path {
+        let object = current.ok_or(ProofError::NotComposite)?;
+        match element {
+            PathElement::Length => {
+                if object.length().is_none() {
+                    return Err(ProofError::NotAList);
+                }
+                root = root
+                    .checked_mul(2)
+                    .map(|root| root + 1)
+                    .ok_or(ProofError::GeneralizedIndexOverflow)?;
+                current = None;
+            }
+            _ => {
+                let position = object.position(*element)?;
+                let base_index = if object.length().is_some() { 2 } else { 1 };
+                root = root
+                    .checked_mul(base_index)
+                    .and_then(|root| root.checked_mul(1 << object.chunk_depth()))
+                    .and_then(|root| root.checked_add(position))
+                    .ok_or(ProofError::GeneralizedIndexOverflow)?;
+                current = object.child(position);
+            }
+        }
+    }
+
+    Ok(root)
+}
+
+/// Returns the node at generalized index `index` in the tree of `object`.
+pub fn get_node(object: &dyn MerkleTree, index: usize) -> Result<H256, ProofError> {
+    if index == 0 {
+        return Err(ProofError::IndexOutOfRange);
+    }
+    node_below(object, index, bit_length(index) - 1)
+}
+
+/// Produces a proof for the objects at the end of each of `paths`.
+///
+/// Leaves and indices are returned in the same order as `paths`.
+pub fn get_multiproof(
+    object: &dyn MerkleTree,
+    paths: &[&[PathElement]],
+) -> Result<MultiProof, ProofError> {
+    let indices = paths
+        .iter()
+        .map(|path| get_generalized_index(object, path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let leaves = indices
+        .iter()
+        .map(|index| get_node(object, *index))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let proof = merkle_proof::get_helper_indices(indices.as_slice())
+        .into_iter()
+        .filter(|index| *index != 0)
+        .map(|index| get_node(object, index))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MultiProof {
+        indices,
+        leaves,
+        proof,
+    })
+}
+
+// Returns the node reached by following the lowest `remaining` bits of `index` from the root of
+// `object`, most significant bit first.
+fn node_below(object: &dyn MerkleTree, index: usize, remaining: usize) -> Result<H256, ProofError> {
+    let depth = object.chunk_depth();
+
+    let mut remaining = remaining;
+
+    if let Some(length) = object.length() {
+        if remaining == 0 {
+            return Ok(mix_in_length(subtree_root(object, depth, 0), length));
+        }
+        remaining -= 1;
+        let goes_right = (index >> remaining) & 1 == 1;
+        match (goes_right, remaining) {
+            (true, 0) => return Ok(length_chunk(length)),
+            (true, _) => return Err(ProofError::PathThroughLength),
+            (false, 0) => return Ok(subtree_root(object, depth, 0)),
+            (false, _) => {}
+        }
+    }
+
+    if remaining <= depth {
+        let position = index & low_bits(remaining);
+        return Ok(subtree_root(object, depth - remaining, position));
+    }
+
+    remaining -= depth;
+    let position = (index >> remaining) & low_bits(depth);
+    let child = object.child(position).ok_or(ProofError::NotComposite)?;
+    node_below(child, index, remaining)
+}
+
+// Returns the root of the subtree `height` levels above the chunks with index `position` among
+// the nodes at that level. Subtrees containing only padding are not traversed.
+fn subtree_root(object: &dyn MerkleTree, height: usize, position: usize) -> H256 {
+    let first_chunk = position
+        .checked_shl(height as u32)
+        .unwrap_or(usize::max_value());
+    if object.chunk_count() <= first_chunk {
+        return zero_hash(height);
+    }
+    if height == 0 {
+        return object.chunk(position);
+    }
+    let left = subtree_root(object, height - 1, position * 2);
+    let right = subtree_root(object, height - 1, position * 2 + 1);
+    hash_pair(left, right)
+}
+
+fn mix_in_length(root: H256, length: usize) -> H256 {
+    hash_pair(root, length_chunk(length))
+}
+
+fn length_chunk(length: usize) -> H256 {
+    let mut chunk = H256::zero();
+    chunk.as_bytes_mut()[..8].copy_from_slice(&(length as u64).to_le_bytes());
+    chunk
+}
+
+fn zero_hash(height: usize) -> H256 {
+    (0..height).fold(H256::zero(), |node, _| hash_pair(node, node))
+}
+
+fn hash_pair(left: H256, right: H256) -> H256 {
+    let mut bytes = left.as_bytes().to_vec();
+    bytes.extend_from_slice(right.as_bytes());
+    H256::from_slice(hash(bytes.as_slice()).as_slice())
+}
+
+fn is_basic<T: TreeHash>() -> bool {
+    match T::tree_hash_type() {
+        TreeHashType::Basic => true,
+        _ => false,
+    }
+}
+
+fn chunks_for<T: TreeHash>(length: usize) -> usize {
+    if is_basic::<T>() {
+        let packing_factor = T::tree_hash_packing_factor();
+        (length + packing_factor - 1) / packing_factor
+    } else {
+        length
+    }
+}
+
+fn depth_for(chunk_count: usize) -> usize {
+    chunk_count.next_power_of_two().trailing_zeros() as usize
+}
+
+fn bit_length(index: usize) -> usize {
+    (0_usize.count_zeros() - index.leading_zeros()) as usize
+}
+
+fn low_bits(count: usize) -> usize {
+    (1_usize << count) - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use merkle_proof::{verify_merkle_multiproof, verify_merkle_proof};
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    fn state() -> BeaconState<MinimalConfig> {
+        let validator = |index| Validator {
+            effective_balance: index * 1_000_000_000,
+            exit_epoch: index,
+            ..Validator::default()
+        };
+        BeaconState {
+            slot: 17,
+            validators: VariableList::from((0..10).map(validator).collect::<Vec<_>>()),
+            balances: VariableList::from((0..10).map(|index| index * 3).collect::<Vec<_>>()),
+            finalized_checkpoint: Checkpoint {
+                epoch: 2,
+                root: H256::repeat_byte(7),
+            },
+            ..BeaconState::default()
+        }
+    }
+
+    fn root(state: &BeaconState<MinimalConfig>) -> H256 {
+        H256::from_slice(state.tree_hash_root().as_slice())
+    }
+
+    #[test]
+    fn generalized_indices_of_container_fields() {
+        let checkpoint = Checkpoint::default();
+        assert_eq!(
+            get_generalized_index(&checkpoint, &[PathElement::Field("epoch")]),
+            Ok(2),
+        );
+        assert_eq!(
+            get_generalized_index(&checkpoint, &[PathElement::Field("root")]),
+            Ok(3),
+        );
+        // `BeaconState` has 20 fields, which are padded to 32 chunks.
+        assert_eq!(
+            get_generalized_index(&state(), &[PathElement::Field("genesis_time")]),
+            Ok(32),
+        );
+        assert_eq!(
+            get_generalized_index(
+                &state(),
+                &[
+                    PathElement::Field("finalized_checkpoint"),
+                    PathElement::Field("root")
+                ],
+            ),
+            Ok((32 + 19) * 2 + 1),
+        );
+    }
+
+    #[test]
+    fn generalized_indices_of_list_elements() {
+        let state = state();
+        let validator_registry_depth = 40;
+        // 4 balances fit in a chunk, so the tree of balances is 2 levels shallower.
+        assert_eq!(
+            get_generalized_index(
+                &state,
+                &[PathElement::Field("balances"), PathElement::Index(5)],
+            ),
+            Ok((((32 + 11) * 2) << (validator_registry_depth - 2)) + 1),
+        );
+        assert_eq!(
+            get_generalized_index(
+                &state,
+                &[PathElement::Field("balances"), PathElement::Length]
+            ),
+            Ok((32 + 11) * 2 + 1),
+        );
+    }
+
+    #[test]
+    fn root_node_is_hash_tree_root() {
+        let state = state();
+        assert_eq!(get_node(&state, 1), Ok(root(&state)));
+    }
+
+    #[test]
+    fn single_proof_verifies() {
+        let state = state();
+        let path = [
+            PathElement::Field("finalized_checkpoint"),
+            PathElement::Field("root"),
+        ];
+        let proof = get_multiproof(&state, &[&path]).expect("path is valid");
+
+        assert_eq!(proof.leaves, vec![H256::repeat_byte(7)]);
+        assert_eq!(
+            verify_merkle_proof(
+                proof.leaves[0],
+                proof.proof.as_slice(),
+                0,
+                proof.indices[0],
+                root(&state),
+            ),
+            Ok(true),
+        );
+    }
+
+    #[test]
+    fn multiproof_of_validator_and_balances_verifies() {
+        let state = state();
+        let validator_path = [
+            PathElement::Field("validators"),
+            PathElement::Index(7),
+            PathElement::Field("exit_epoch"),
+        ];
+        let balance_path = [PathElement::Field("balances"), PathElement::Index(9)];
+        let length_path = [PathElement::Field("balances"), PathElement::Length];
+        let proof = get_multiproof(&state, &[&validator_path, &balance_path, &length_path])
+            .expect("paths are valid");
+
+        let mut exit_epoch_chunk = H256::zero();
+        exit_epoch_chunk.as_bytes_mut()[..8].copy_from_slice(&7_u64.to_le_bytes());
+        assert_eq!(proof.leaves[0], exit_epoch_chunk);
+        assert_eq!(proof.leaves[2], length_chunk(10));
+
+        assert_eq!(
+            verify_merkle_multiproof(
+                proof.leaves.as_slice(),
+                proof.proof.as_slice(),
+                proof.indices.as_slice(),
+                root(&state),
+            ),
+            Ok(true),
+        );
+
+        let mut tampered = state.clone();
+        tampered.balances[9] += 1;
+        assert_eq!(
+            verify_merkle_multiproof(
+                proof.leaves.as_slice(),
+                proof.proof.as_slice(),
+                proof.indices.as_slice(),
+                root(&tampered),
+            ),
+            Ok(false),
+        );
+    }
+
+    #[test]
+    fn unknown_paths_are_rejected() {
+        let state = state();
+        assert_eq!(
+            get_generalized_index(&state, &[PathElement::Field("shard_states")]),
+            Err(ProofError::UnknownField),
+        );
+        assert_eq!(
+            get_generalized_index(
+                &state,
+                &[PathElement::Field("slot"), PathElement::Field("epoch")],
+            ),
+            Err(ProofError::NotComposite),
+        );
+    }
+}
@@ -84,6 +84,71 @@ pub fn compute_shuffled_index<C: Config>(
     Ok(ind)
 }
 
+/// Permutes `list` so that `list[i]` is replaced by `list[compute_shuffled_index(i)]`.
+///
+/// Equivalent to calling `compute_shuffled_index` for every index but only hashes once per 256
+/// indices per round. This is the optimized algorithm used by other clients.
+/// Committees are ranges of the shuffled list of active validators (see `compute_committee`).
+pub fn shuffle_list<C: Config>(list: &mut [ValidatorIndex], seed: &H256) {
+    // `compute_shuffled_index` applies the rounds to an index in ascending order.
+    // Applying them to the list in descending order has the same effect.
+    apply_rounds(list, seed, (0..C::shuffle_round_count()).rev())
+}
+
+/// The inverse of `shuffle_list`. Moves `list[i]` to `compute_shuffled_index(i)`.
+pub fn unshuffle_list<C: Config>(list: &mut [ValidatorIndex], seed: &H256) {
+    apply_rounds(list, seed, 0..C::shuffle_round_count())
+}
+
+fn apply_rounds(list: &mut [ValidatorIndex], seed: &H256, rounds: impl Iterator<Item = u64>) {
+    let list_size = list.len();
+    if list_size == 0 {
+        return;
+    }
+
+    // The seed, the round and the position divided by 256.
+    let mut buffer = [0; 37];
+    buffer[..32].copy_from_slice(seed.as_bytes());
+
+    for round in rounds {
+        buffer[32] = round as u8;
+        let mut pivot_bytes = [0; 8];
+        pivot_bytes.copy_from_slice(&hash(&buffer[..33])[..8]);
+        let pivot = (u64::from_le_bytes(pivot_bytes) % list_size as u64) as usize;
+
+        // Every index `i` is swapped with `pivot - i` (mod `list_size`) or left in place depending
+        // on a bit derived from the greater of the two. Each round is its own inverse.
+        swap_or_not(list, &mut buffer, 0..(pivot + 1) / 2, pivot);
+        swap_or_not(
+            list,
+            &mut buffer,
+            pivot + 1..(pivot + list_size + 1) / 2,
+            list_size - 1,
+        );
+    }
+}
+
+// Swaps the elements at indices in `lower` with the ones at `upper`, `upper - 1` and so on.
+fn swap_or_not(
+    list: &mut [ValidatorIndex],
+    buffer: &mut [u8; 37],
+    lower: core::ops::Range<usize>,
+    upper: usize,
+) {
+    let mut source = vec![];
+    for (offset, i) in lower.enumerate() {
+        let j = upper - offset;
+        if offset == 0 || j % 256 == 255 {
+            buffer[33..].copy_from_slice(&((j / 256) as u32).to_le_bytes());
+            source = hash(&buffer[..]);
+        }
+        let byte = source[(j % 256) / 8];
+        if (byte >> (j % 8)) & 1 == 1 {
+            list.swap(i, j);
+        }
+    }
+}
+
 pub fn compute_proposer_index<C: Config>(
     state: &BeaconState<C>,
     indices: &[ValidatorIndex],
@@ -154,6 +219,8 @@ pub fn compute_committee<'a, C: Config>(
 mod tests {
     use super::*;
     use bls::{PublicKey, SecretKey};
+    use serde::Deserialize;
+    use std::{fs::File, path::Path};
     use types::config::MinimalConfig;
     use types::consts::FAR_FUTURE_EPOCH;
     use types::types::Validator;
@@ -246,4 +313,106 @@ mod tests {
             compute_committee::<MinimalConfig>(&test_vec, &H256::random(), 2, 20).expect("");
         assert_eq!(5, committee.len());
     }
+
+    fn shuffled_indices(count: u64, seed: &H256) -> Vec<ValidatorIndex> {
+        (0..count)
+            .map(|index| compute_shuffled_index::<MinimalConfig>(index, count, seed).expect(""))
+            .collect()
+    }
+
+    #[test]
+    fn test_shuffle_list_matches_compute_shuffled_index() {
+        for count in &[0, 1, 2, 3, 31, 255, 256, 257, 1000] {
+            for _ in 0..5 {
+                let seed = H256::random();
+                let mut list = (0..*count).collect::<Vec<_>>();
+                shuffle_list::<MinimalConfig>(list.as_mut_slice(), &seed);
+                assert_eq!(list, shuffled_indices(*count, &seed));
+            }
+        }
+    }
+
+    #[test]
+    fn test_shuffle_list_permutes_values() {
+        let seed = H256::random();
+        let original = (0..300).map(|index| index * 7 + 3).collect::<Vec<_>>();
+        let mut list = original.clone();
+        shuffle_list::<MinimalConfig>(list.as_mut_slice(), &seed);
+
+        for (index, shuffled_index) in shuffled_indices(300, &seed).into_iter().enumerate() {
+            assert_eq!(list[index], original[shuffled_index as usize]);
+        }
+    }
+
+    #[test]
+    fn test_unshuffle_list_inverts_shuffle_list() {
+        for count in &[1, 2, 100, 256, 513] {
+            let seed = H256::random();
+            let original = (0..*count).collect::<Vec<_>>();
+
+            let mut list = original.clone();
+            shuffle_list::<MinimalConfig>(list.as_mut_slice(), &seed);
+            unshuffle_list::<MinimalConfig>(list.as_mut_slice(), &seed);
+            assert_eq!(list, original);
+
+            let mut list = original.clone();
+            unshuffle_list::<MinimalConfig>(list.as_mut_slice(), &seed);
+            for (index, shuffled_index) in shuffled_indices(*count, &seed).into_iter().enumerate() {
+                assert_eq!(list[shuffled_index as usize], original[index]);
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct ShufflingTest {
+        seed: H256,
+        count: u64,
+        mapping: Vec<ValidatorIndex>,
+    }
+
+    fn run_shuffling_tests(directory: &Path) {
+        let cases = directory.read_dir().expect("spec tests should be present");
+
+        for case in cases {
+            let path = case.expect("").path().join("mapping.yaml");
+            let file = File::open(&path).expect("test case should contain mapping.yaml");
+            let test: ShufflingTest = serde_yaml::from_reader(file).expect("");
+
+            let mut list = (0..test.count).collect::<Vec<_>>();
+            shuffle_list::<MinimalConfig>(list.as_mut_slice(), &test.seed);
+            assert_eq!(list, test.mapping, "{:?}", path);
+            assert_eq!(
+                shuffled_indices(test.count, &test.seed),
+                test.mapping,
+                "{:?}",
+                path
+            );
+
+            unshuffle_list::<MinimalConfig>(list.as_mut_slice(), &test.seed);
+            assert_eq!(list, (0..test.count).collect::<Vec<_>>(), "{:?}", path);
+        }
+    }
+
+    // A few of the minimal shuffling tests, generated the same way as the ones released in
+    // <https://github.com/ethereum/eth2.0-spec-tests>. Seeds are hashes of 4 byte little-endian
+    // integers and `mapping[i]` is `compute_shuffled_index(i, count, seed)` from the specification.
+    #[test]
+    fn test_shuffle_list_checked_in_spec_tests() {
+        run_shuffling_tests(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("test_vectors/minimal/phase0/shuffling/core/shuffle"),
+        );
+    }
+
+    // The full set of test vectors is not part of the repository. To run this, extract the release
+    // of <https://github.com/ethereum/eth2.0-spec-tests> matching the specification into
+    // `eth2.0-spec-tests` in the root of the repository and pass `--ignored` to the test binary.
+    #[test]
+    #[ignore]
+    fn test_shuffle_list_spec_tests() {
+        run_shuffling_tests(
+            &Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../../eth2.0-spec-tests/tests/minimal/phase0/shuffling/core/shuffle"),
+        );
+    }
 }
@@ -1,4 +1,7 @@
-use crate::{beacon_state_accessors as accessors, crypto};
+use crate::{
+    beacon_state_accessors as accessors,
+    crypto::{self, SignatureSet},
+};
 use bls::AggregatePublicKey;
 use itertools::Itertools;
 use ssz_types::VariableList;
@@ -61,30 +64,9 @@ pub fn validate_indexed_attestation<C: Config>(
     state: &BeaconState<C>,
     indexed_attestation: &IndexedAttestation<C>,
 ) -> Result<(), Error> {
-    let indices = &indexed_attestation.attesting_indices;
-
-    let max_validators = C::MaxValidatorsPerCommittee::to_usize();
-    if indices.len() > max_validators {
-        return Err(Error::IndicesExceedMaxValidators);
-    }
-
-    if !is_sorted(indices) {
-        return Err(Error::IndicesNotSorted);
-    }
+    validate_attesting_indices(indexed_attestation)?;
 
-    let aggr_pubkey = aggregate_validator_public_keys(indices, state)?;
-
-    let hash = indexed_attestation.data.tree_hash_root();
-
-    if indexed_attestation.signature.verify_multiple(
-        &[hash.as_slice()],
-        accessors::get_domain(
-            state,
-            C::domain_attestation(),
-            Some(indexed_attestation.data.target.epoch),
-        ),
-        &[&aggr_pubkey],
-    ) {
+    if indexed_attestation_signature_set(state, indexed_attestation)?.is_valid() {
         Ok(())
     } else {
         Err(Error::InvalidSignature)
@@ -111,6 +93,44 @@ pub fn validate_indexed_attestation<C: Config>(
     // }
 }
 
+/// The part of `validate_indexed_attestation` that does not involve the signature.
+pub fn validate_attesting_indices<C: Config>(
+    indexed_attestation: &IndexedAttestation<C>,
+) -> Result<(), Error> {
+    let indices = &indexed_attestation.attesting_indices;
+
+    let max_validators = C::MaxValidatorsPerCommittee::to_usize();
+    if indices.len() > max_validators {
+        return Err(Error::IndicesExceedMaxValidators);
+    }
+
+    if !is_sorted(indices) {
+        return Err(Error::IndicesNotSorted);
+    }
+
+    Ok(())
+}
+
+/// The signature checked by `validate_indexed_attestation`.
+pub fn indexed_attestation_signature_set<C: Config>(
+    state: &BeaconState<C>,
+    indexed_attestation: &IndexedAttestation<C>,
+) -> Result<SignatureSet, Error> {
+    let aggr_pubkey =
+        aggregate_validator_public_keys(&indexed_attestation.attesting_indices, state)?;
+
+    Ok(SignatureSet::new(
+        indexed_attestation.signature.clone(),
+        aggr_pubkey,
+        H256::from_slice(&indexed_attestation.data.tree_hash_root()),
+        accessors::get_domain(
+            state,
+            C::domain_attestation(),
+            Some(indexed_attestation.data.target.epoch),
+        ),
+    ))
+}
+
 pub fn is_valid_merkle_branch(
     leaf: &H256,
     branch: &[H256],
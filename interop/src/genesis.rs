@@ -0,0 +1,120 @@
+//! Genesis states with deposits made by interop validators.
+//!
+//! Based on `initialize_beacon_state_from_eth1` in the specification, except that `genesis_time`
+//! is chosen by the caller and validators are added directly instead of processing deposits:
+//! <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/core/0_beacon-chain.md#genesis>
+
+use bls::{PublicKey, PublicKeyBytes, Signature, SignatureBytes};
+use helper_functions::{
+    crypto::{hash, hash_tree_root, signed_root},
+    misc::compute_domain,
+};
+use merkle_proof::DepositTree;
+use ssz_types::FixedVector;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    consts::FAR_FUTURE_EPOCH,
+    primitives::{UnixSeconds, H256},
+    types::{BeaconBlockBody, BeaconBlockHeader, DepositData, Eth1Data, Validator},
+};
+
+use crate::keys;
+
+/// The eth1 block hash used in the interoperability standard.
+pub const ETH1_BLOCK_HASH: H256 = H256([0x42; 32]);
+
+/// Returns a state with `validator_count` active validators, each with the maximum balance.
+pub fn genesis_state<C: Config>(validator_count: u64, genesis_time: UnixSeconds) -> BeaconState<C> {
+    let mut deposit_tree = DepositTree::new();
+    let mut validators = vec![];
+    let mut balances = vec![];
+
+    for validator_index in 0..validator_count {
+        let deposit_data = deposit_data::<C>(validator_index);
+
+        deposit_tree
+            .push(hash_tree_root(&deposit_data))
+            .expect("deposit tree should have room for interop deposits");
+
+        validators.push(Validator {
+            pubkey: keys::public_key(validator_index),
+            withdrawal_credentials: deposit_data.withdrawal_credentials,
+            effective_balance: C::max_effective_balance(),
+            slashed: false,
+            activation_eligibility_epoch: C::genesis_epoch(),
+            activation_epoch: C::genesis_epoch(),
+            exit_epoch: FAR_FUTURE_EPOCH,
+            withdrawable_epoch: FAR_FUTURE_EPOCH,
+        });
+        balances.push(deposit_data.amount);
+    }
+
+    BeaconState {
+        genesis_time,
+        latest_block_header: BeaconBlockHeader {
+            body_root: hash_tree_root(&BeaconBlockBody::<C>::default()),
+            ..BeaconBlockHeader::default()
+        },
+        eth1_data: Eth1Data {
+            deposit_root: deposit_tree.root(),
+            deposit_count: validator_count,
+            block_hash: ETH1_BLOCK_HASH,
+        },
+        eth1_deposit_index: validator_count,
+        validators: validators.into(),
+        balances: balances.into(),
+        randao_mixes: FixedVector::from_elem(ETH1_BLOCK_HASH),
+        ..BeaconState::default()
+    }
+}
+
+/// Returns the signed deposit of the maximum effective balance made by the validator.
+pub fn deposit_data<C: Config>(validator_index: u64) -> DepositData {
+    let secret_key = keys::secret_key(validator_index);
+    let pubkey = PublicKey::from_secret_key(&secret_key).as_bytes();
+
+    let mut withdrawal_credentials = H256::from_slice(hash(pubkey.as_slice()).as_slice());
+    withdrawal_credentials.as_bytes_mut()[0] = C::bls_withdrawal_prefix_byte();
+
+    let mut deposit_data = DepositData {
+        pubkey: PublicKeyBytes::from_bytes(pubkey.as_slice())
+            .expect("compressed public key should have the right length"),
+        withdrawal_credentials,
+        amount: C::max_effective_balance(),
+        signature: SignatureBytes::empty(),
+    };
+
+    // Deposits are valid across forks, so the domain does not depend on the fork version.
+    let signature = Signature::new(
+        signed_root(&deposit_data).as_bytes(),
+        compute_domain(C::domain_deposit(), None),
+        &secret_key,
+    );
+    deposit_data.signature = SignatureBytes::from_bytes(signature.as_bytes().as_slice())
+        .expect("signature should have the right length");
+
+    deposit_data
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    #[test]
+    fn genesis_state_matches_interop_genesis_state_in_scripts() {
+        let genesis_time = 1_578_009_600;
+        let yaml = include_str!("../../scripts/interop_minimal_genesis_state.yaml.erb")
+            .replace("<%= genesis_time %>", genesis_time.to_string().as_str());
+        let expected: BeaconState<MinimalConfig> =
+            serde_yaml::from_str(yaml.as_str()).expect("state in scripts should be valid");
+
+        let actual = genesis_state::<MinimalConfig>(64, genesis_time);
+
+        assert_eq!(actual.validators, expected.validators);
+        assert_eq!(actual.eth1_data, expected.eth1_data);
+        assert_eq!(actual.canonical_root(), expected.canonical_root());
+    }
+}
@@ -0,0 +1,34 @@
+//! Deterministic validator keys.
+//!
+//! See <https://github.com/ethereum/eth2.0-pm/tree/525650511543073a80e24602eb3619d31e721249/interop/mocked_start#pubkeyprivkey-generation>.
+
+use core::convert::TryInto as _;
+
+use bls::{PublicKey, SecretKey};
+use types::primitives::ValidatorIndex;
+
+pub fn secret_key(validator_index: ValidatorIndex) -> SecretKey {
+    let validator_index = validator_index
+        .try_into()
+        .expect("validator index should fit in usize");
+    let bytes = eth2_interop_keypairs::be_private_key(validator_index);
+    SecretKey::from_bytes(&bytes).expect("interop secret keys should be valid")
+}
+
+pub fn public_key(validator_index: ValidatorIndex) -> PublicKey {
+    PublicKey::from_secret_key(&secret_key(validator_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_key_matches_interop_standard() {
+        assert_eq!(
+            hex::encode(public_key(0).as_bytes()),
+            "a99a76ed7796f7be22d5b7e85deeb7c5677e88e511e0b337618f8c4eb61349b4\
+             bf2d153f649f7b53359fe8b94a38e44c",
+        );
+    }
+}
@@ -0,0 +1,10 @@
+//! Validator keys, genesis states and validator duties following the [Ethereum 2.0
+//! interoperability standard for mocked start][standard].
+//!
+//! This is meant for tests and local testnets. Nothing here protects the keys in any way.
+//!
+//! [standard]: https://github.com/ethereum/eth2.0-pm/tree/525650511543073a80e24602eb3619d31e721249/interop/mocked_start
+
+pub mod genesis;
+pub mod keys;
+pub mod production;
@@ -0,0 +1,225 @@
+//! Producing blocks and attestations signed with interop keys.
+//!
+//! Based on the validator guide:
+//! <https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/specs/validator/0_beacon-chain-validator.md>
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use bls::{AggregateSignature, Signature};
+use error_utils::DebugAsError;
+use helper_functions::{
+    beacon_state_accessors::{
+        get_attestation_duties, get_beacon_proposer_index, get_block_root, get_current_epoch,
+        get_domain, get_previous_epoch,
+    },
+    crypto::{hash_tree_root, signed_root},
+    misc::{compute_epoch_at_slot, compute_start_slot_at_epoch},
+};
+use ssz_types::BitList;
+use transition_functions::{
+    blocks::{block_processing, block_signatures::VerifySignatures},
+    process_slot,
+};
+use typenum::Unsigned as _;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    primitives::{Slot, ValidatorIndex, H256},
+    types::{Attestation, AttestationData, BeaconBlock, BeaconBlockBody, Checkpoint},
+};
+
+use crate::keys;
+
+/// Produces a block for `slot` on top of the block that produced `parent_state`.
+///
+/// Attestations that cannot be included in the block are skipped, so `attestations` may contain
+/// anything the proposer has seen. The block is signed by whichever validator is the proposer.
+pub fn produce_block<'a, C: Config>(
+    parent_state: &BeaconState<C>,
+    slot: Slot,
+    attestations: impl IntoIterator<Item = &'a Attestation<C>>,
+) -> Result<BeaconBlock<C>> {
+    let mut state = parent_state.clone();
+    if state.slot < slot {
+        process_slot::process_slots(&mut state, slot);
+    }
+
+    let proposer_index = get_beacon_proposer_index(&state).map_err(DebugAsError::new)?;
+    let secret_key = keys::secret_key(proposer_index);
+    let epoch = get_current_epoch(&state);
+
+    let attestations = attestations
+        .into_iter()
+        .filter(|attestation| can_include(&state, attestation))
+        .take(C::MaxAttestations::USIZE)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut block = BeaconBlock {
+        slot,
+        parent_root: signed_root(&state.latest_block_header),
+        body: BeaconBlockBody {
+            randao_reveal: Signature::new(
+                hash_tree_root(&epoch).as_bytes(),
+                get_domain(&state, C::domain_randao(), None),
+                &secret_key,
+            ),
+            eth1_data: state.eth1_data.clone(),
+            attestations: attestations.into(),
+            ..BeaconBlockBody::default()
+        },
+        ..BeaconBlock::default()
+    };
+
+    let domain = get_domain(&state, C::domain_beacon_proposer(), None);
+    block_processing::process_block(&mut state, &block, VerifySignatures::None);
+    block.state_root = state.canonical_root();
+    block.signature = Signature::new(signed_root(&block).as_bytes(), domain, &secret_key);
+
+    Ok(block)
+}
+
+/// Produces attestations for `slot` by the validators in `validator_indices` that are assigned to
+/// attest in it. Validators in the same committee are aggregated into a single attestation.
+///
+/// `head_state` must be the state produced by the block with root `head_root`.
+pub fn produce_attestations<C: Config>(
+    head_state: &BeaconState<C>,
+    head_root: H256,
+    slot: Slot,
+    validator_indices: &[ValidatorIndex],
+) -> Result<Vec<Attestation<C>>> {
+    let epoch = compute_epoch_at_slot::<C>(slot);
+    let epoch_start_slot = compute_start_slot_at_epoch::<C>(epoch);
+
+    // If there are no blocks between the head and the start of the epoch, the head is the block
+    // at the epoch boundary.
+    let target_root = if head_state.slot <= epoch_start_slot {
+        head_root
+    } else {
+        get_block_root(head_state, epoch).map_err(DebugAsError::new)?
+    };
+
+    let mut state = head_state.clone();
+    if state.slot < slot {
+        process_slot::process_slots(&mut state, slot);
+    }
+
+    let duties =
+        get_attestation_duties(&state, epoch, validator_indices).map_err(DebugAsError::new)?;
+
+    let mut committees = BTreeMap::new();
+    for (validator_index, duty) in validator_indices.iter().zip(duties) {
+        if let Some(duty) = duty.filter(|duty| duty.slot == slot) {
+            committees
+                .entry(duty.index)
+                .or_insert_with(Vec::new)
+                .push((*validator_index, duty));
+        }
+    }
+
+    committees
+        .into_iter()
+        .map(|(index, members)| {
+            let data = AttestationData {
+                slot,
+                index,
+                beacon_block_root: head_root,
+                source: state.current_justified_checkpoint,
+                target: Checkpoint {
+                    epoch,
+                    root: target_root,
+                },
+            };
+            let message = hash_tree_root(&data);
+            let domain = get_domain(&state, C::domain_attestation(), Some(epoch));
+
+            let committee_len = members[0].1.committee_len;
+            let mut aggregation_bits =
+                BitList::with_capacity(committee_len).map_err(DebugAsError::new)?;
+            let mut signature = AggregateSignature::new();
+
+            for (validator_index, duty) in members {
+                aggregation_bits
+                    .set(duty.committee_position, true)
+                    .map_err(DebugAsError::new)?;
+                signature.add(&Signature::new(
+                    message.as_bytes(),
+                    domain,
+                    &keys::secret_key(validator_index),
+                ));
+            }
+
+            Ok(Attestation {
+                aggregation_bits,
+                data,
+                signature,
+            })
+        })
+        .collect()
+}
+
+// See `process_attestation`.
+fn can_include<C: Config>(state: &BeaconState<C>, attestation: &Attestation<C>) -> bool {
+    let data = &attestation.data;
+
+    let in_inclusion_range = data.slot + C::min_attestation_inclusion_delay() <= state.slot
+        && state.slot <= data.slot + C::SlotsPerEpoch::U64;
+
+    let source_matches = if data.target.epoch == get_current_epoch(state) {
+        data.source == state.current_justified_checkpoint
+    } else if data.target.epoch == get_previous_epoch(state) {
+        data.source == state.previous_justified_checkpoint
+    } else {
+        false
+    };
+
+    in_inclusion_range && source_matches
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use crate::genesis;
+
+    use super::*;
+
+    #[test]
+    fn produced_block_is_valid() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let block = produce_block(&genesis_state, 1, &[])?;
+
+        let mut state = genesis_state.clone();
+        process_slot::state_transition(&mut state, &block, VerifySignatures::Individually, true);
+
+        assert_eq!(state.slot, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn produced_attestations_cover_every_committee_of_the_slot() -> Result<()> {
+        let genesis_state = genesis::genesis_state::<MinimalConfig>(16, 0);
+        let genesis_root = signed_root(&genesis_state.latest_block_header);
+        let validator_indices = (0..16).collect::<Vec<_>>();
+
+        let attestations =
+            produce_attestations(&genesis_state, genesis_root, 1, &validator_indices)?;
+
+        let mut state = genesis_state.clone();
+        process_slot::process_slots(&mut state, 1);
+        let committees =
+            helper_functions::beacon_state_accessors::get_committee_count_at_slot(&state, 1)
+                .map_err(DebugAsError::new)?;
+
+        assert_eq!(attestations.len() as u64, committees);
+        for attestation in &attestations {
+            assert!(attestation.aggregation_bits.iter().all(|bit| bit));
+        }
+
+        let block = produce_block(&genesis_state, 2, &attestations)?;
+        assert_eq!(block.body.attestations.len(), attestations.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,599 @@
+//! An in-process testnet for exercising fork choice and networking without real time or sockets.
+//!
+//! Every node is a [`Node`] with its own fork choice store. Time is simulated with [`FakeInstant`]
+//! and [`FakeSystemTime`], so a simulation runs as fast as the state transitions allow.
+//! Validators from an interop genesis state are assigned to nodes round-robin. Nodes propose
+//! blocks at the start of slots and attest at their midpoints, like the validator guide says.
+//!
+//! Objects published by a node are delivered to every other node after a random delay unless
+//! they are dropped or the nodes are separated by a [`Partition`].
+//!
+//! A node that receives a block with an unknown parent catches up with the sender by exchanging
+//! [`SyncMessage`]s with it. Nodes only interact through the methods of [`Networked`], so the
+//! exchange exercises the same code paths as syncing with real peers. The messages are subject to
+//! the same delays, drops and partitions as published objects. Only the wire protocol is not
+//! simulated.
+//!
+//! [`Networked`]:    eth2_network::Networked
+//! [`SyncMessage`]:  crate::network::SyncMessage
+//! [`FakeInstant`]:    beacon_node::fake_time::FakeInstant
+//! [`FakeSystemTime`]: beacon_node::fake_time::FakeSystemTime
+//! [`Node`]:           beacon_node::node::Node
+
+use core::{iter, ops::Range, time::Duration};
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Result};
+use beacon_node::{
+    clock::ManualClock,
+    fake_time::{FakeInstant, FakeSystemTime, Timespec},
+    node::Node,
+    slot_timer::{self, Tick},
+};
+use error_utils::DebugAsError;
+use eth2_network::{Network as _, Networked as _, PeerKey, Status};
+use helper_functions::{beacon_state_accessors::get_beacon_proposer_index, misc};
+use interop::{genesis, production};
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use thiserror::Error;
+use transition_functions::{blocks::block_signatures::VerifySignatures, process_slot};
+use typenum::Unsigned as _;
+use types::{
+    config::Config,
+    primitives::{Epoch, Slot, UnixSeconds, ValidatorIndex, H256},
+    types::Attestation,
+};
+
+use crate::network::{InMemoryNetwork, Message, SyncMessage};
+
+pub mod network;
+
+#[derive(Debug, Error)]
+enum Error {
+    #[error("minimum delay {min_delay:?} is greater than maximum delay {max_delay:?}")]
+    InvalidDelays {
+        min_delay: Duration,
+        max_delay: Duration,
+    },
+    #[error("drop probability {0} is not between 0 and 1")]
+    InvalidDropProbability(f64),
+    #[error(
+        "node {node} has finalized epoch {finalized_epoch} (expected at least {expected_epoch})"
+    )]
+    NotFinalized {
+        node: usize,
+        finalized_epoch: Epoch,
+        expected_epoch: Epoch,
+    },
+}
+
+/// Splits the network in two for a range of slots.
+///
+/// While the partition is in effect, objects are only delivered between nodes on the same side.
+/// Objects already in flight when the partition starts are still delivered.
+#[derive(Clone, Debug)]
+pub struct Partition {
+    pub slots: Range<Slot>,
+    /// Indices of the nodes on one side of the partition. All other nodes are on the other side.
+    pub nodes: Vec<usize>,
+}
+
+impl Partition {
+    fn separates(&self, slot: Slot, sender: usize, recipient: usize) -> bool {
+        self.slots.contains(&slot)
+            && self.nodes.contains(&sender) != self.nodes.contains(&recipient)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    pub node_count: usize,
+    pub validator_count: u64,
+    pub genesis_time: UnixSeconds,
+    /// Delays are chosen uniformly between `min_delay` and `max_delay` for every recipient.
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// The probability of an object not being delivered to a recipient.
+    pub drop_probability: f64,
+    pub partitions: Vec<Partition>,
+    /// How thoroughly nodes verify blocks from other nodes, whether gossiped or synced.
+    /// Signatures of attestations received on their own are always verified.
+    pub verify_signatures: VerifySignatures,
+    /// Simulations with the same configuration and seed run identically.
+    pub seed: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            node_count: 4,
+            validator_count: 64,
+            genesis_time: 0,
+            min_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            drop_probability: 0.0,
+            partitions: vec![],
+            verify_signatures: VerifySignatures::Batch,
+            seed: 0,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Event<C: Config> {
+    Tick(Tick),
+    Delivery {
+        sender: usize,
+        recipient: usize,
+        message: Message<C>,
+    },
+    Sync {
+        sender: usize,
+        recipient: usize,
+        message: SyncMessage<C>,
+    },
+}
+
+struct SimulatedNode<C: Config> {
+    index: usize,
+    node: Node<C>,
+    network: InMemoryNetwork<C>,
+    validator_indices: Vec<ValidatorIndex>,
+    // Attestations seen by the node that may still be included in blocks.
+    attestation_pool: Vec<Attestation<C>>,
+}
+
+impl<C: Config> SimulatedNode<C> {
+    fn propose(&mut self, slot: Slot) -> Result<()> {
+        let head_state = self.node.head_state();
+
+        let mut state = head_state.clone();
+        process_slot::process_slots(&mut state, slot);
+        let proposer_index = get_beacon_proposer_index(&state).map_err(DebugAsError::new)?;
+
+        if !self.validator_indices.contains(&proposer_index) {
+            return Ok(());
+        }
+
+        let block = production::produce_block(head_state, slot, &self.attestation_pool)?;
+        info!(
+            "node {} proposed block at slot {} with {} attestations",
+            self.index,
+            slot,
+            block.body.attestations.len(),
+        );
+        self.node
+            .accept_beacon_block(block.clone(), self.peer_key())?;
+        self.network.publish_beacon_block(block)
+    }
+
+    fn attest(&mut self, slot: Slot) -> Result<()> {
+        let head_root = self.node.head_root();
+        let attestations = production::produce_attestations(
+            self.node.head_state(),
+            head_root,
+            slot,
+            self.validator_indices.as_slice(),
+        )?;
+
+        for attestation in attestations {
+            self.node
+                .accept_beacon_attestation(attestation.clone(), self.peer_key())?;
+            self.attestation_pool.push(attestation.clone());
+            self.network.publish_beacon_attestation(attestation)?;
+        }
+
+        Ok(())
+    }
+
+    // `Networked` has no way to mark objects as the node's own, so they are attributed to a peer
+    // key no other node uses.
+    fn peer_key(&self) -> PeerKey {
+        PeerKey(self.index as u64)
+    }
+}
+
+pub struct Simulation<C: Config> {
+    config: SimulationConfig,
+    nodes: Vec<SimulatedNode<C>>,
+    now: FakeInstant,
+    // Shared by all nodes. Advanced along with `now`.
+    clock: ManualClock,
+    // Events are ordered by time and then by the order in which they were scheduled.
+    events: BTreeMap<(Timespec, u64), Event<C>>,
+    next_event_id: u64,
+    current_slot: Slot,
+    rng: StdRng,
+    rejected_objects: usize,
+}
+
+impl<C: Config> Simulation<C> {
+    pub fn new(config: SimulationConfig) -> Result<Self> {
+        ensure!(
+            config.min_delay <= config.max_delay,
+            Error::InvalidDelays {
+                min_delay: config.min_delay,
+                max_delay: config.max_delay,
+            },
+        );
+        ensure!(
+            0.0 <= config.drop_probability && config.drop_probability <= 1.0,
+            Error::InvalidDropProbability(config.drop_probability),
+        );
+
+        let genesis_state =
+            genesis::genesis_state::<C>(config.validator_count, config.genesis_time);
+        let node_count = config.node_count as u64;
+
+        // Simulations start at genesis.
+        let genesis = Timespec::from_secs(config.genesis_time);
+        let clock = ManualClock::new(genesis);
+
+        let nodes = (0..config.node_count)
+            .map(|index| SimulatedNode {
+                index,
+                node: Node::new(genesis_state.clone(), config.verify_signatures)
+                    .with_gossiped_block_signatures(config.verify_signatures)
+                    .with_trusted_peers((0..node_count).map(PeerKey))
+                    .with_clock(clock.clone()),
+                network: InMemoryNetwork::default(),
+                validator_indices: (0..config.validator_count)
+                    .filter(|validator_index| validator_index % node_count == index as u64)
+                    .collect(),
+                attestation_pool: vec![],
+            })
+            .collect();
+
+        let (first_tick, first_tick_instant) = slot_timer::next_tick_with_instant::<C, _, _>(
+            FakeInstant(genesis),
+            FakeSystemTime(genesis),
+            config.genesis_time,
+        )?;
+
+        let mut simulation = Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            nodes,
+            now: FakeInstant(genesis),
+            clock,
+            events: BTreeMap::new(),
+            next_event_id: 0,
+            current_slot: C::genesis_slot(),
+            rejected_objects: 0,
+        };
+
+        simulation.schedule(first_tick_instant, Event::Tick(first_tick));
+
+        Ok(simulation)
+    }
+
+    /// Processes all events that occur before the end of `slot`.
+    pub fn run_until_end_of(&mut self, slot: Slot) -> Result<()> {
+        // Slot `C::genesis_slot() + 1` starts at genesis. See `slot_timer::next_tick_with_instant`.
+        let end = Timespec::from_secs(
+            self.config.genesis_time + (slot - C::genesis_slot()) * C::SecondsPerSlot::U64,
+        );
+
+        loop {
+            let key = match self.events.keys().next() {
+                Some(key) if key.0 < end => *key,
+                _ => break,
+            };
+            let event = self
+                .events
+                .remove(&key)
+                .expect("key was taken from the map");
+            self.clock.advance(key.0 - self.now.0);
+            self.now = FakeInstant(key.0);
+            self.handle_event(event)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if any node has not finalized `epoch` or a later epoch.
+    pub fn ensure_finalized(&self, epoch: Epoch) -> Result<()> {
+        for (node, finalized_epoch) in self.finalized_epochs().into_iter().enumerate() {
+            ensure!(
+                epoch <= finalized_epoch,
+                Error::NotFinalized {
+                    node,
+                    finalized_epoch,
+                    expected_epoch: epoch,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub fn finalized_epochs(&self) -> Vec<Epoch> {
+        self.nodes
+            .iter()
+            .map(|simulated| simulated.node.finalized_checkpoint().epoch)
+            .collect()
+    }
+
+    pub fn head_roots(&self) -> Vec<H256> {
+        self.nodes
+            .iter()
+            .map(|simulated| simulated.node.head_root())
+            .collect()
+    }
+
+    pub fn node(&self, index: usize) -> &Node<C> {
+        &self.nodes[index].node
+    }
+
+    /// The number of objects from other nodes that were rejected by their recipients.
+    ///
+    /// Some rejections are expected when objects are delayed for long enough, for example
+    /// attestations from epochs that are too old by the time they are delivered.
+    pub fn rejected_objects(&self) -> usize {
+        self.rejected_objects
+    }
+
+    fn schedule(&mut self, instant: FakeInstant, event: Event<C>) {
+        self.events.insert((instant.0, self.next_event_id), event);
+        self.next_event_id += 1;
+    }
+
+    fn handle_event(&mut self, event: Event<C>) -> Result<()> {
+        match event {
+            Event::Tick(tick) => {
+                self.handle_tick(tick)?;
+                let half_slot_duration = Duration::from_secs(C::SecondsPerSlot::U64) / 2;
+                self.schedule(self.now + half_slot_duration, Event::Tick(tick.next()));
+            }
+            Event::Delivery {
+                sender,
+                recipient,
+                message,
+            } => self.deliver(sender, recipient, message),
+            Event::Sync {
+                sender,
+                recipient,
+                message,
+            } => self.handle_sync_message(sender, recipient, message),
+        }
+        self.dispatch_published();
+        Ok(())
+    }
+
+    fn handle_tick(&mut self, tick: Tick) -> Result<()> {
+        match tick {
+            Tick::SlotStart(slot) => {
+                self.current_slot = slot;
+                for simulated in &mut self.nodes {
+                    simulated.node.handle_slot_start(slot)?;
+                    simulated.attestation_pool.retain(|attestation| {
+                        slot <= attestation.data.slot + C::SlotsPerEpoch::U64
+                    });
+                    simulated.propose(slot)?;
+                }
+            }
+            Tick::SlotMidpoint(slot) => {
+                for simulated in &mut self.nodes {
+                    simulated.node.handle_slot_midpoint(slot)?;
+                    simulated.attest(slot)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn dispatch_published(&mut self) {
+        for sender in 0..self.nodes.len() {
+            for message in self.nodes[sender].network.take_published() {
+                for recipient in 0..self.nodes.len() {
+                    if recipient == sender || self.is_lost(sender, recipient) {
+                        continue;
+                    }
+                    let delay = self.random_delay();
+                    let event = Event::Delivery {
+                        sender,
+                        recipient,
+                        message: message.clone(),
+                    };
+                    self.schedule(self.now + delay, event);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, sender: usize, recipient: usize, message: SyncMessage<C>) {
+        if self.is_lost(sender, recipient) {
+            return;
+        }
+        let delay = self.random_delay();
+        let event = Event::Sync {
+            sender,
+            recipient,
+            message,
+        };
+        self.schedule(self.now + delay, event);
+    }
+
+    fn random_delay(&mut self) -> Duration {
+        let delay_spread = self.config.max_delay - self.config.min_delay;
+        self.config.min_delay + delay_spread.mul_f64(self.rng.gen())
+    }
+
+    fn is_lost(&mut self, sender: usize, recipient: usize) -> bool {
+        let slot = self.current_slot;
+        let partitioned = self
+            .config
+            .partitions
+            .iter()
+            .any(|partition| partition.separates(slot, sender, recipient));
+        partitioned || self.rng.gen_bool(self.config.drop_probability)
+    }
+
+    fn deliver(&mut self, sender: usize, recipient: usize, message: Message<C>) {
+        let peer = PeerKey(sender as u64);
+        match message {
+            Message::BeaconBlock(block) => {
+                // Blocks with unknown parents are delayed by the fork choice store until the
+                // parents are synced.
+                let parent_known = self.nodes[recipient]
+                    .node
+                    .get_beacon_block(block.parent_root)
+                    .is_some();
+                self.accept(recipient, |node| node.accept_beacon_block(block, peer));
+                if !parent_known {
+                    let status = self.nodes[recipient].node.get_status();
+                    self.send(recipient, sender, SyncMessage::StatusRequest(status));
+                }
+            }
+            Message::Attestation(attestation) => {
+                self.nodes[recipient]
+                    .attestation_pool
+                    .push(attestation.clone());
+                self.accept(recipient, |node| {
+                    node.accept_beacon_attestation(attestation, peer)
+                });
+            }
+        }
+    }
+
+    // Handled much like `eth2_network_libp2p` handles the corresponding RPCs.
+    fn handle_sync_message(&mut self, sender: usize, recipient: usize, message: SyncMessage<C>) {
+        match message {
+            SyncMessage::StatusRequest(remote) => {
+                let local = self.nodes[recipient].node.get_status();
+                self.send(recipient, sender, SyncMessage::StatusResponse(local));
+                self.request_missing_blocks(recipient, sender, remote);
+            }
+            SyncMessage::StatusResponse(remote) => {
+                self.request_missing_blocks(recipient, sender, remote);
+            }
+            SyncMessage::BlocksByRangeRequest {
+                head_block_root,
+                start_slot,
+            } => {
+                let node = &self.nodes[recipient].node;
+                let mut blocks =
+                    iter::successors(node.get_beacon_block(head_block_root), |block| {
+                        node.get_beacon_block(block.parent_root)
+                    })
+                    .take_while(|block| start_slot <= block.slot)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                blocks.reverse();
+                self.send(
+                    recipient,
+                    sender,
+                    SyncMessage::BlocksByRangeResponse(blocks),
+                );
+            }
+            SyncMessage::BlocksByRangeResponse(blocks) => {
+                let peer = PeerKey(sender as u64);
+                for block in blocks {
+                    self.accept(recipient, |node| {
+                        node.accept_synced_beacon_block(block, peer)
+                    });
+                }
+            }
+        }
+    }
+
+    // Unlike `eth2_network_libp2p`, this requests blocks whenever the remote head is unknown rather
+    // than only when the remote node is ahead. Nodes on different forks need each other's blocks
+    // to agree on the head.
+    fn request_missing_blocks(&mut self, local: usize, remote: usize, remote_status: Status) {
+        let node = &self.nodes[local].node;
+        if node.get_beacon_block(remote_status.head_root).is_some() {
+            return;
+        }
+        // Blocks before the local finalized epoch are either known or not descendants of the
+        // local finalized block, so there is no point in requesting them.
+        let start_slot = misc::compute_start_slot_at_epoch::<C>(node.get_status().finalized_epoch);
+        let request = SyncMessage::BlocksByRangeRequest {
+            head_block_root: remote_status.head_root,
+            start_slot,
+        };
+        self.send(local, remote, request);
+    }
+
+    fn accept(&mut self, recipient: usize, accept: impl FnOnce(&mut Node<C>) -> Result<()>) {
+        if let Err(error) = accept(&mut self.nodes[recipient].node) {
+            warn!("node {} rejected object: {}", recipient, error);
+            self.rejected_objects += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::config::MinimalConfig;
+
+    use super::*;
+
+    #[test]
+    fn nodes_agree_on_head_without_faults() -> Result<()> {
+        let mut simulation = Simulation::<MinimalConfig>::new(SimulationConfig {
+            node_count: 2,
+            validator_count: 16,
+            ..SimulationConfig::default()
+        })?;
+
+        simulation.run_until_end_of(3)?;
+
+        let head_roots = simulation.head_roots();
+        assert_eq!(head_roots[0], head_roots[1]);
+        assert_eq!(simulation.node(0).head_state().slot, 3);
+        assert_eq!(simulation.rejected_objects(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_drop_probability() {
+        let config = SimulationConfig {
+            drop_probability: 1.5,
+            ..SimulationConfig::default()
+        };
+
+        assert!(Simulation::<MinimalConfig>::new(config).is_err());
+    }
+
+    // Verifying every signature makes the simulations below take minutes without optimizations.
+    // Proposer signatures are enough to tell blocks from different nodes apart.
+    fn fast_config() -> SimulationConfig {
+        SimulationConfig {
+            validator_count: 16,
+            verify_signatures: VerifySignatures::ProposerOnly,
+            ..SimulationConfig::default()
+        }
+    }
+
+    #[test]
+    fn chain_finalizes_despite_delays_and_drops() -> Result<()> {
+        let mut simulation = Simulation::<MinimalConfig>::new(SimulationConfig {
+            max_delay: Duration::from_secs(2),
+            drop_probability: 0.05,
+            ..fast_config()
+        })?;
+
+        simulation.run_until_end_of(5 * <MinimalConfig as Config>::SlotsPerEpoch::U64)?;
+
+        simulation.ensure_finalized(2)
+    }
+
+    #[test]
+    fn minority_partition_catches_up_after_healing() -> Result<()> {
+        let slots_per_epoch = <MinimalConfig as Config>::SlotsPerEpoch::U64;
+        let mut simulation = Simulation::<MinimalConfig>::new(SimulationConfig {
+            partitions: vec![Partition {
+                slots: slots_per_epoch + 1..2 * slots_per_epoch + 1,
+                nodes: vec![0],
+            }],
+            ..fast_config()
+        })?;
+
+        simulation.run_until_end_of(7 * slots_per_epoch)?;
+
+        let head_roots = simulation.head_roots();
+        assert!(head_roots.iter().all(|root| *root == head_roots[0]));
+        simulation.ensure_finalized(3)
+    }
+}
@@ -0,0 +1,74 @@
+//! An implementation of [`Network`] that keeps published objects in memory.
+//!
+//! Objects are not delivered by the network itself. The simulation takes them out with
+//! [`InMemoryNetwork::take_published`] and decides when (and whether) other nodes receive them.
+//!
+//! [`Network`]: eth2_network::Network
+
+use core::{cell::RefCell, mem};
+
+use anyhow::Result;
+use eth2_network::{Network, Status};
+use types::{
+    config::Config,
+    primitives::{Slot, H256},
+    types::{Attestation, BeaconBlock},
+};
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum Message<C: Config> {
+    BeaconBlock(BeaconBlock<C>),
+    Attestation(Attestation<C>),
+}
+
+/// Requests and responses exchanged by a pair of nodes to catch up with each other.
+///
+/// They mirror the `Status` and `BlocksByRange` RPCs used by `eth2_network_libp2p`.
+#[derive(Clone, Debug)]
+pub enum SyncMessage<C: Config> {
+    StatusRequest(Status),
+    StatusResponse(Status),
+    BlocksByRangeRequest {
+        head_block_root: H256,
+        start_slot: Slot,
+    },
+    /// Blocks ordered by slot.
+    BlocksByRangeResponse(Vec<BeaconBlock<C>>),
+}
+
+// `Network` methods take `&self`, hence the `RefCell`.
+pub struct InMemoryNetwork<C: Config> {
+    published: RefCell<Vec<Message<C>>>,
+}
+
+impl<C: Config> Default for InMemoryNetwork<C> {
+    fn default() -> Self {
+        Self {
+            published: RefCell::default(),
+        }
+    }
+}
+
+impl<C: Config> InMemoryNetwork<C> {
+    /// Returns the objects published since the last call in the order they were published.
+    pub fn take_published(&self) -> Vec<Message<C>> {
+        mem::replace(&mut self.published.borrow_mut(), vec![])
+    }
+}
+
+impl<C: Config> Network<C> for InMemoryNetwork<C> {
+    fn publish_beacon_block(&self, beacon_block: BeaconBlock<C>) -> Result<()> {
+        self.published
+            .borrow_mut()
+            .push(Message::BeaconBlock(beacon_block));
+        Ok(())
+    }
+
+    fn publish_beacon_attestation(&self, attestation: Attestation<C>) -> Result<()> {
+        self.published
+            .borrow_mut()
+            .push(Message::Attestation(attestation));
+        Ok(())
+    }
+}
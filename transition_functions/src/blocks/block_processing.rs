@@ -1,11 +1,13 @@
+use crate::blocks::block_signatures::{verify_block_signatures, VerifySignatures};
+use crate::observer::{observe, NoopObserver, StateTransitionObserver, Step};
 use helper_functions::beacon_state_accessors::*;
 use helper_functions::beacon_state_mutators::*;
 use helper_functions::crypto::{bls_verify, hash, hash_tree_root, signed_root};
 use helper_functions::math::*;
-use helper_functions::misc::{compute_domain, compute_epoch_at_slot};
+use helper_functions::misc::compute_domain;
 use helper_functions::predicates::{
     is_active_validator, is_slashable_attestation_data, is_slashable_validator,
-    is_valid_merkle_branch, validate_indexed_attestation,
+    is_valid_merkle_branch, validate_attesting_indices,
 };
 use std::collections::BTreeSet;
 use std::convert::TryInto;
@@ -18,11 +20,35 @@ use types::{
     types::VoluntaryExit,
 };
 
-pub fn process_block<T: Config>(state: &mut BeaconState<T>, block: &BeaconBlock<T>) {
-    process_block_header(state, &block);
-    process_randao(state, &block.body);
-    process_eth1_data(state, &block.body);
-    process_operations(state, &block.body);
+pub fn process_block<T: Config>(
+    state: &mut BeaconState<T>,
+    block: &BeaconBlock<T>,
+    verify_signatures: VerifySignatures,
+) {
+    process_block_with_observer(state, block, verify_signatures, &mut NoopObserver)
+}
+
+/// Like [`process_block`], but reports every step and operation to `observer`.
+pub fn process_block_with_observer<T: Config>(
+    state: &mut BeaconState<T>,
+    block: &BeaconBlock<T>,
+    verify_signatures: VerifySignatures,
+    observer: &mut dyn StateTransitionObserver<T>,
+) {
+    //# Signatures of everything except deposits are verified up front
+    if let Err(error) = verify_block_signatures(state, block, verify_signatures) {
+        panic!("block signature verification failed: {:?}", error);
+    }
+    observe(state, observer, Step::BlockHeader, |state| {
+        process_block_header(state, block)
+    });
+    observe(state, observer, Step::Randao, |state| {
+        process_randao(state, &block.body)
+    });
+    observe(state, observer, Step::Eth1Data, |state| {
+        process_eth1_data(state, &block.body)
+    });
+    process_operations(state, &block.body, observer);
 }
 
 fn process_voluntary_exit<T: Config>(state: &mut BeaconState<T>, exit: &VoluntaryExit) {
@@ -37,15 +63,7 @@ fn process_voluntary_exit<T: Config>(state: &mut BeaconState<T>, exit: &Voluntar
     assert!(
         get_current_epoch(state) >= validator.activation_epoch + T::persistent_committee_period()
     );
-    // Verify signature
-    let domain = get_domain(state, T::domain_voluntary_exit() as u32, Some(exit.epoch));
-    assert!(bls_verify(
-        &(bls::PublicKeyBytes::from_bytes(&validator.pubkey.as_bytes()).unwrap()),
-        signed_root(exit).as_bytes(),
-        &(exit.signature.clone()).try_into().unwrap(),
-        domain
-    )
-    .unwrap());
+    // Signature is verified in `verify_block_signatures`
     // Initiate exit
     initiate_validator_exit(state, exit.validator_index).unwrap();
 }
@@ -68,13 +86,10 @@ fn process_deposit<T: Config>(state: &mut BeaconState<T>, deposit: &Deposit) {
     let pubkey = &deposit.data.pubkey;
     let amount = &deposit.data.amount;
 
-    for (index, v) in state.validators.iter().enumerate() {
-        // bls::PublicKeyBytes::from_bytes(&v.pubkey.as_bytes()).unwrap()
-        if bls::PublicKeyBytes::from_bytes(&v.pubkey.as_bytes()).unwrap() == *pubkey {
-            //# Increase balance by deposit amount
-            increase_balance(state, index as u64, *amount).unwrap();
-            return;
-        }
+    if let Some(index) = state.pubkey_cache.get(&state.validators, pubkey) {
+        //# Increase balance by deposit amount
+        increase_balance(state, index, *amount).unwrap();
+        return;
     }
     //# Verify the deposit signature (proof of possession) for new validators.
     //# Note: The deposit contract does not check signatures.
@@ -128,27 +143,12 @@ fn process_block_header<T: Config>(state: &mut BeaconState<T>, block: &BeaconBlo
     //# Verify proposer is not slashed
     let proposer = &state.validators[get_beacon_proposer_index(&state).unwrap() as usize];
     assert!(!proposer.slashed);
-    //# Verify proposer signature
-    assert!(bls_verify(
-        &bls::PublicKeyBytes::from_bytes(&proposer.pubkey.as_bytes()).unwrap(),
-        signed_root(block).as_bytes(),
-        &block.signature.clone().try_into().unwrap(),
-        get_domain(&state, T::domain_beacon_proposer() as u32, None)
-    )
-    .unwrap());
+    //# Proposer signature is verified in `verify_block_signatures`
 }
 
 fn process_randao<T: Config>(state: &mut BeaconState<T>, body: &BeaconBlockBody<T>) {
     let epoch = get_current_epoch(&state);
-    //# Verify RANDAO reveal
-    let proposer = &state.validators[get_beacon_proposer_index(&state).unwrap() as usize];
-    assert!(bls_verify(
-        &(proposer.pubkey.clone()).try_into().unwrap(),
-        hash_tree_root(&epoch).as_bytes(),
-        &(body.randao_reveal.clone()).try_into().unwrap(),
-        get_domain(&state, T::domain_randao() as u32, None)
-    )
-    .unwrap());
+    //# RANDAO reveal is verified in `verify_block_signatures`
     //# Mix in RANDAO reveal
     let mix = xor(
         get_randao_mix(&state, epoch).unwrap().as_fixed_bytes(),
@@ -178,26 +178,7 @@ fn process_proposer_slashing<T: Config>(
     assert_ne!(proposer_slashing.header_1, proposer_slashing.header_2);
     // Check proposer is slashable
     assert!(is_slashable_validator(&proposer, get_current_epoch(state)));
-    // Signatures are valid
-    let headers: [BeaconBlockHeader; 2] = [
-        proposer_slashing.header_1.clone(),
-        proposer_slashing.header_2.clone(),
-    ];
-    for header in &headers {
-        let domain = get_domain(
-            state,
-            T::domain_beacon_proposer() as u32,
-            Some(compute_epoch_at_slot::<T>(header.slot)),
-        );
-        //# Sekanti eilutė tai ******* amazing. signed_root helperiuose užkomentuota
-        assert!(bls_verify(
-            &(proposer.pubkey.clone()).try_into().unwrap(),
-            signed_root(header).as_bytes(),
-            &(header.signature.clone()).try_into().unwrap(),
-            domain
-        )
-        .unwrap());
-    }
+    // Signatures are verified in `verify_block_signatures`
 
     slash_validator(state, proposer_slashing.proposer_index, None).unwrap();
 }
@@ -212,8 +193,9 @@ fn process_attester_slashing<T: Config>(
         &attestation_1.data,
         &attestation_2.data
     ));
-    assert!(validate_indexed_attestation(state, &attestation_1).is_ok());
-    assert!(validate_indexed_attestation(state, &attestation_2).is_ok());
+    // Signatures are verified in `verify_block_signatures`
+    assert!(validate_attesting_indices(&attestation_1).is_ok());
+    assert!(validate_attesting_indices(&attestation_2).is_ok());
 
     let mut slashed_any = false;
 
@@ -279,12 +261,10 @@ fn process_attestation<T: Config>(state: &mut BeaconState<T>, attestation: &Atte
             .unwrap();
     }
 
-    //# Check signature
-    assert!(validate_indexed_attestation(
-        &state,
-        &get_indexed_attestation(&state, &attestation).unwrap()
-    )
-    .is_ok());
+    //# Signature is verified in `verify_block_signatures`
+    assert!(
+        validate_attesting_indices(&get_indexed_attestation(&state, &attestation).unwrap()).is_ok()
+    );
 }
 
 fn process_eth1_data<T: Config>(state: &mut BeaconState<T>, body: &BeaconBlockBody<T>) {
@@ -300,7 +280,11 @@ fn process_eth1_data<T: Config>(state: &mut BeaconState<T>, body: &BeaconBlockBo
     }
 }
 
-fn process_operations<T: Config>(state: &mut BeaconState<T>, body: &BeaconBlockBody<T>) {
+fn process_operations<T: Config>(
+    state: &mut BeaconState<T>,
+    body: &BeaconBlockBody<T>,
+    observer: &mut dyn StateTransitionObserver<T>,
+) {
     //# Verify that outstanding deposits are processed up to the maximum number of deposits
     assert_eq!(
         body.deposits.len(),
@@ -310,20 +294,30 @@ fn process_operations<T: Config>(state: &mut BeaconState<T>, body: &BeaconBlockB
         )
     );
 
-    for proposer_slashing in body.proposer_slashings.iter() {
-        process_proposer_slashing(state, proposer_slashing);
+    for (index, proposer_slashing) in body.proposer_slashings.iter().enumerate() {
+        observe(state, observer, Step::ProposerSlashing(index), |state| {
+            process_proposer_slashing(state, proposer_slashing)
+        });
     }
-    for attester_slashing in body.attester_slashings.iter() {
-        process_attester_slashing(state, attester_slashing);
+    for (index, attester_slashing) in body.attester_slashings.iter().enumerate() {
+        observe(state, observer, Step::AttesterSlashing(index), |state| {
+            process_attester_slashing(state, attester_slashing)
+        });
     }
-    for attestation in body.attestations.iter() {
-        process_attestation(state, attestation);
+    for (index, attestation) in body.attestations.iter().enumerate() {
+        observe(state, observer, Step::Attestation(index), |state| {
+            process_attestation(state, attestation)
+        });
     }
-    for deposit in body.deposits.iter() {
-        process_deposit(state, deposit);
+    for (index, deposit) in body.deposits.iter().enumerate() {
+        observe(state, observer, Step::Deposit(index), |state| {
+            process_deposit(state, deposit)
+        });
     }
-    for voluntary_exit in body.voluntary_exits.iter() {
-        process_voluntary_exit(state, voluntary_exit);
+    for (index, voluntary_exit) in body.voluntary_exits.iter().enumerate() {
+        observe(state, observer, Step::VoluntaryExit(index), |state| {
+            process_voluntary_exit(state, voluntary_exit)
+        });
     }
 }
 
@@ -0,0 +1,371 @@
+//! Verification of all signatures in a block with a single batch check.
+//!
+//! The signatures are collected from the state before the block is applied. None of the
+//! operations in a block change the public keys, committees or domains the other operations are
+//! verified against, so this is equivalent to verifying them one by one during processing.
+//!
+//! Deposit signatures are not included. An invalid deposit signature does not make the block
+//! invalid, so they are still verified individually in `process_deposit`.
+
+use helper_functions::beacon_state_accessors::{
+    get_beacon_committee, get_beacon_proposer_index, get_committee_count_at_slot,
+    get_current_epoch, get_domain, get_indexed_attestation,
+};
+use helper_functions::crypto::{hash_tree_root, signed_root, verify_signature_sets, SignatureSet};
+use helper_functions::misc::compute_epoch_at_slot;
+use helper_functions::predicates::indexed_attestation_signature_set;
+use serde::Deserialize;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    helper_functions_types::Error as HelperError,
+    primitives::ValidatorIndex,
+    types::{Attestation, BeaconBlock, Validator},
+};
+
+/// How thoroughly `process_block` verifies the signatures in a block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum VerifySignatures {
+    /// Verify every signature separately. Slowest, but the result does not depend on randomness.
+    Individually,
+    /// Verify all signatures with a single batch check.
+    Batch,
+    /// Verify only the signature of the block itself.
+    /// Suitable for blocks from a source that is trusted to have verified the operations in them.
+    ProposerOnly,
+    /// Verify no signatures at all. Only for blocks that are known to be valid and for fuzzing.
+    /// Deposit signatures are still verified because they determine the resulting state.
+    None,
+}
+
+impl Default for VerifySignatures {
+    fn default() -> Self {
+        VerifySignatures::Batch
+    }
+}
+
+/// An operation in a block that carries a signature.
+/// Operations in the block body are identified by their position in the corresponding list.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SignedOperation {
+    Block,
+    RandaoReveal,
+    ProposerSlashing(usize),
+    AttesterSlashing(usize),
+    Attestation(usize),
+    VoluntaryExit(usize),
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// The signature of the operation could not be collected. The block is invalid.
+    Operation(SignedOperation, HelperError),
+    InvalidSignature(SignedOperation),
+}
+
+/// Verifies the signatures of `block` and all operations in it except deposits according to
+/// `verify_signatures`.
+///
+/// If a batch is invalid, the signatures are verified individually to find the offending operation.
+pub fn verify_block_signatures<C: Config>(
+    state: &BeaconState<C>,
+    block: &BeaconBlock<C>,
+    verify_signatures: VerifySignatures,
+) -> Result<(), Error> {
+    let sets = match verify_signatures {
+        VerifySignatures::Individually => block_signature_sets(state, block)?,
+        VerifySignatures::Batch => {
+            let sets = block_signature_sets(state, block)?;
+            if verify_signature_sets(sets.iter().map(|(_, set)| set)) {
+                return Ok(());
+            }
+            sets
+        }
+        VerifySignatures::ProposerOnly => vec![block_signature_set(state, block)?],
+        VerifySignatures::None => return Ok(()),
+    };
+
+    match sets.iter().find(|(_, set)| !set.is_valid()) {
+        Some((operation, _)) => Err(Error::InvalidSignature(*operation)),
+        None => Ok(()),
+    }
+}
+
+/// Collects the signatures verified by `verify_block_signatures`.
+pub fn block_signature_sets<C: Config>(
+    state: &BeaconState<C>,
+    block: &BeaconBlock<C>,
+) -> Result<Vec<(SignedOperation, SignatureSet)>, Error> {
+    let body = &block.body;
+    let mut sets = vec![block_signature_set(state, block)?];
+
+    let proposer =
+        proposer(state).map_err(|error| Error::Operation(SignedOperation::RandaoReveal, error))?;
+    sets.push((
+        SignedOperation::RandaoReveal,
+        SignatureSet::single(
+            &body.randao_reveal,
+            &proposer.pubkey,
+            hash_tree_root(&get_current_epoch(state)),
+            get_domain(state, C::domain_randao(), None),
+        ),
+    ));
+
+    for (index, proposer_slashing) in body.proposer_slashings.iter().enumerate() {
+        let operation = SignedOperation::ProposerSlashing(index);
+        let proposer = validator(state, proposer_slashing.proposer_index)
+            .map_err(|error| Error::Operation(operation, error))?;
+        for header in &[&proposer_slashing.header_1, &proposer_slashing.header_2] {
+            let domain = get_domain(
+                state,
+                C::domain_beacon_proposer(),
+                Some(compute_epoch_at_slot::<C>(header.slot)),
+            );
+            sets.push((
+                operation,
+                SignatureSet::single(
+                    &header.signature,
+                    &proposer.pubkey,
+                    signed_root(*header),
+                    domain,
+                ),
+            ));
+        }
+    }
+
+    for (index, attester_slashing) in body.attester_slashings.iter().enumerate() {
+        let operation = SignedOperation::AttesterSlashing(index);
+        for attestation in &[
+            &attester_slashing.attestation_1,
+            &attester_slashing.attestation_2,
+        ] {
+            let set = indexed_attestation_signature_set(state, attestation)
+                .map_err(|error| Error::Operation(operation, error))?;
+            sets.push((operation, set));
+        }
+    }
+
+    for (index, attestation) in body.attestations.iter().enumerate() {
+        let operation = SignedOperation::Attestation(index);
+        let set = attestation_signature_set(state, attestation)
+            .map_err(|error| Error::Operation(operation, error))?;
+        sets.push((operation, set));
+    }
+
+    for (index, exit) in body.voluntary_exits.iter().enumerate() {
+        let operation = SignedOperation::VoluntaryExit(index);
+        let validator = validator(state, exit.validator_index)
+            .map_err(|error| Error::Operation(operation, error))?;
+        sets.push((
+            operation,
+            SignatureSet::single(
+                &exit.signature,
+                &validator.pubkey,
+                signed_root(exit),
+                get_domain(state, C::domain_voluntary_exit(), Some(exit.epoch)),
+            ),
+        ));
+    }
+
+    Ok(sets)
+}
+
+fn block_signature_set<C: Config>(
+    state: &BeaconState<C>,
+    block: &BeaconBlock<C>,
+) -> Result<(SignedOperation, SignatureSet), Error> {
+    let proposer =
+        proposer(state).map_err(|error| Error::Operation(SignedOperation::Block, error))?;
+    let set = SignatureSet::single(
+        &block.signature,
+        &proposer.pubkey,
+        signed_root(block),
+        get_domain(state, C::domain_beacon_proposer(), None),
+    );
+    Ok((SignedOperation::Block, set))
+}
+
+// `process_attestation` checks the committee index and the length of the aggregation bits, but
+// the signature sets are collected before any operations are processed.
+fn attestation_signature_set<C: Config>(
+    state: &BeaconState<C>,
+    attestation: &Attestation<C>,
+) -> Result<SignatureSet, HelperError> {
+    let data = &attestation.data;
+    if data.index >= get_committee_count_at_slot(state, data.slot)? {
+        return Err(HelperError::IndexOutOfRange);
+    }
+    let committee = get_beacon_committee(state, data.slot, data.index)?;
+    if attestation.aggregation_bits.len() != committee.len() {
+        return Err(HelperError::AggregationBitsLengthMismatch);
+    }
+    let indexed = get_indexed_attestation(state, attestation)?;
+    indexed_attestation_signature_set(state, &indexed)
+}
+
+fn proposer<C: Config>(state: &BeaconState<C>) -> Result<&Validator, HelperError> {
+    validator(state, get_beacon_proposer_index(state)?)
+}
+
+fn validator<C: Config>(
+    state: &BeaconState<C>,
+    index: ValidatorIndex,
+) -> Result<&Validator, HelperError> {
+    state
+        .validators
+        .get(index as usize)
+        .ok_or(HelperError::IndexOutOfRange)
+}
+
+#[cfg(test)]
+mod tests {
+    use bls::{AggregateSignature, PublicKey, SecretKey, Signature};
+    use ssz_types::BitList;
+    use types::{config::MinimalConfig, consts::FAR_FUTURE_EPOCH, types::AttestationData};
+
+    use super::*;
+
+    fn state_and_keys(count: usize) -> (BeaconState<MinimalConfig>, Vec<SecretKey>) {
+        let secret_keys = (0..count).map(|_| SecretKey::random()).collect::<Vec<_>>();
+        let validators = secret_keys
+            .iter()
+            .map(|secret_key| Validator {
+                pubkey: PublicKey::from_secret_key(secret_key),
+                effective_balance: MinimalConfig::max_effective_balance(),
+                exit_epoch: FAR_FUTURE_EPOCH,
+                withdrawable_epoch: FAR_FUTURE_EPOCH,
+                ..Validator::default()
+            })
+            .collect::<Vec<_>>();
+        let state = BeaconState {
+            slot: 3,
+            validators: validators.into(),
+            ..BeaconState::default()
+        };
+        (state, secret_keys)
+    }
+
+    fn signed_block(
+        state: &BeaconState<MinimalConfig>,
+        secret_keys: &[SecretKey],
+    ) -> BeaconBlock<MinimalConfig> {
+        let proposer_key = &secret_keys[get_beacon_proposer_index(state).unwrap() as usize];
+        let mut block = BeaconBlock::<MinimalConfig>::default();
+        block.slot = state.slot;
+        block.body.randao_reveal = Signature::new(
+            hash_tree_root(&get_current_epoch(state)).as_bytes(),
+            get_domain(state, MinimalConfig::domain_randao(), None),
+            proposer_key,
+        );
+        block.signature = Signature::new(
+            signed_root(&block).as_bytes(),
+            get_domain(state, MinimalConfig::domain_beacon_proposer(), None),
+            proposer_key,
+        );
+        block
+    }
+
+    fn unsigned_attestation(index: u64, bits: usize) -> Attestation<MinimalConfig> {
+        Attestation {
+            aggregation_bits: BitList::with_capacity(bits).unwrap(),
+            data: AttestationData {
+                index,
+                ..AttestationData::default()
+            },
+            signature: AggregateSignature::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_correctly_signed_block() {
+        let (state, secret_keys) = state_and_keys(16);
+        let block = signed_block(&state, &secret_keys);
+
+        assert_eq!(block_signature_sets(&state, &block).unwrap().len(), 2);
+        for verify_signatures in &[
+            VerifySignatures::Individually,
+            VerifySignatures::Batch,
+            VerifySignatures::ProposerOnly,
+            VerifySignatures::None,
+        ] {
+            assert_eq!(
+                verify_block_signatures(&state, &block, *verify_signatures),
+                Ok(()),
+            );
+        }
+    }
+
+    #[test]
+    fn names_invalid_randao_reveal() {
+        let (state, secret_keys) = state_and_keys(16);
+        let mut block = signed_block(&state, &secret_keys);
+        let proposer_index = get_beacon_proposer_index(&state).unwrap() as usize;
+        let other_key = &secret_keys[(proposer_index + 1) % secret_keys.len()];
+
+        block.body.randao_reveal = Signature::new(
+            hash_tree_root(&get_current_epoch(&state)).as_bytes(),
+            get_domain(&state, MinimalConfig::domain_randao(), None),
+            other_key,
+        );
+        // The block signature covers the body, so it has to be redone.
+        block.signature = Signature::new(
+            signed_root(&block).as_bytes(),
+            get_domain(&state, MinimalConfig::domain_beacon_proposer(), None),
+            &secret_keys[proposer_index],
+        );
+
+        for verify_signatures in &[VerifySignatures::Individually, VerifySignatures::Batch] {
+            assert_eq!(
+                verify_block_signatures(&state, &block, *verify_signatures),
+                Err(Error::InvalidSignature(SignedOperation::RandaoReveal)),
+            );
+        }
+        for verify_signatures in &[VerifySignatures::ProposerOnly, VerifySignatures::None] {
+            assert_eq!(
+                verify_block_signatures(&state, &block, *verify_signatures),
+                Ok(()),
+            );
+        }
+    }
+
+    #[test]
+    fn proposer_only_rejects_invalid_block_signature() {
+        let (state, secret_keys) = state_and_keys(16);
+        let mut block = signed_block(&state, &secret_keys);
+        block.slot += 1;
+
+        assert_eq!(
+            verify_block_signatures(&state, &block, VerifySignatures::ProposerOnly),
+            Err(Error::InvalidSignature(SignedOperation::Block)),
+        );
+        assert_eq!(
+            verify_block_signatures(&state, &block, VerifySignatures::None),
+            Ok(()),
+        );
+    }
+
+    #[test]
+    fn rejects_attestations_that_do_not_match_committees() {
+        let (state, secret_keys) = state_and_keys(16);
+        let operation = SignedOperation::Attestation(0);
+
+        // There is only one committee per slot with this few validators.
+        let mut block = signed_block(&state, &secret_keys);
+        block.body.attestations = vec![unsigned_attestation(1, 2)].into();
+        assert_eq!(
+            verify_block_signatures(&state, &block, VerifySignatures::Batch),
+            Err(Error::Operation(operation, HelperError::IndexOutOfRange)),
+        );
+
+        // The committee of slot 0 has 2 members.
+        let mut block = signed_block(&state, &secret_keys);
+        block.body.attestations = vec![unsigned_attestation(0, 1)].into();
+        assert_eq!(
+            verify_block_signatures(&state, &block, VerifySignatures::Batch),
+            Err(Error::Operation(
+                operation,
+                HelperError::AggregationBitsLengthMismatch,
+            )),
+        );
+    }
+}
@@ -1 +1,2 @@
 pub mod block_processing;
+pub mod block_signatures;
@@ -1 +1,2 @@
 pub mod process_epoch;
+pub mod validator_statuses;
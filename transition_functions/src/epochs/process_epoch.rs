@@ -1,5 +1,7 @@
 use crate::attestations::{attestations::AttestableBlock, *};
-use crate::rewards_and_penalties::rewards_and_penalties::StakeholderBlock;
+use crate::epochs::validator_statuses::ValidatorStatuses;
+use crate::observer::{observe, NoopObserver, StateTransitionObserver, Step};
+use crate::rewards_and_penalties::rewards_and_penalties::{self, Deltas, StakeholderBlock};
 use helper_functions::beacon_state_accessors::*;
 use helper_functions::{
     beacon_state_accessors::{get_randao_mix, get_total_active_balance, get_validator_churn_limit},
@@ -8,7 +10,7 @@ use helper_functions::{
     misc::compute_activation_exit_epoch,
     predicates::is_active_validator,
 };
-use itertools::{Either, Itertools};
+use itertools::Itertools;
 use ssz_types::VariableList;
 use std::cmp;
 use typenum::Unsigned as _;
@@ -23,15 +25,43 @@ use types::{
 };
 
 pub fn process_epoch<T: Config>(state: &mut BeaconState<T>) {
-    process_justification_and_finalization(state);
-    process_rewards_and_penalties(state);
-    process_registry_updates(state);
-    process_slashings(state);
-    process_final_updates(state);
+    process_epoch_with_deltas(state);
+}
+
+/// Like [`process_epoch`], but also returns the balance changes of every validator.
+pub fn process_epoch_with_deltas<T: Config>(state: &mut BeaconState<T>) -> Vec<Deltas> {
+    process_epoch_with_observer(state, &mut NoopObserver)
+}
+
+/// Like [`process_epoch_with_deltas`], but reports every sub-step to `observer`.
+pub fn process_epoch_with_observer<T: Config>(
+    state: &mut BeaconState<T>,
+    observer: &mut dyn StateTransitionObserver<T>,
+) -> Vec<Deltas> {
+    // Justification, rewards and registry updates do not change anything the statuses depend on.
+    let statuses = ValidatorStatuses::new(state).expect("pending attestations should be valid");
+    observe(
+        state,
+        observer,
+        Step::JustificationAndFinalization,
+        |state| process_justification_and_finalization(state, &statuses),
+    );
+    let mut deltas = observe(state, observer, Step::RewardsAndPenalties, |state| {
+        process_rewards_and_penalties(state, &statuses)
+    });
+    observe(state, observer, Step::RegistryUpdates, |state| {
+        process_registry_updates(state, &statuses)
+    });
+    observe(state, observer, Step::Slashings, |state| {
+        process_slashings(state, &mut deltas)
+    });
+    observe(state, observer, Step::FinalUpdates, process_final_updates);
+    deltas
 }
 
 fn process_justification_and_finalization<T: Config>(
     state: &mut BeaconState<T>,
+    statuses: &ValidatorStatuses,
 ) -> Result<(), Error> {
     if get_current_epoch(state) <= T::genesis_epoch() + 1 {
         return Ok(());
@@ -45,10 +75,9 @@ fn process_justification_and_finalization<T: Config>(
     // Process justifications
     state.previous_justified_checkpoint = state.current_justified_checkpoint.clone();
     state.justification_bits.shift_up(1)?;
+    let total_balances = &statuses.total_balances;
     //Previous epoch
-    let matching_target_attestations = state.get_matching_target_attestations(previous_epoch);
-    if state.get_attesting_balance(matching_target_attestations) * 3
-        >= get_total_active_balance(state)? * 2
+    if total_balances.previous_epoch_target_attesters * 3 >= total_balances.current_epoch_active * 2
     {
         state.current_justified_checkpoint = Checkpoint {
             epoch: previous_epoch,
@@ -58,9 +87,7 @@ fn process_justification_and_finalization<T: Config>(
     }
 
     // Current epoch
-    let matching_target_attestations = state.get_matching_target_attestations(current_epoch);
-    if state.get_attesting_balance(matching_target_attestations) * 3
-        >= get_total_active_balance(state)? * 2
+    if total_balances.current_epoch_target_attesters * 3 >= total_balances.current_epoch_active * 2
     {
         state.current_justified_checkpoint = Checkpoint {
             epoch: current_epoch,
@@ -97,37 +124,22 @@ fn process_justification_and_finalization<T: Config>(
     Ok(())
 }
 
-fn process_registry_updates<T: Config>(state: &mut BeaconState<T>) {
-    let state_copy = state.clone();
+fn process_registry_updates<T: Config>(state: &mut BeaconState<T>, statuses: &ValidatorStatuses) {
+    let current_epoch = get_current_epoch(state);
+    let churn_limit = get_validator_churn_limit(&state).unwrap();
+    let mut exit_queue = ExitQueue::new(state, churn_limit);
 
-    let is_eligible = |validator: &Validator| {
-        validator.activation_eligibility_epoch == FAR_FUTURE_EPOCH
+    for (validator, status) in state.validators.iter_mut().zip(&statuses.statuses) {
+        if validator.activation_eligibility_epoch == FAR_FUTURE_EPOCH
             && validator.effective_balance == T::max_effective_balance()
-    };
-
-    let is_exiting_validator = |validator: &Validator| {
-        is_active_validator(validator, get_current_epoch(&state_copy))
+        {
+            validator.activation_eligibility_epoch = current_epoch;
+        } else if status.is_active_in_current_epoch
             && validator.effective_balance <= T::ejection_balance()
-    };
-
-    let (eligible, exiting): (Vec<_>, Vec<_>) = state
-        .validators
-        .iter_mut()
-        .enumerate()
-        .filter(|(_, validator)| is_eligible(validator) || is_exiting_validator(validator))
-        .partition_map(|(i, validator)| {
-            if is_eligible(validator) {
-                Either::Left(i)
-            } else {
-                Either::Right(i)
-            }
-        });
-
-    for index in eligible {
-        state.validators[index].activation_eligibility_epoch = get_current_epoch(&state_copy);
-    }
-    for index in exiting {
-        initiate_validator_exit(state, index as u64).unwrap();
+            && validator.exit_epoch == FAR_FUTURE_EPOCH
+        {
+            exit_queue.initiate_exit(validator);
+        }
     }
 
     // Queue validators eligible for activation and not dequeued for activation prior to finalized epoch
@@ -144,8 +156,6 @@ fn process_registry_updates<T: Config>(state: &mut BeaconState<T>) {
         .map(|(i, _)| i)
         .collect_vec();
     // Dequeued validators for activation up to churn limit (without resetting activation epoch)
-
-    let churn_limit = get_validator_churn_limit(&state).unwrap();
     let delayed_activation_epoch =
         compute_activation_exit_epoch::<T>(get_current_epoch(state) as u64);
     for index in activation_queue.into_iter().take(churn_limit as usize) {
@@ -156,21 +166,68 @@ fn process_registry_updates<T: Config>(state: &mut BeaconState<T>) {
     }
 }
 
-fn process_rewards_and_penalties<T: Config>(state: &mut BeaconState<T>) -> Result<(), Error> {
+/// The exit queue as seen by `initiate_validator_exit`, kept up to date while exits are initiated.
+/// Calling `initiate_validator_exit` for every ejected validator would be quadratic.
+struct ExitQueue {
+    epoch: Epoch,
+    churn: u64,
+    churn_limit: u64,
+    withdrawability_delay: Epoch,
+}
+
+impl ExitQueue {
+    fn new<T: Config>(state: &BeaconState<T>, churn_limit: u64) -> Self {
+        let epoch = state
+            .validators
+            .iter()
+            .map(|validator| validator.exit_epoch)
+            .filter(|exit_epoch| *exit_epoch != FAR_FUTURE_EPOCH)
+            .fold(
+                compute_activation_exit_epoch::<T>(get_current_epoch(state)),
+                cmp::max,
+            );
+        let churn = state
+            .validators
+            .iter()
+            .filter(|validator| validator.exit_epoch == epoch)
+            .count() as u64;
+        Self {
+            epoch,
+            churn,
+            churn_limit,
+            withdrawability_delay: T::min_validator_withdrawability_delay(),
+        }
+    }
+
+    fn initiate_exit(&mut self, validator: &mut Validator) {
+        if self.churn >= self.churn_limit {
+            self.epoch += 1;
+            self.churn = 0;
+        }
+        self.churn += 1;
+        validator.exit_epoch = self.epoch;
+        validator.withdrawable_epoch = self.epoch + self.withdrawability_delay;
+    }
+}
+
+fn process_rewards_and_penalties<T: Config>(
+    state: &mut BeaconState<T>,
+    statuses: &ValidatorStatuses,
+) -> Vec<Deltas> {
     if get_current_epoch(state) == T::genesis_epoch() {
-        return Ok(());
+        return vec![Deltas::default(); state.validators.len()];
     }
 
-    let (rewards, penalties) = state.get_attestation_deltas();
-    for index in 0..state.validators.len() {
-        increase_balance(state, index as ValidatorIndex, rewards[index]).unwrap();
-        decrease_balance(state, index as ValidatorIndex, penalties[index]).unwrap();
+    let deltas = rewards_and_penalties::get_attestation_deltas_breakdown(state, statuses);
+    for (index, validator_deltas) in deltas.iter().enumerate() {
+        increase_balance(state, index as ValidatorIndex, validator_deltas.rewards()).unwrap();
+        decrease_balance(state, index as ValidatorIndex, validator_deltas.penalties()).unwrap();
     }
 
-    Ok(())
+    deltas
 }
 
-fn process_slashings<T: Config>(state: &mut BeaconState<T>) {
+fn process_slashings<T: Config>(state: &mut BeaconState<T>, deltas: &mut [Deltas]) {
     let epoch = get_current_epoch(state);
     let total_balance = get_total_active_balance(state).unwrap();
 
@@ -184,6 +241,7 @@ fn process_slashings<T: Config>(state: &mut BeaconState<T>) {
                 * cmp::min(slashings_sum * 3, total_balance);
             let penalty = penalty_numerator / total_balance * increment;
             decrease_balance(state, index as u64, penalty).unwrap();
+            deltas[index].slashing_penalty = penalty;
         }
     }
 }
@@ -0,0 +1,372 @@
+//! A summary of every validator's participation, computed once per epoch transition.
+//!
+//! The functions in the specification (`get_matching_target_attestations`,
+//! `get_unslashed_attesting_indices`, `get_total_balance` and so on) each iterate over the pending
+//! attestations or the whole registry. Calling them for every validator makes epoch processing
+//! quadratic. [`ValidatorStatuses::new`] goes over the registry and the pending attestations once
+//! and records everything the rest of epoch processing needs.
+
+use std::collections::BTreeSet;
+
+use helper_functions::{
+    beacon_state_accessors::{
+        get_attesting_indices, get_block_root, get_block_root_at_slot, get_current_epoch,
+        get_previous_epoch,
+    },
+    predicates::is_active_validator,
+};
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    helper_functions_types::Error,
+    primitives::{Gwei, ValidatorIndex},
+    types::PendingAttestation,
+};
+
+/// The attestation with the smallest inclusion delay among those a validator took part in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InclusionInfo {
+    pub delay: u64,
+    pub proposer_index: ValidatorIndex,
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct ValidatorStatus {
+    pub is_slashed: bool,
+    pub is_active_in_current_epoch: bool,
+    pub is_active_in_previous_epoch: bool,
+    /// Whether the validator receives rewards and penalties for the previous epoch.
+    pub is_eligible: bool,
+    pub effective_balance: Gwei,
+    // The participation flags are only set for unslashed validators.
+    pub is_previous_epoch_source_attester: bool,
+    pub is_previous_epoch_target_attester: bool,
+    pub is_previous_epoch_head_attester: bool,
+    pub is_current_epoch_target_attester: bool,
+    /// Present if the validator is a source attester in the previous epoch.
+    pub inclusion_info: Option<InclusionInfo>,
+}
+
+/// Sums of effective balances with the same semantics as `get_total_balance`.
+/// Every sum is at least 1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TotalBalances {
+    pub current_epoch_active: Gwei,
+    pub previous_epoch_source_attesters: Gwei,
+    pub previous_epoch_target_attesters: Gwei,
+    pub previous_epoch_head_attesters: Gwei,
+    pub current_epoch_target_attesters: Gwei,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ValidatorStatuses {
+    /// Indexed by validator index.
+    pub statuses: Vec<ValidatorStatus>,
+    pub total_balances: TotalBalances,
+}
+
+impl ValidatorStatuses {
+    pub fn new<C: Config>(state: &BeaconState<C>) -> Result<Self, Error> {
+        let current_epoch = get_current_epoch(state);
+        let previous_epoch = get_previous_epoch(state);
+
+        let mut statuses = state
+            .validators
+            .iter()
+            .map(|validator| ValidatorStatus {
+                is_slashed: validator.slashed,
+                is_active_in_current_epoch: is_active_validator(validator, current_epoch),
+                is_active_in_previous_epoch: is_active_validator(validator, previous_epoch),
+                is_eligible: is_active_validator(validator, previous_epoch)
+                    || (validator.slashed && previous_epoch + 1 < validator.withdrawable_epoch),
+                effective_balance: validator.effective_balance,
+                ..ValidatorStatus::default()
+            })
+            .collect::<Vec<_>>();
+
+        // `get_matching_source_attestations` returns the current epoch attestations if the
+        // previous and current epochs are the same, which only happens in the genesis epoch.
+        let previous_epoch_attestations = if previous_epoch == current_epoch {
+            &state.current_epoch_attestations
+        } else {
+            &state.previous_epoch_attestations
+        };
+
+        for attestation in previous_epoch_attestations.iter() {
+            let is_target = attestation.data.target.root == get_block_root(state, previous_epoch)?;
+            let is_head = attestation.data.beacon_block_root
+                == get_block_root_at_slot(state, attestation.data.slot)?;
+
+            for index in attesting_indices(state, attestation)? {
+                let status = &mut statuses[index as usize];
+                if status.is_slashed {
+                    continue;
+                }
+                status.is_previous_epoch_source_attester = true;
+                status.is_previous_epoch_target_attester |= is_target;
+                status.is_previous_epoch_head_attester |= is_head;
+
+                // Attestations are visited in order, so the first one with the smallest delay is
+                // kept, as with `min_by_key` in the specification.
+                let inclusion_info = InclusionInfo {
+                    delay: attestation.inclusion_delay,
+                    proposer_index: attestation.proposer_index,
+                };
+                match status.inclusion_info {
+                    Some(previous) if previous.delay <= inclusion_info.delay => {}
+                    _ => status.inclusion_info = Some(inclusion_info),
+                }
+            }
+        }
+
+        for attestation in state.current_epoch_attestations.iter() {
+            if attestation.data.target.root == get_block_root(state, current_epoch)? {
+                for index in attesting_indices(state, attestation)? {
+                    let status = &mut statuses[index as usize];
+                    status.is_current_epoch_target_attester |= !status.is_slashed;
+                }
+            }
+        }
+
+        let total_balance = |predicate: fn(&ValidatorStatus) -> bool| {
+            let sum = statuses
+                .iter()
+                .filter(|status| predicate(status))
+                .map(|status| status.effective_balance)
+                .sum::<Gwei>();
+            sum.max(1)
+        };
+
+        let total_balances = TotalBalances {
+            current_epoch_active: total_balance(|status| status.is_active_in_current_epoch),
+            previous_epoch_source_attesters: total_balance(|status| {
+                status.is_previous_epoch_source_attester
+            }),
+            previous_epoch_target_attesters: total_balance(|status| {
+                status.is_previous_epoch_target_attester
+            }),
+            previous_epoch_head_attesters: total_balance(|status| {
+                status.is_previous_epoch_head_attester
+            }),
+            current_epoch_target_attesters: total_balance(|status| {
+                status.is_current_epoch_target_attester
+            }),
+        };
+
+        Ok(Self {
+            statuses,
+            total_balances,
+        })
+    }
+}
+
+fn attesting_indices<C: Config>(
+    state: &BeaconState<C>,
+    attestation: &PendingAttestation<C>,
+) -> Result<BTreeSet<ValidatorIndex>, Error> {
+    get_attesting_indices(state, &attestation.data, &attestation.aggregation_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use helper_functions::beacon_state_accessors::{
+        get_beacon_committee, get_committee_count_at_slot, get_total_active_balance,
+    };
+    use helper_functions::misc::compute_start_slot_at_epoch;
+    use ssz_types::BitList;
+    use typenum::Unsigned as _;
+    use types::{
+        config::MinimalConfig,
+        consts::FAR_FUTURE_EPOCH,
+        primitives::{Epoch, Slot, H256},
+        types::{AttestationData, Checkpoint, Validator},
+    };
+
+    use crate::attestations::attestations::AttestableBlock;
+    use crate::epochs::process_epoch::process_epoch_with_deltas;
+    use crate::rewards_and_penalties::rewards_and_penalties::{
+        get_attestation_deltas, StakeholderBlock,
+    };
+
+    use super::*;
+
+    const VALIDATOR_COUNT: u64 = 64;
+
+    fn state_in_epoch(epoch: Epoch) -> BeaconState<MinimalConfig> {
+        let mut state = BeaconState::<MinimalConfig> {
+            slot: compute_start_slot_at_epoch::<MinimalConfig>(epoch + 1) - 1,
+            ..BeaconState::default()
+        };
+        for index in 0..VALIDATOR_COUNT {
+            // Every tenth validator is not active and every seventh one is slashed.
+            let activation_epoch = if index % 10 == 0 { FAR_FUTURE_EPOCH } else { 0 };
+            let validator = Validator {
+                activation_epoch,
+                exit_epoch: FAR_FUTURE_EPOCH,
+                withdrawable_epoch: FAR_FUTURE_EPOCH,
+                slashed: index % 7 == 3,
+                effective_balance: 16_000_000_000 + index % 5 * 4_000_000_000,
+                ..Validator::default()
+            };
+            state.validators.push(validator).expect("");
+            state.balances.push(32_000_000_000).expect("");
+        }
+        for (index, root) in state.block_roots.iter_mut().enumerate() {
+            *root = H256::from_low_u64_be(index as u64 + 1);
+        }
+        let previous_epoch = get_previous_epoch(&state);
+        let current_epoch = get_current_epoch(&state);
+        for slot in compute_start_slot_at_epoch::<MinimalConfig>(previous_epoch)..state.slot {
+            let pending_attestations = attestations(&state, slot);
+            let list = if compute_start_slot_at_epoch::<MinimalConfig>(current_epoch) <= slot {
+                &mut state.current_epoch_attestations
+            } else {
+                &mut state.previous_epoch_attestations
+            };
+            for attestation in pending_attestations {
+                list.push(attestation).expect("");
+            }
+        }
+        state
+    }
+
+    // Every committee attests exactly once, so no validator appears in several attestations.
+    fn attestations(
+        state: &BeaconState<MinimalConfig>,
+        slot: Slot,
+    ) -> Vec<PendingAttestation<MinimalConfig>> {
+        let epoch = slot / MinimalConfig::SlotsPerEpoch::U64;
+        let count = get_committee_count_at_slot(state, slot).expect("");
+        (0..count)
+            .map(|index| {
+                let committee = get_beacon_committee(state, slot, index).expect("");
+                let mut aggregation_bits = BitList::with_capacity(committee.len()).expect("");
+                for position in 0..committee.len() {
+                    let bit = (slot + index + position as u64) % 3 != 0;
+                    aggregation_bits.set(position, bit).expect("");
+                }
+                let pattern = slot + index;
+                let wrong_root = H256::repeat_byte(0xff);
+                let target_root = if pattern % 4 == 0 {
+                    wrong_root
+                } else {
+                    get_block_root(state, epoch).expect("")
+                };
+                let head_root = if pattern % 3 == 0 {
+                    wrong_root
+                } else {
+                    get_block_root_at_slot(state, slot).expect("")
+                };
+                PendingAttestation {
+                    data: AttestationData {
+                        slot,
+                        index,
+                        beacon_block_root: head_root,
+                        source: Checkpoint::default(),
+                        target: Checkpoint {
+                            epoch,
+                            root: target_root,
+                        },
+                    },
+                    aggregation_bits,
+                    inclusion_delay: 1 + pattern % 5,
+                    proposer_index: (slot * 7 + index) % VALIDATOR_COUNT,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas_match_reference_implementation() {
+        // The inactivity penalty only applies in the later epoch.
+        for epoch in &[2, 7] {
+            let state = state_in_epoch(*epoch);
+            let statuses = ValidatorStatuses::new(&state).expect("");
+
+            assert!(statuses.statuses.iter().any(|status| status.is_eligible
+                && status.is_previous_epoch_source_attester
+                && !status.is_previous_epoch_target_attester));
+            assert_eq!(
+                get_attestation_deltas(&state, &statuses),
+                state.get_attestation_deltas(),
+            );
+        }
+    }
+
+    #[test]
+    fn deltas_add_up_to_balance_changes() {
+        let mut state = state_in_epoch(7);
+        let slashed_index = 3;
+        state.validators[slashed_index].withdrawable_epoch =
+            7 + MinimalConfig::EpochsPerSlashingsVector::U64 / 2;
+        state.slashings[0] = 64_000_000_000;
+        let old_balances = state.balances.clone();
+
+        let deltas = process_epoch_with_deltas(&mut state);
+
+        assert!(deltas[slashed_index].slashing_penalty > 0);
+        assert!(deltas.iter().any(|deltas| deltas.proposer_reward > 0));
+        assert!(deltas.iter().any(|deltas| deltas.inactivity_penalty > 0));
+        for ((old_balance, new_balance), deltas) in
+            old_balances.iter().zip(state.balances.iter()).zip(deltas)
+        {
+            assert_eq!(
+                new_balance + deltas.penalties(),
+                old_balance + deltas.rewards()
+            );
+        }
+    }
+
+    #[test]
+    fn total_balances_match_reference_implementation() {
+        let state = state_in_epoch(3);
+        let statuses = ValidatorStatuses::new(&state).expect("");
+        let previous_epoch = get_previous_epoch(&state);
+        let current_epoch = get_current_epoch(&state);
+
+        let reference = TotalBalances {
+            current_epoch_active: get_total_active_balance(&state).expect(""),
+            previous_epoch_source_attesters: state
+                .get_attesting_balance(state.get_matching_source_attestations(previous_epoch)),
+            previous_epoch_target_attesters: state
+                .get_attesting_balance(state.get_matching_target_attestations(previous_epoch)),
+            previous_epoch_head_attesters: state
+                .get_attesting_balance(state.get_matching_head_attestations(previous_epoch)),
+            current_epoch_target_attesters: state
+                .get_attesting_balance(state.get_matching_target_attestations(current_epoch)),
+        };
+
+        assert_eq!(statuses.total_balances, reference);
+    }
+
+    #[test]
+    fn inclusion_info_comes_from_first_attestation_with_smallest_delay() {
+        let mut state = state_in_epoch(3);
+        let mut attestation = state.previous_epoch_attestations[0].clone();
+        let attesting_indices = attesting_indices(&state, &attestation).expect("");
+        let original_delay = attestation.inclusion_delay;
+
+        for (delay, proposer_index) in &[(original_delay + 1, 1), (1, 2), (1, 3)] {
+            attestation.inclusion_delay = *delay;
+            attestation.proposer_index = *proposer_index;
+            state
+                .previous_epoch_attestations
+                .push(attestation.clone())
+                .expect("");
+        }
+
+        let statuses = ValidatorStatuses::new(&state).expect("");
+        let unslashed_index = attesting_indices
+            .into_iter()
+            .find(|index| !state.validators[*index as usize].slashed)
+            .expect("");
+
+        assert_eq!(
+            statuses.statuses[unslashed_index as usize].inclusion_info,
+            Some(InclusionInfo {
+                delay: 1,
+                proposer_index: 2,
+            }),
+        );
+    }
+}
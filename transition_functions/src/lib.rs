@@ -6,5 +6,6 @@
 pub mod attestations;
 pub mod blocks;
 pub mod epochs;
+pub mod observer;
 pub mod process_slot;
 pub mod rewards_and_penalties;
@@ -0,0 +1,332 @@
+//! Hooks for observing the individual steps of a state transition.
+//!
+//! Every step reports the state before and after it is applied. Steps that panic never report the
+//! state after them, so the last step passed to `before_step` before a failure is the one that
+//! failed. Observers that only act in `after_step` see nothing of the failing step.
+
+use std::{
+    fs::File,
+    io::{LineWriter, Result as IoResult, Write as _},
+    path::Path,
+};
+
+use log::error;
+use serde::Serialize;
+use types::{
+    beacon_state::BeaconState,
+    config::Config,
+    primitives::{Gwei, Slot, ValidatorIndex},
+    types::{Checkpoint, Validator},
+};
+
+/// A step of a state transition. Operations are identified by their position in the block body.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub enum Step {
+    /// Caching of roots in `process_slot`.
+    Slot(Slot),
+    JustificationAndFinalization,
+    RewardsAndPenalties,
+    RegistryUpdates,
+    Slashings,
+    FinalUpdates,
+    BlockHeader,
+    Randao,
+    Eth1Data,
+    ProposerSlashing(usize),
+    AttesterSlashing(usize),
+    Attestation(usize),
+    Deposit(usize),
+    VoluntaryExit(usize),
+}
+
+pub trait StateTransitionObserver<C: Config> {
+    fn before_step(&mut self, _step: Step, _state: &BeaconState<C>) {}
+
+    fn after_step(&mut self, _step: Step, _state: &BeaconState<C>) {}
+}
+
+/// An observer that does nothing. Used by the functions that take no observer.
+pub struct NoopObserver;
+
+impl<C: Config> StateTransitionObserver<C> for NoopObserver {}
+
+pub(crate) fn observe<C: Config, T>(
+    state: &mut BeaconState<C>,
+    observer: &mut dyn StateTransitionObserver<C>,
+    step: Step,
+    process: impl FnOnce(&mut BeaconState<C>) -> T,
+) -> T {
+    observer.before_step(step, state);
+    let output = process(state);
+    observer.after_step(step, state);
+    output
+}
+
+/// Writes the changes made by every step as a line of JSON.
+///
+/// A line naming the step is written before the step is applied, so a trace of a step that panics
+/// ends with the step that failed. Lines are flushed as soon as they are written.
+///
+/// Only validator balances, validators, justification bits and checkpoints are compared.
+/// Comparing them requires copying parts of the state before every step, so this is slow.
+pub struct JsonLinesTracer {
+    output: LineWriter<File>,
+    before: Option<Snapshot>,
+}
+
+impl JsonLinesTracer {
+    pub fn create(path: impl AsRef<Path>) -> IoResult<Self> {
+        Ok(Self {
+            output: LineWriter::new(File::create(path)?),
+            before: None,
+        })
+    }
+
+    fn write<C: Config>(&mut self, step: Step, before: &Snapshot, state: &BeaconState<C>) {
+        let after = Snapshot::new(state);
+        let record = Record {
+            step,
+            slot: state.slot,
+            balance_changes: balance_changes(&before.balances, &after.balances),
+            validator_changes: validator_changes(&before.validators, &after.validators),
+            justification_bits: Change::new(&before.justification_bits, &after.justification_bits),
+            previous_justified_checkpoint: Change::new(
+                &before.previous_justified_checkpoint,
+                &after.previous_justified_checkpoint,
+            ),
+            current_justified_checkpoint: Change::new(
+                &before.current_justified_checkpoint,
+                &after.current_justified_checkpoint,
+            ),
+            finalized_checkpoint: Change::new(
+                &before.finalized_checkpoint,
+                &after.finalized_checkpoint,
+            ),
+        };
+
+        self.write_line(&record);
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) {
+        let result = serde_json::to_writer(&mut self.output, value)
+            .map_err(Into::into)
+            .and_then(|()| writeln!(self.output));
+
+        if let Err(error) = result {
+            error!("failed to write state transition trace: {}", error);
+        }
+    }
+}
+
+impl<C: Config> StateTransitionObserver<C> for JsonLinesTracer {
+    fn before_step(&mut self, step: Step, state: &BeaconState<C>) {
+        self.write_line(&Started {
+            started: step,
+            slot: state.slot,
+        });
+        self.before = Some(Snapshot::new(state));
+    }
+
+    fn after_step(&mut self, step: Step, state: &BeaconState<C>) {
+        if let Some(before) = self.before.take() {
+            self.write(step, &before, state);
+        }
+    }
+}
+
+struct Snapshot {
+    balances: Vec<Gwei>,
+    validators: Vec<Validator>,
+    justification_bits: Vec<bool>,
+    previous_justified_checkpoint: Checkpoint,
+    current_justified_checkpoint: Checkpoint,
+    finalized_checkpoint: Checkpoint,
+}
+
+impl Snapshot {
+    fn new<C: Config>(state: &BeaconState<C>) -> Self {
+        Self {
+            balances: state.balances.iter().copied().collect(),
+            validators: state.validators.iter().cloned().collect(),
+            justification_bits: state.justification_bits.iter().collect(),
+            previous_justified_checkpoint: state.previous_justified_checkpoint,
+            current_justified_checkpoint: state.current_justified_checkpoint,
+            finalized_checkpoint: state.finalized_checkpoint,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Started {
+    started: Step,
+    slot: Slot,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    step: Step,
+    slot: Slot,
+    balance_changes: Vec<BalanceChange>,
+    validator_changes: Vec<ValidatorChange<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    justification_bits: Option<Change<'a, Vec<bool>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_justified_checkpoint: Option<Change<'a, Checkpoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_justified_checkpoint: Option<Change<'a, Checkpoint>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finalized_checkpoint: Option<Change<'a, Checkpoint>>,
+}
+
+#[derive(Serialize)]
+struct BalanceChange {
+    validator_index: ValidatorIndex,
+    // Validators added by deposits have no balance before.
+    before: Option<Gwei>,
+    after: Gwei,
+}
+
+#[derive(Serialize)]
+struct ValidatorChange<'a> {
+    validator_index: ValidatorIndex,
+    before: Option<&'a Validator>,
+    after: &'a Validator,
+}
+
+#[derive(Serialize)]
+struct Change<'a, T> {
+    before: &'a T,
+    after: &'a T,
+}
+
+impl<'a, T: PartialEq> Change<'a, T> {
+    fn new(before: &'a T, after: &'a T) -> Option<Self> {
+        if before == after {
+            None
+        } else {
+            Some(Self { before, after })
+        }
+    }
+}
+
+fn balance_changes(before: &[Gwei], after: &[Gwei]) -> Vec<BalanceChange> {
+    after
+        .iter()
+        .enumerate()
+        .filter_map(|(index, after)| {
+            let before = before.get(index).copied();
+            if before == Some(*after) {
+                return None;
+            }
+            Some(BalanceChange {
+                validator_index: index as ValidatorIndex,
+                before,
+                after: *after,
+            })
+        })
+        .collect()
+}
+
+fn validator_changes<'a>(
+    before: &'a [Validator],
+    after: &'a [Validator],
+) -> Vec<ValidatorChange<'a>> {
+    after
+        .iter()
+        .enumerate()
+        .filter_map(|(index, after)| {
+            let before = before.get(index);
+            if before == Some(after) {
+                return None;
+            }
+            Some(ValidatorChange {
+                validator_index: index as ValidatorIndex,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, panic};
+
+    use types::config::MinimalConfig;
+
+    use super::*;
+    use crate::process_slot::process_slots_with_observer;
+
+    #[derive(Default)]
+    struct Recorder(Vec<(&'static str, Step, Slot)>);
+
+    impl<C: Config> StateTransitionObserver<C> for Recorder {
+        fn before_step(&mut self, step: Step, state: &BeaconState<C>) {
+            self.0.push(("before", step, state.slot));
+        }
+
+        fn after_step(&mut self, step: Step, state: &BeaconState<C>) {
+            self.0.push(("after", step, state.slot));
+        }
+    }
+
+    #[test]
+    fn process_slots_reports_every_slot() {
+        let mut state = BeaconState::<MinimalConfig>::default();
+        let mut recorder = Recorder::default();
+
+        process_slots_with_observer(&mut state, 2, &mut recorder);
+
+        assert_eq!(
+            recorder.0,
+            vec![
+                ("before", Step::Slot(0), 0),
+                ("after", Step::Slot(0), 0),
+                ("before", Step::Slot(1), 1),
+                ("after", Step::Slot(1), 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn balance_changes_include_new_validators() {
+        let changes = balance_changes(&[1, 2, 3], &[1, 5, 3, 7]);
+
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.validator_index, change.before, change.after))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(2), 5), (3, None, 7)],
+        );
+    }
+
+    #[test]
+    fn unchanged_values_are_omitted() {
+        assert!(Change::new(&1, &1).is_none());
+        assert!(Change::new(&1, &2).is_some());
+    }
+
+    #[test]
+    fn trace_names_step_that_panics() {
+        let path = std::env::temp_dir().join(format!("observer-{}.jsonl", std::process::id()));
+        let mut tracer = JsonLinesTracer::create(&path).expect("trace file should be created");
+        let mut state = BeaconState::<MinimalConfig>::default();
+
+        observe(&mut state, &mut tracer, Step::RegistryUpdates, |_| {});
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            observe(&mut state, &mut tracer, Step::Slashings, |_| {
+                panic!("step failed");
+            })
+        }));
+        assert!(result.is_err());
+
+        let trace = fs::read_to_string(&path).expect("trace file should be readable");
+        fs::remove_file(&path).expect("trace file should be removable");
+        let last_line = trace.lines().last().expect("trace should not be empty");
+
+        assert_eq!(trace.lines().count(), 3);
+        assert_eq!(last_line, r#"{"started":"Slashings","slot":0}"#);
+    }
+}
@@ -1,9 +1,12 @@
 use crate::*;
 use blocks::block_processing::*;
-use epochs::process_epoch::process_epoch;
+use blocks::block_signatures::VerifySignatures;
+use epochs::process_epoch::{process_epoch, process_epoch_with_observer};
 use ethereum_types::H256 as Hash256;
 use helper_functions;
 use helper_functions::crypto::*;
+use observer::{observe, NoopObserver, StateTransitionObserver, Step};
+use rewards_and_penalties::rewards_and_penalties::Deltas;
 use typenum::Unsigned as _;
 use types::primitives::*;
 use types::types::*;
@@ -18,35 +21,75 @@ pub enum Error {}
 pub fn state_transition<T: Config>(
     state: &mut BeaconState<T>,
     block: &BeaconBlock<T>,
+    verify_signatures: VerifySignatures,
     validate_state_root: bool,
+) -> BeaconState<T> {
+    state_transition_with_observer(
+        state,
+        block,
+        verify_signatures,
+        validate_state_root,
+        &mut NoopObserver,
+    )
+}
+
+/// Like [`state_transition`], but reports every step of the transition to `observer`.
+pub fn state_transition_with_observer<T: Config>(
+    state: &mut BeaconState<T>,
+    block: &BeaconBlock<T>,
+    verify_signatures: VerifySignatures,
+    validate_state_root: bool,
+    observer: &mut dyn StateTransitionObserver<T>,
 ) -> BeaconState<T> {
     //# Process slots (including those with no blocks) since block
-    process_slots(state, block.slot);
+    process_slots_with_observer(state, block.slot, observer);
     //# Process block
-    blocks::block_processing::process_block(state, block);
+    process_block_with_observer(state, block, verify_signatures, observer);
     //# Validate state root (`validate_state_root == True` in production)
     if validate_state_root {
-        assert!(block.state_root == hash_tree_root(state));
+        assert!(block.state_root == state.canonical_root());
     }
     //# Return post-state
     return state.clone();
 }
 
 pub fn process_slots<T: Config>(state: &mut BeaconState<T>, slot: Slot) {
+    process_slots_with_deltas(state, slot);
+}
+
+/// Like [`process_slots`], but also returns the balance changes from every epoch transition along
+/// with the epoch that ended.
+pub fn process_slots_with_deltas<T: Config>(
+    state: &mut BeaconState<T>,
+    slot: Slot,
+) -> Vec<(Epoch, Vec<Deltas>)> {
+    process_slots_with_observer(state, slot, &mut NoopObserver)
+}
+
+/// Like [`process_slots_with_deltas`], but reports every slot and epoch step to `observer`.
+pub fn process_slots_with_observer<T: Config>(
+    state: &mut BeaconState<T>,
+    slot: Slot,
+    observer: &mut dyn StateTransitionObserver<T>,
+) -> Vec<(Epoch, Vec<Deltas>)> {
     assert!(state.slot <= slot);
+    let mut epoch_deltas = vec![];
     while state.slot < slot {
-        process_slot(state);
+        let step = Step::Slot(state.slot);
+        observe(state, observer, step, process_slot);
         //# Process epoch on the start slot of the next epoch
         if (state.slot + 1) % T::SlotsPerEpoch::U64 == 0 {
-            process_epoch(state);
+            let epoch = state.slot / T::SlotsPerEpoch::U64;
+            epoch_deltas.push((epoch, process_epoch_with_observer(state, observer)));
         }
         state.slot += 1;
     }
+    epoch_deltas
 }
 
 fn process_slot<T: Config>(state: &mut BeaconState<T>) {
     // Cache state root
-    let previous_state_root = hash_tree_root(state);
+    let previous_state_root = state.canonical_root();
 
     state.state_roots[(state.slot as usize) % T::SlotsPerHistoricalRoot::USIZE] =
         previous_state_root;
@@ -1,4 +1,5 @@
 use helper_functions;
+use serde::Serialize;
 use types::consts::*;
 use types::{
     beacon_state::*,
@@ -6,6 +7,7 @@ use types::{
 };
 // use types::types::*;
 use crate::attestations::attestations::AttestableBlock;
+use crate::epochs::validator_statuses::ValidatorStatuses;
 use helper_functions::beacon_state_accessors::*;
 use helper_functions::beacon_state_mutators::*;
 use helper_functions::math::*;
@@ -128,6 +130,130 @@ where
     }
 }
 
+/// The balance changes of a single validator in an epoch transition, broken down by cause.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize)]
+pub struct Deltas {
+    pub source_reward: Gwei,
+    pub source_penalty: Gwei,
+    pub target_reward: Gwei,
+    pub target_penalty: Gwei,
+    pub head_reward: Gwei,
+    pub head_penalty: Gwei,
+    /// Reward for attestations the validator made that were included quickly.
+    pub inclusion_delay_reward: Gwei,
+    /// Reward for including attestations of other validators in blocks.
+    pub proposer_reward: Gwei,
+    pub inactivity_penalty: Gwei,
+    /// Set by `process_slashings`. Not included in the results of `get_attestation_deltas`.
+    pub slashing_penalty: Gwei,
+}
+
+impl Deltas {
+    pub fn rewards(&self) -> Gwei {
+        self.source_reward
+            + self.target_reward
+            + self.head_reward
+            + self.inclusion_delay_reward
+            + self.proposer_reward
+    }
+
+    pub fn penalties(&self) -> Gwei {
+        self.source_penalty
+            + self.target_penalty
+            + self.head_penalty
+            + self.inactivity_penalty
+            + self.slashing_penalty
+    }
+}
+
+/// Computes the same deltas as [`StakeholderBlock::get_attestation_deltas`] in linear time.
+///
+/// Unlike `get_attestation_deltas`, this gives the proposer and inclusion delay rewards once per
+/// validator even if the validator is included in several attestations, as in the specification.
+pub fn get_attestation_deltas<T: Config>(
+    state: &BeaconState<T>,
+    validator_statuses: &ValidatorStatuses,
+) -> (Vec<Gwei>, Vec<Gwei>) {
+    get_attestation_deltas_breakdown(state, validator_statuses)
+        .iter()
+        .map(|deltas| (deltas.rewards(), deltas.penalties()))
+        .unzip()
+}
+
+/// Like [`get_attestation_deltas`], but keeps the components of the deltas separate.
+pub fn get_attestation_deltas_breakdown<T: Config>(
+    state: &BeaconState<T>,
+    validator_statuses: &ValidatorStatuses,
+) -> Vec<Deltas> {
+    let statuses = &validator_statuses.statuses;
+    let total_balances = &validator_statuses.total_balances;
+    let total_balance = total_balances.current_epoch_active;
+    let sqrt_total_balance = integer_squareroot(total_balance);
+    let finality_delay = get_previous_epoch(state) - state.finalized_checkpoint.epoch;
+    let is_inactivity_leak = finality_delay > T::min_epochs_to_inactivity_penalty();
+
+    let mut deltas = vec![Deltas::default(); statuses.len()];
+
+    for (index, status) in statuses.iter().enumerate() {
+        let base_reward = status.effective_balance * T::base_reward_factor()
+            / sqrt_total_balance
+            / BASE_REWARDS_PER_EPOCH;
+
+        //# Proposer and inclusion delay micro-rewards
+        // Every source attester is eligible, but the proposer of its attestation may not be.
+        if let Some(inclusion_info) = status.inclusion_info {
+            let proposer_reward = base_reward / T::proposer_reward_quotient();
+            deltas[inclusion_info.proposer_index as usize].proposer_reward += proposer_reward;
+            let max_attester_reward = base_reward - proposer_reward;
+            deltas[index].inclusion_delay_reward += max_attester_reward / inclusion_info.delay;
+        }
+
+        if !status.is_eligible {
+            continue;
+        }
+
+        let validator_deltas = &mut deltas[index];
+
+        //# Micro-incentives for matching FFG source, FFG target, and head
+        let component_reward = |is_attester: bool, attesting_balance: Gwei| {
+            if is_attester {
+                (base_reward * attesting_balance / total_balance, 0)
+            } else {
+                (0, base_reward)
+            }
+        };
+        let (reward, penalty) = component_reward(
+            status.is_previous_epoch_source_attester,
+            total_balances.previous_epoch_source_attesters,
+        );
+        validator_deltas.source_reward = reward;
+        validator_deltas.source_penalty = penalty;
+        let (reward, penalty) = component_reward(
+            status.is_previous_epoch_target_attester,
+            total_balances.previous_epoch_target_attesters,
+        );
+        validator_deltas.target_reward = reward;
+        validator_deltas.target_penalty = penalty;
+        let (reward, penalty) = component_reward(
+            status.is_previous_epoch_head_attester,
+            total_balances.previous_epoch_head_attesters,
+        );
+        validator_deltas.head_reward = reward;
+        validator_deltas.head_penalty = penalty;
+
+        //# Inactivity penalty
+        if is_inactivity_leak {
+            validator_deltas.inactivity_penalty += BASE_REWARDS_PER_EPOCH * base_reward;
+            if !status.is_previous_epoch_target_attester {
+                validator_deltas.inactivity_penalty +=
+                    status.effective_balance * finality_delay / T::inactivity_penalty_quotient();
+            }
+        }
+    }
+
+    deltas
+}
+
 #[test]
 fn test_base_reward() {
     use types::types::Validator;
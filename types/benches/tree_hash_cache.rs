@@ -0,0 +1,87 @@
+//! Compares hashing a `BeaconState` from scratch with updating its tree hash cache.
+//!
+//! The state has a registry of mainnet size. Every validator has its own public key so that the
+//! benchmarks notice if updates start serializing or hashing keys again. Generating the keys takes
+//! a while, but it is only done once.
+
+use bls::{PublicKey, SecretKey};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use ethereum_types::H256;
+use ssz_types::VariableList;
+use tree_hash::TreeHash as _;
+use types::{beacon_state::BeaconState, config::MainnetConfig, types::Validator};
+
+const VALIDATOR_COUNT: u64 = 300_000;
+
+fn mainnet_state() -> BeaconState<MainnetConfig> {
+    let validators = (0..VALIDATOR_COUNT)
+        .map(|index| Validator {
+            pubkey: PublicKey::from_secret_key(&SecretKey::random()),
+            withdrawal_credentials: H256::from_low_u64_be(index),
+            effective_balance: 32_000_000_000,
+            exit_epoch: u64::max_value(),
+            withdrawable_epoch: u64::max_value(),
+            ..Validator::default()
+        })
+        .collect::<Vec<_>>();
+    let balances = vec![32_000_000_000; VALIDATOR_COUNT as usize];
+
+    BeaconState {
+        validators: VariableList::from(validators),
+        balances: VariableList::from(balances),
+        ..BeaconState::default()
+    }
+}
+
+// Roughly what happens to a state in a slot with a block in it.
+fn process_slot(state: &mut BeaconState<MainnetConfig>) {
+    let slot = state.slot as usize;
+    state.state_roots[slot % state.state_roots.len()] = H256::repeat_byte(1);
+    state.block_roots[slot % state.block_roots.len()] = H256::repeat_byte(2);
+    for index in 0..128 {
+        state.balances[(slot * 128 + index) % VALIDATOR_COUNT as usize] += 1;
+    }
+    state.slot += 1;
+}
+
+fn tree_hash_benchmarks(criterion: &mut Criterion) {
+    let state = mainnet_state();
+    let mut cached_state = state.clone();
+    cached_state.canonical_root();
+
+    let mut group = criterion.benchmark_group("beacon_state_root");
+    group.sample_size(10);
+
+    group.bench_function("hash_tree_root", |bencher| {
+        bencher.iter(|| state.tree_hash_root())
+    });
+
+    group.bench_function("canonical_root_without_cache", |bencher| {
+        bencher.iter_batched(
+            || state.clone(),
+            |state| state.canonical_root(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("canonical_root_after_slot", |bencher| {
+        bencher.iter_batched(
+            || {
+                let mut state = cached_state.clone();
+                process_slot(&mut state);
+                state
+            },
+            |state| state.canonical_root(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("canonical_root_unchanged", |bencher| {
+        bencher.iter(|| cached_state.canonical_root())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, tree_hash_benchmarks);
+criterion_main!(benches);
@@ -1,5 +1,7 @@
 use crate::{
-    config::*, consts, helper_functions_types::Error as HelperError, primitives::*, types::*,
+    committee_cache::CommitteeCache, config::*, consts,
+    helper_functions_types::Error as HelperError, primitives::*, pubkey_cache::PubkeyCache,
+    tree_hash_cache::TreeHashCache, types::*,
 };
 use ethereum_types::H256 as Hash256;
 use serde::{Deserialize, Serialize};
@@ -91,15 +93,45 @@ pub struct BeaconState<C: Config> {
     pub previous_justified_checkpoint: Checkpoint,
     pub current_justified_checkpoint: Checkpoint,
     pub finalized_checkpoint: Checkpoint,
+
+    // Caches
+    #[serde(skip)]
+    #[ssz(skip_serializing)]
+    #[ssz(skip_deserializing)]
+    #[tree_hash(skip_hashing)]
+    pub tree_hash_cache: TreeHashCache,
+    #[serde(skip)]
+    #[ssz(skip_serializing)]
+    #[ssz(skip_deserializing)]
+    #[tree_hash(skip_hashing)]
+    pub committee_cache: CommitteeCache,
+    #[serde(skip)]
+    #[ssz(skip_serializing)]
+    #[ssz(skip_deserializing)]
+    #[tree_hash(skip_hashing)]
+    pub pubkey_cache: PubkeyCache,
 }
 
 impl<C: Config> BeaconState<C> {
+    /// Equivalent to `hash_tree_root(self)` but only rehashes the parts of the state that changed
+    /// since the last call.
     pub fn canonical_root(&self) -> Hash256 {
-        Hash256::from_slice(&self.tree_hash_root()[..])
+        self.tree_hash_cache.root(self)
     }
 
     pub fn update_tree_hash_cache(&mut self) -> Result<Hash256, Error> {
-        Ok(Hash256::from_slice(&self.tree_hash_root()))
+        Ok(self.canonical_root())
+    }
+
+    /// Replaces the validator registry and discards caches that assume public keys of existing
+    /// validators never change. Assigning to `validators` directly leaves those caches stale.
+    pub fn replace_validators(
+        &mut self,
+        validators: VariableList<Validator, C::ValidatorRegistryLimit>,
+    ) {
+        self.validators = validators;
+        self.tree_hash_cache.invalidate();
+        self.pubkey_cache.invalidate();
     }
 
     fn get_latest_block_roots_index(&self, slot: Slot) -> Result<usize, Error> {
@@ -0,0 +1,108 @@
+//! Storage for the committees and proposers of recent epochs.
+//!
+//! Committees are computed by `helper_functions::beacon_state_accessors::get_epoch_committees`.
+//! Entries are keyed by epoch and seed. The active validators of an epoch cannot change once its
+//! seed is known, so an entry never has to be invalidated while the state is processed according
+//! to the specification.
+
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::primitives::{Epoch, ValidatorIndex, H256};
+
+// Enough for the previous, current and next epochs plus one spare.
+const MAX_ENTRIES: usize = 4;
+
+/// The committees of an epoch.
+#[derive(PartialEq, Debug)]
+pub struct EpochCommittees {
+    pub epoch: Epoch,
+    pub seed: H256,
+    /// Indices of validators active in `epoch` in ascending order.
+    pub active_indices: Vec<ValidatorIndex>,
+    /// `active_indices` in shuffled order. Every committee is a contiguous range of it.
+    pub shuffling: Vec<ValidatorIndex>,
+    pub committees_per_slot: u64,
+}
+
+/// Committees and proposers of recent epochs.
+#[derive(Clone, Default)]
+pub struct CommitteeCache {
+    committees: EpochCache<EpochCommittees>,
+    proposers: EpochCache<Vec<ValidatorIndex>>,
+}
+
+impl CommitteeCache {
+    pub fn get(&self, epoch: Epoch, seed: H256) -> Option<Arc<EpochCommittees>> {
+        self.committees.get(epoch, seed)
+    }
+
+    /// Stores `committees`, evicting the oldest entry if the cache is full.
+    pub fn insert(&self, committees: Arc<EpochCommittees>) {
+        self.committees
+            .insert(committees.epoch, committees.seed, committees)
+    }
+
+    /// Returns the proposers of the slots in `epoch`. `seed` is the proposer seed of `epoch`.
+    pub fn get_proposers(&self, epoch: Epoch, seed: H256) -> Option<Arc<Vec<ValidatorIndex>>> {
+        self.proposers.get(epoch, seed)
+    }
+
+    pub fn insert_proposers(&self, epoch: Epoch, seed: H256, proposers: Arc<Vec<ValidatorIndex>>) {
+        self.proposers.insert(epoch, seed, proposers)
+    }
+}
+
+struct EpochCache<T>(Mutex<VecDeque<(Epoch, H256, Arc<T>)>>);
+
+impl<T> Default for EpochCache<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T> Clone for EpochCache<T> {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl<T> EpochCache<T> {
+    fn get(&self, epoch: Epoch, seed: H256) -> Option<Arc<T>> {
+        self.lock()
+            .iter()
+            .find(|(entry_epoch, entry_seed, _)| *entry_epoch == epoch && *entry_seed == seed)
+            .map(|(_, _, value)| value.clone())
+    }
+
+    fn insert(&self, epoch: Epoch, seed: H256, value: Arc<T>) {
+        let mut entries = self.lock();
+        entries.retain(|(entry_epoch, entry_seed, _)| *entry_epoch != epoch || *entry_seed != seed);
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back((epoch, seed, value));
+    }
+
+    fn lock(&self) -> MutexGuard<VecDeque<(Epoch, H256, Arc<T>)>> {
+        self.0
+            .lock()
+            .expect("committee cache mutex should not be poisoned")
+    }
+}
+
+// The cache is not part of the state. See `TreeHashCache`.
+impl PartialEq for CommitteeCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Debug for CommitteeCache {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.write_str("CommitteeCache")
+    }
+}
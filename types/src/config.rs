@@ -199,6 +199,9 @@ where
     fn ejection_balance() -> u64 {
         16_000_000_000
     }
+    fn eth1_follow_distance() -> u64 {
+        1024
+    }
     fn genesis_epoch() -> u64 {
         0
     }
@@ -252,6 +255,9 @@ where
     fn proposer_reward_quotient() -> u64 {
         8
     }
+    fn safe_slots_to_update_justified() -> u64 {
+        8
+    }
     fn shuffle_round_count() -> u64 {
         10
     }
@@ -308,9 +314,15 @@ impl Config for MinimalConfig {
     type SlotsPerHistoricalRoot = typenum::U64;
     type ValidatorRegistryLimit = typenum::U1099511627776;
 
+    fn eth1_follow_distance() -> u64 {
+        16
+    }
     fn max_committees_per_slot() -> u64 {
         4
     }
+    fn safe_slots_to_update_justified() -> u64 {
+        2
+    }
     fn target_committee_size() -> u64 {
         4
     }
@@ -8,4 +8,6 @@ pub enum Error {
     NumberExceedsCapacity,
     ArrayIsEmpty,
     NotAHash,
+    EpochOutOfRange,
+    AggregationBitsLengthMismatch,
 }
@@ -3,10 +3,13 @@
 #![allow(warnings)]
 
 pub mod beacon_state;
+pub mod committee_cache;
 pub mod config;
 pub mod consts;
 pub mod helper_functions_types;
 pub mod primitives;
+pub mod pubkey_cache;
+pub mod tree_hash_cache;
 pub mod types;
 
 pub use crate::beacon_state::{Error as BeaconStateError, *};
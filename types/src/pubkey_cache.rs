@@ -0,0 +1,141 @@
+//! Maps public keys of validators to their indices.
+//!
+//! `Validator` stores public keys decompressed, which is the form needed to verify signatures.
+//! Deposits contain compressed public keys. Compressing the key of every validator to find the one
+//! a deposit belongs to is slow, so the compressed keys are kept in a map.
+//!
+//! Public keys of validators are assumed to never change once a validator is added to the
+//! registry, as required by the specification. Code that replaces the whole registry must call
+//! [`PubkeyCache::invalidate`] (see `BeaconState::replace_validators`). The map is also rebuilt if
+//! the registry gets shorter.
+
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use bls::PublicKeyBytes;
+
+use crate::{primitives::ValidatorIndex, types::Validator};
+
+#[derive(Default)]
+pub struct PubkeyCache(Mutex<Indices>);
+
+#[derive(Clone, Default)]
+struct Indices {
+    // The number of validators in the map.
+    len: usize,
+    map: HashMap<PublicKeyBytes, ValidatorIndex>,
+}
+
+impl PubkeyCache {
+    /// Discards the map. It will be rebuilt on the next lookup.
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the index of the validator in `validators` with the public key `pubkey`.
+    ///
+    /// Validators missing from the cache are added to it first.
+    pub fn get(&self, validators: &[Validator], pubkey: &PublicKeyBytes) -> Option<ValidatorIndex> {
+        let mut indices = self.lock();
+
+        if validators.len() < indices.len {
+            *indices = Indices::default();
+        }
+
+        for (index, validator) in validators.iter().enumerate().skip(indices.len) {
+            let bytes = PublicKeyBytes::from_bytes(validator.pubkey.as_bytes().as_slice())
+                .expect("compressed public keys have the right length");
+            // The specification uses the first matching validator. There should be only one.
+            indices.map.entry(bytes).or_insert(index as ValidatorIndex);
+        }
+        indices.len = validators.len();
+
+        indices.map.get(pubkey).copied()
+    }
+
+    fn lock(&self) -> MutexGuard<Indices> {
+        self.0
+            .lock()
+            .expect("pubkey cache mutex should not be poisoned")
+    }
+}
+
+impl Clone for PubkeyCache {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+// The cache is not part of the state. See `TreeHashCache`.
+impl PartialEq for PubkeyCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Debug for PubkeyCache {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.write_str("PubkeyCache")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bls::{PublicKey, SecretKey};
+
+    use super::*;
+
+    fn validator() -> Validator {
+        Validator {
+            pubkey: PublicKey::from_secret_key(&SecretKey::random()),
+            ..Validator::default()
+        }
+    }
+
+    fn bytes(validator: &Validator) -> PublicKeyBytes {
+        PublicKeyBytes::from_bytes(validator.pubkey.as_bytes().as_slice())
+            .expect("compressed public keys have the right length")
+    }
+
+    #[test]
+    fn finds_validators_added_after_first_lookup() {
+        let cache = PubkeyCache::default();
+        let mut validators = vec![validator(), validator()];
+        let new_validator = validator();
+
+        assert_eq!(cache.get(&validators, &bytes(&validators[1])), Some(1));
+        assert_eq!(cache.get(&validators, &bytes(&new_validator)), None);
+
+        validators.push(new_validator.clone());
+        assert_eq!(cache.get(&validators, &bytes(&new_validator)), Some(2));
+        assert_eq!(cache.get(&validators, &bytes(&validators[0])), Some(0));
+    }
+
+    #[test]
+    fn is_rebuilt_when_registry_shrinks() {
+        let cache = PubkeyCache::default();
+        let validators = vec![validator(), validator(), validator()];
+        assert_eq!(cache.get(&validators, &bytes(&validators[2])), Some(2));
+
+        let replacement = vec![validators[2].clone()];
+        assert_eq!(cache.get(&replacement, &bytes(&validators[2])), Some(0));
+        assert_eq!(cache.get(&replacement, &bytes(&validators[0])), None);
+    }
+
+    #[test]
+    fn finds_replaced_public_keys_after_invalidation() {
+        let mut cache = PubkeyCache::default();
+        let mut validators = vec![validator(), validator()];
+        let old_validator = validators[1].clone();
+        assert_eq!(cache.get(&validators, &bytes(&old_validator)), Some(1));
+
+        validators[1] = validator();
+        cache.invalidate();
+        assert_eq!(cache.get(&validators, &bytes(&validators[1])), Some(1));
+        assert_eq!(cache.get(&validators, &bytes(&old_validator)), None);
+        assert_eq!(cache.get(&validators, &bytes(&validators[0])), Some(0));
+    }
+}
@@ -0,0 +1,534 @@
+//! Incremental tree hashing for `BeaconState`.
+//!
+//! The cache keeps the Merkle trees of the large fields of a `BeaconState`. Every update compares
+//! the leaves of each tree with the current contents of the state and only rehashes the paths
+//! above leaves that changed. Small fields are hashed from scratch every time.
+//!
+//! Public keys of validators are only hashed when validators are added. The specification never
+//! changes the public key of an existing validator. Code that replaces the whole registry must call
+//! [`TreeHashCache::invalidate`] (see [`BeaconState::replace_validators`]). The caches are also
+//! rebuilt if the registry ever gets shorter.
+//!
+//! Clones of a state share its cache until one of them is updated.
+
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use ethereum_types::H256;
+use ssz_types::{FixedVector, VariableList};
+use tree_hash::TreeHash;
+use typenum::Unsigned;
+
+use crate::{
+    beacon_state::BeaconState,
+    config::Config,
+    types::{Eth1Data, PendingAttestation, Validator},
+};
+
+const BYTES_PER_CHUNK: usize = 32;
+
+// `BeaconState` has 20 fields.
+const STATE_FIELD_DEPTH: usize = 5;
+
+/// A cache of the Merkle trees of a `BeaconState`.
+///
+/// The cache is not part of the state as far as SSZ and the specification are concerned. Two
+/// caches always compare as equal so that states can be compared with `==` regardless of whether
+/// their caches have been populated.
+#[derive(Default)]
+pub struct TreeHashCache(Mutex<Option<Arc<StateCache>>>);
+
+impl TreeHashCache {
+    /// Discards the cached trees. The next update will hash the whole state.
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn root<C: Config>(&self, state: &BeaconState<C>) -> H256 {
+        let mut cache = self.lock();
+        let cache = cache.get_or_insert_with(|| Arc::new(StateCache::new::<C>()));
+        Arc::make_mut(cache).update(state)
+    }
+
+    fn lock(&self) -> MutexGuard<Option<Arc<StateCache>>> {
+        self.0
+            .lock()
+            .expect("tree hash cache mutex should not be poisoned")
+    }
+}
+
+impl Clone for TreeHashCache {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.lock().clone()))
+    }
+}
+
+impl PartialEq for TreeHashCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Debug for TreeHashCache {
+    fn fmt(&self, formatter: &mut Formatter) -> FmtResult {
+        formatter.write_str("TreeHashCache")
+    }
+}
+
+#[derive(Clone)]
+struct StateCache {
+    block_roots: MerkleCache,
+    state_roots: MerkleCache,
+    historical_roots: MerkleCache,
+    eth1_data_votes: MerkleCache,
+    validators: ValidatorsCache,
+    balances: MerkleCache,
+    randao_mixes: MerkleCache,
+    slashings: MerkleCache,
+    previous_epoch_attestations: MerkleCache,
+    current_epoch_attestations: MerkleCache,
+    fields: MerkleCache,
+}
+
+impl StateCache {
+    fn new<C: Config>() -> Self {
+        Self {
+            block_roots: MerkleCache::new(depth_for::<H256>(C::SlotsPerHistoricalRoot::USIZE)),
+            state_roots: MerkleCache::new(depth_for::<H256>(C::SlotsPerHistoricalRoot::USIZE)),
+            historical_roots: MerkleCache::new(depth_for::<H256>(C::HistoricalRootsLimit::USIZE)),
+            eth1_data_votes: MerkleCache::new(depth_for::<Eth1Data>(
+                C::SlotsPerEth1VotingPeriod::USIZE,
+            )),
+            validators: ValidatorsCache::new(depth_for::<Validator>(
+                C::ValidatorRegistryLimit::USIZE,
+            )),
+            balances: MerkleCache::new(depth_for::<u64>(C::ValidatorRegistryLimit::USIZE)),
+            randao_mixes: MerkleCache::new(depth_for::<H256>(C::EpochsPerHistoricalVector::USIZE)),
+            slashings: MerkleCache::new(depth_for::<u64>(C::EpochsPerSlashingsVector::USIZE)),
+            previous_epoch_attestations: MerkleCache::new(depth_for::<PendingAttestation<C>>(
+                C::MaxAttestationsPerEpoch::USIZE,
+            )),
+            current_epoch_attestations: MerkleCache::new(depth_for::<PendingAttestation<C>>(
+                C::MaxAttestationsPerEpoch::USIZE,
+            )),
+            fields: MerkleCache::new(STATE_FIELD_DEPTH),
+        }
+    }
+
+    fn update<C: Config>(&mut self, state: &BeaconState<C>) -> H256 {
+        let fields = [
+            root_of(&state.genesis_time),
+            root_of(&state.slot),
+            root_of(&state.fork),
+            root_of(&state.latest_block_header),
+            self.block_roots.update_vector(&state.block_roots),
+            self.state_roots.update_vector(&state.state_roots),
+            self.historical_roots.update_list(&state.historical_roots),
+            self.eth1_data_votes.update_list(&state.eth1_data_votes),
+            root_of(&state.eth1_data),
+            root_of(&state.eth1_deposit_index),
+            self.validators.update(&state.validators),
+            self.balances.update_list(&state.balances),
+            self.randao_mixes.update_vector(&state.randao_mixes),
+            self.slashings.update_vector(&state.slashings),
+            self.previous_epoch_attestations
+                .update_list(&state.previous_epoch_attestations),
+            self.current_epoch_attestations
+                .update_list(&state.current_epoch_attestations),
+            root_of(&state.justification_bits),
+            root_of(&state.previous_justified_checkpoint),
+            root_of(&state.current_justified_checkpoint),
+            root_of(&state.finalized_checkpoint),
+        ];
+        self.fields.update(fields.iter().copied())
+    }
+}
+
+/// The fields of a `Validator` other than `pubkey`.
+#[derive(Clone, PartialEq)]
+struct ValidatorFields {
+    withdrawal_credentials: H256,
+    effective_balance: u64,
+    slashed: bool,
+    activation_eligibility_epoch: u64,
+    activation_epoch: u64,
+    exit_epoch: u64,
+    withdrawable_epoch: u64,
+}
+
+impl From<&Validator> for ValidatorFields {
+    fn from(validator: &Validator) -> Self {
+        Self {
+            withdrawal_credentials: validator.withdrawal_credentials,
+            effective_balance: validator.effective_balance,
+            slashed: validator.slashed,
+            activation_eligibility_epoch: validator.activation_eligibility_epoch,
+            activation_epoch: validator.activation_epoch,
+            exit_epoch: validator.exit_epoch,
+            withdrawable_epoch: validator.withdrawable_epoch,
+        }
+    }
+}
+
+impl ValidatorFields {
+    fn root(&self, pubkey_root: H256) -> H256 {
+        let mut nodes = [
+            pubkey_root,
+            self.withdrawal_credentials,
+            root_of(&self.effective_balance),
+            root_of(&self.slashed),
+            root_of(&self.activation_eligibility_epoch),
+            root_of(&self.activation_epoch),
+            root_of(&self.exit_epoch),
+            root_of(&self.withdrawable_epoch),
+        ];
+        // `Validator` has 8 fields, so its tree is full.
+        let mut width = nodes.len();
+        while width > 1 {
+            width /= 2;
+            for index in 0..width {
+                nodes[index] = hash_pair(nodes[2 * index], nodes[2 * index + 1]);
+            }
+        }
+        nodes[0]
+    }
+}
+
+// Comparing the fields of a `Validator` with their previous values is cheaper than hashing them.
+// Public keys are assumed not to change, so they are not compared at all.
+#[derive(Clone)]
+struct ValidatorsCache {
+    pubkey_roots: Vec<H256>,
+    fields: Vec<ValidatorFields>,
+    roots: Vec<H256>,
+    tree: MerkleCache,
+}
+
+impl ValidatorsCache {
+    fn new(depth: usize) -> Self {
+        Self {
+            pubkey_roots: vec![],
+            fields: vec![],
+            roots: vec![],
+            tree: MerkleCache::new(depth),
+        }
+    }
+
+    fn update<N: Unsigned>(&mut self, validators: &VariableList<Validator, N>) -> H256 {
+        if validators.len() < self.roots.len() {
+            *self = Self::new(self.tree.depth);
+        }
+
+        for (index, validator) in validators.iter().enumerate() {
+            let fields = ValidatorFields::from(validator);
+            if index < self.roots.len() {
+                if self.fields[index] != fields {
+                    self.roots[index] = fields.root(self.pubkey_roots[index]);
+                    self.fields[index] = fields;
+                }
+            } else {
+                let pubkey_root = root_of(&validator.pubkey);
+                self.roots.push(fields.root(pubkey_root));
+                self.pubkey_roots.push(pubkey_root);
+                self.fields.push(fields);
+            }
+        }
+
+        let root = self.tree.update(self.roots.iter().copied());
+        mix_in_length(root, validators.len())
+    }
+}
+
+/// A Merkle tree of fixed depth that keeps all of its nodes.
+///
+/// Only nodes that have at least one leaf under them are stored. The rest are known to be the
+/// roots of trees of zeros.
+#[derive(Clone)]
+struct MerkleCache {
+    depth: usize,
+    // `layers[0]` contains the leaves. `layers[depth]` contains the root if there are any leaves.
+    layers: Vec<Vec<H256>>,
+    zero_hashes: Vec<H256>,
+}
+
+impl MerkleCache {
+    fn new(depth: usize) -> Self {
+        let mut zero_hashes = vec![H256::zero()];
+        for height in 0..depth {
+            zero_hashes.push(hash_pair(zero_hashes[height], zero_hashes[height]));
+        }
+        Self {
+            depth,
+            layers: vec![vec![]; depth + 1],
+            zero_hashes,
+        }
+    }
+
+    fn update_vector<T: TreeHash, N: Unsigned>(&mut self, vector: &FixedVector<T, N>) -> H256 {
+        self.update(chunks(vector))
+    }
+
+    fn update_list<T: TreeHash, N: Unsigned>(&mut self, list: &VariableList<T, N>) -> H256 {
+        mix_in_length(self.update(chunks(list)), list.len())
+    }
+
+    fn update(&mut self, leaves: impl Iterator<Item = H256>) -> H256 {
+        let old_length = self.layers[0].len();
+        let mut dirty = vec![];
+        let mut length = 0;
+
+        for (index, leaf) in leaves.enumerate() {
+            if index >= old_length {
+                self.layers[0].push(leaf);
+                dirty.push(index);
+            } else if self.layers[0][index] != leaf {
+                self.layers[0][index] = leaf;
+                dirty.push(index);
+            }
+            length = index + 1;
+        }
+
+        if length < old_length {
+            // Every node to the right of the new last leaf has to be dropped. Rebuilding the whole
+            // tree is simpler and shrinking lists are rare.
+            self.layers[0].truncate(length);
+            for layer in &mut self.layers[1..] {
+                layer.clear();
+            }
+            dirty = (0..length).collect();
+        }
+
+        for height in 1..=self.depth {
+            let (lower, upper) = self.layers.split_at_mut(height);
+            let children = &lower[height - 1];
+            let parents = &mut upper[0];
+
+            parents.resize((children.len() + 1) / 2, H256::zero());
+            dirty.dedup_by_key(|index| *index / 2);
+
+            for index in &mut dirty {
+                *index /= 2;
+                let left = children[*index * 2];
+                let right = children
+                    .get(*index * 2 + 1)
+                    .copied()
+                    .unwrap_or(self.zero_hashes[height - 1]);
+                parents[*index] = hash_pair(left, right);
+            }
+        }
+
+        self.layers[self.depth]
+            .first()
+            .copied()
+            .unwrap_or(self.zero_hashes[self.depth])
+    }
+}
+
+// Packs basic values into chunks. Composite values are replaced with their roots.
+fn chunks<'a, T: TreeHash>(values: &'a [T]) -> impl Iterator<Item = H256> + 'a {
+    let packing_factor = match T::tree_hash_type() {
+        tree_hash::TreeHashType::Basic => T::tree_hash_packing_factor(),
+        _ => 1,
+    };
+    values.chunks(packing_factor).map(move |values| {
+        if packing_factor == 1 {
+            root_of(&values[0])
+        } else {
+            let mut chunk = H256::zero();
+            let bytes = values
+                .iter()
+                .flat_map(|value| value.tree_hash_packed_encoding())
+                .collect::<Vec<_>>();
+            chunk.as_bytes_mut()[..bytes.len()].copy_from_slice(bytes.as_slice());
+            chunk
+        }
+    })
+}
+
+fn depth_for<T: TreeHash>(limit: usize) -> usize {
+    let chunk_limit = match T::tree_hash_type() {
+        tree_hash::TreeHashType::Basic => {
+            let packing_factor = T::tree_hash_packing_factor();
+            (limit + packing_factor - 1) / packing_factor
+        }
+        _ => limit,
+    };
+    chunk_limit.next_power_of_two().trailing_zeros() as usize
+}
+
+fn root_of(value: &impl TreeHash) -> H256 {
+    H256::from_slice(value.tree_hash_root().as_slice())
+}
+
+fn mix_in_length(root: H256, length: usize) -> H256 {
+    let mut length_chunk = H256::zero();
+    length_chunk.as_bytes_mut()[..8].copy_from_slice(&(length as u64).to_le_bytes());
+    hash_pair(root, length_chunk)
+}
+
+fn hash_pair(left: H256, right: H256) -> H256 {
+    let mut bytes = [0; 2 * BYTES_PER_CHUNK];
+    bytes[..BYTES_PER_CHUNK].copy_from_slice(left.as_bytes());
+    bytes[BYTES_PER_CHUNK..].copy_from_slice(right.as_bytes());
+    H256::from_slice(eth2_hashing::hash(&bytes).as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use bls::{PublicKey, SecretKey};
+
+    use crate::{
+        config::{MainnetConfig, MinimalConfig},
+        types::Checkpoint,
+    };
+
+    use super::*;
+
+    fn validator(index: u64) -> Validator {
+        Validator {
+            withdrawal_credentials: H256::from_low_u64_be(index),
+            effective_balance: index * 1_000_000_000,
+            exit_epoch: u64::max_value(),
+            ..Validator::default()
+        }
+    }
+
+    fn state_with_validators<C: Config>(count: u64) -> BeaconState<C> {
+        let mut state = BeaconState::<C>::default();
+        for index in 0..count {
+            state
+                .validators
+                .push(validator(index))
+                .expect("registry limit is not reached");
+            state
+                .balances
+                .push(index * 3)
+                .expect("registry limit is not reached");
+        }
+        state
+    }
+
+    fn assert_cache_matches<C: Config>(state: &mut BeaconState<C>) {
+        let expected = H256::from_slice(state.tree_hash_root().as_slice());
+        assert_eq!(state.canonical_root(), expected);
+        assert_eq!(state.update_tree_hash_cache(), Ok(expected));
+    }
+
+    #[test]
+    fn empty_state_root_matches() {
+        assert_cache_matches(&mut BeaconState::<MinimalConfig>::default());
+        assert_cache_matches(&mut BeaconState::<MainnetConfig>::default());
+    }
+
+    #[test]
+    fn root_matches_after_updates() {
+        let mut state = state_with_validators::<MinimalConfig>(13);
+        assert_cache_matches(&mut state);
+
+        state.slot = 5;
+        state.balances[4] += 1;
+        state.validators[7].slashed = true;
+        state.block_roots[3] = H256::repeat_byte(3);
+        state.slashings[10] = 17;
+        assert_cache_matches(&mut state);
+
+        state
+            .validators
+            .push(validator(13))
+            .expect("registry limit is not reached");
+        state
+            .balances
+            .push(1)
+            .expect("registry limit is not reached");
+        state
+            .historical_roots
+            .push(H256::repeat_byte(1))
+            .expect("limit is not reached");
+        state
+            .eth1_data_votes
+            .push(Eth1Data::default())
+            .expect("limit is not reached");
+        state
+            .current_epoch_attestations
+            .push(PendingAttestation::default())
+            .expect("limit is not reached");
+        state.finalized_checkpoint = Checkpoint {
+            epoch: 1,
+            root: H256::repeat_byte(9),
+        };
+        assert_cache_matches(&mut state);
+    }
+
+    #[test]
+    fn root_matches_after_lists_shrink() {
+        let mut state = state_with_validators::<MinimalConfig>(9);
+        state
+            .eth1_data_votes
+            .push(Eth1Data::default())
+            .expect("limit is not reached");
+        assert_cache_matches(&mut state);
+
+        state.eth1_data_votes = VariableList::default();
+        state.validators = VariableList::from(vec![validator(20), validator(21)]);
+        state.balances = VariableList::from(vec![5, 6]);
+        assert_cache_matches(&mut state);
+    }
+
+    #[test]
+    fn root_matches_after_registry_is_replaced_with_different_keys() {
+        let mut state = state_with_validators::<MinimalConfig>(4);
+        for validator in state.validators.iter_mut() {
+            validator.pubkey = PublicKey::from_secret_key(&SecretKey::random());
+        }
+        assert_cache_matches(&mut state);
+
+        let mut validators = state.validators.clone();
+        for validator in validators.iter_mut() {
+            validator.pubkey = PublicKey::from_secret_key(&SecretKey::random());
+        }
+        state.replace_validators(validators);
+        assert_cache_matches(&mut state);
+    }
+
+    #[test]
+    fn clones_share_cache_until_updated() {
+        let state = state_with_validators::<MinimalConfig>(5);
+        state.canonical_root();
+
+        let mut clone = state.clone();
+        let shared = |state: &BeaconState<MinimalConfig>, clone: &BeaconState<MinimalConfig>| match (
+            &*state.tree_hash_cache.lock(),
+            &*clone.tree_hash_cache.lock(),
+        ) {
+            (Some(cache), Some(clone_cache)) => Arc::ptr_eq(cache, clone_cache),
+            _ => false,
+        };
+        assert!(shared(&state, &clone));
+
+        clone.balances[0] = 100;
+        assert_cache_matches(&mut clone);
+        assert!(!shared(&state, &clone));
+    }
+
+    #[test]
+    fn cloned_cache_is_independent() {
+        let mut state = state_with_validators::<MinimalConfig>(5);
+        assert_cache_matches(&mut state);
+
+        let mut clone = state.clone();
+        clone.balances[0] = 100;
+        assert_cache_matches(&mut clone);
+
+        state.validators[1].exit_epoch = 3;
+        assert_cache_matches(&mut state);
+        assert_ne!(state.canonical_root(), clone.canonical_root());
+    }
+
+    #[test]
+    fn cache_does_not_affect_equality() {
+        let state = state_with_validators::<MinimalConfig>(3);
+        let clone = state.clone();
+        state.canonical_root();
+        assert_eq!(state, clone);
+    }
+}
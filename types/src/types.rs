@@ -66,8 +66,8 @@ pub struct AttestationDataAndCustodyBit {
 )]
 pub struct AttestationDuty {
     pub slot: Slot,
-    pub shard: Shard,
-    pub committee_index: usize,
+    pub index: u64,
+    pub committee_position: usize,
     pub committee_len: usize,
 }
 
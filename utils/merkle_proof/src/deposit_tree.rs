@@ -0,0 +1,372 @@
+use ethereum_types::H256;
+use lazy_static::lazy_static;
+
+use crate::{fill_zero_hashes, hash_and_concat, MerkleProofError};
+
+// `DEPOSIT_CONTRACT_TREE_DEPTH` in the specification.
+// This crate does not depend on `types`, so the constant is repeated here.
+pub const DEPOSIT_TREE_DEPTH: usize = 32;
+
+lazy_static! {
+    static ref ZERO_HASHES: Vec<H256> = fill_zero_hashes();
+}
+
+/// The state of a [`DepositTree`] that is enough to continue appending deposits to it.
+///
+/// Based on the snapshot format proposed in [EIP-4881]. `finalized` contains the roots of the
+/// largest complete subtrees covering the first `deposit_count` leaves, from left to right.
+///
+/// [EIP-4881]: https://eips.ethereum.org/EIPS/eip-4881
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DepositTreeSnapshot {
+    pub finalized: Vec<H256>,
+    pub deposit_root: H256,
+    pub deposit_count: u64,
+}
+
+/// An incremental Merkle tree of deposits, equivalent to the one in the [deposit contract].
+///
+/// Every complete node is stored, so appending a leaf takes amortized constant time and roots and
+/// proofs can be produced for any number of deposits made so far. A tree restored from a
+/// [`DepositTreeSnapshot`] does not contain nodes to the left of the snapshot and cannot produce
+/// proofs for most of the deposits included in it.
+///
+/// [deposit contract]: https://github.com/ethereum/eth2.0-specs/blob/65b615a4d4cf75a50b29d25c53f1bc5422770ae5/deposit_contract/contracts/validator_registration.v.py
+#[derive(Clone, Debug)]
+pub struct DepositTree {
+    // `layers[0]` contains leaves. `layers[DEPOSIT_TREE_DEPTH]` contains at most the root.
+    layers: Vec<Vec<H256>>,
+    // The index of the first node stored in each layer. These are all 0 unless the tree was
+    // restored from a snapshot.
+    offsets: Vec<u64>,
+    leaf_count: u64,
+}
+
+impl Default for DepositTree {
+    fn default() -> Self {
+        Self {
+            layers: vec![vec![]; DEPOSIT_TREE_DEPTH + 1],
+            offsets: vec![0; DEPOSIT_TREE_DEPTH + 1],
+            leaf_count: 0,
+        }
+    }
+}
+
+impl DepositTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_snapshot(snapshot: &DepositTreeSnapshot) -> Result<Self, MerkleProofError> {
+        let DepositTreeSnapshot {
+            finalized,
+            deposit_root,
+            deposit_count,
+        } = snapshot;
+
+        if *deposit_count > max_leaf_count() {
+            return Err(MerkleProofError::TreeFull);
+        }
+
+        if finalized.len() != deposit_count.count_ones() as usize {
+            return Err(MerkleProofError::InvalidParamLength {
+                len_first: finalized.len(),
+                len_second: deposit_count.count_ones() as usize,
+            });
+        }
+
+        let mut tree = Self {
+            leaf_count: *deposit_count,
+            ..Self::default()
+        };
+
+        let mut finalized = finalized.iter().copied();
+
+        for depth in (0..=DEPOSIT_TREE_DEPTH).rev() {
+            let complete_nodes = deposit_count >> depth;
+            if complete_nodes % 2 == 1 {
+                let node = finalized
+                    .next()
+                    .expect("length of finalized was checked above");
+                tree.layers[depth].push(node);
+                tree.offsets[depth] = complete_nodes - 1;
+            } else {
+                tree.offsets[depth] = complete_nodes;
+            }
+        }
+
+        let actual = tree.root_at(*deposit_count)?;
+        if actual != *deposit_root {
+            return Err(MerkleProofError::RootMismatch {
+                expected: *deposit_root,
+                actual,
+            });
+        }
+
+        Ok(tree)
+    }
+
+    pub fn snapshot(&self, deposit_count: u64) -> Result<DepositTreeSnapshot, MerkleProofError> {
+        self.check_count(deposit_count)?;
+
+        let finalized = (0..=DEPOSIT_TREE_DEPTH)
+            .rev()
+            .filter(|depth| (deposit_count >> depth) % 2 == 1)
+            .map(|depth| self.node(depth, (deposit_count >> depth) - 1, deposit_count))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DepositTreeSnapshot {
+            finalized,
+            deposit_root: self.root_at(deposit_count)?,
+            deposit_count,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.leaf_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaf_count == 0
+    }
+
+    /// Appends a leaf. Deposit leaves are `hash_tree_root(deposit.data)`.
+    pub fn push(&mut self, leaf: H256) -> Result<(), MerkleProofError> {
+        if self.leaf_count == max_leaf_count() {
+            return Err(MerkleProofError::TreeFull);
+        }
+
+        self.layers[0].push(leaf);
+        self.leaf_count += 1;
+
+        let mut node = leaf;
+        let mut index = self.leaf_count - 1;
+
+        for depth in 0..DEPOSIT_TREE_DEPTH {
+            if index % 2 == 0 {
+                break;
+            }
+            let sibling = self.stored_node(depth, index - 1)?;
+            node = hash_and_concat(sibling, node);
+            index /= 2;
+            self.layers[depth + 1].push(node);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the root of the tree with the length mixed in, as returned by `get_deposit_root` in
+    /// the deposit contract.
+    pub fn root(&self) -> H256 {
+        self.root_at(self.leaf_count)
+            .expect("root of the whole tree only depends on stored nodes")
+    }
+
+    /// Like [`DepositTree::root`], but as it was after `deposit_count` deposits.
+    pub fn root_at(&self, deposit_count: u64) -> Result<H256, MerkleProofError> {
+        self.check_count(deposit_count)?;
+        let root = self.node(DEPOSIT_TREE_DEPTH, 0, deposit_count)?;
+        Ok(hash_and_concat(root, length_node(deposit_count)))
+    }
+
+    /// Returns the branch proving that leaf `index` is in the tree as it was after
+    /// `deposit_count` deposits.
+    ///
+    /// The last node of the branch is the mixed in length. The branch can be checked with
+    /// `is_valid_merkle_branch` using a depth of `DEPOSIT_TREE_DEPTH + 1`, as in `process_deposit`.
+    pub fn proof(&self, index: u64, deposit_count: u64) -> Result<Vec<H256>, MerkleProofError> {
+        self.check_count(deposit_count)?;
+
+        if deposit_count <= index {
+            return Err(MerkleProofError::IndexOutOfRange {
+                index,
+                deposit_count,
+            });
+        }
+
+        let mut proof = (0..DEPOSIT_TREE_DEPTH)
+            .map(|depth| self.node(depth, (index >> depth) ^ 1, deposit_count))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        proof.push(length_node(deposit_count));
+
+        Ok(proof)
+    }
+
+    fn check_count(&self, deposit_count: u64) -> Result<(), MerkleProofError> {
+        if self.leaf_count < deposit_count {
+            return Err(MerkleProofError::CountOutOfRange {
+                deposit_count,
+                leaf_count: self.leaf_count,
+            });
+        }
+        Ok(())
+    }
+
+    // Returns the node at `depth` and `index` in the tree containing the first `deposit_count`
+    // leaves. At most one node per layer is incomplete, so this recurses at most once per layer.
+    fn node(&self, depth: usize, index: u64, deposit_count: u64) -> Result<H256, MerkleProofError> {
+        let first_leaf = index << depth;
+        let last_leaf = (index + 1) << depth;
+
+        if deposit_count <= first_leaf {
+            Ok(ZERO_HASHES[depth])
+        } else if last_leaf <= deposit_count {
+            self.stored_node(depth, index)
+        } else {
+            let left = self.node(depth - 1, index * 2, deposit_count)?;
+            let right = self.node(depth - 1, index * 2 + 1, deposit_count)?;
+            Ok(hash_and_concat(left, right))
+        }
+    }
+
+    fn stored_node(&self, depth: usize, index: u64) -> Result<H256, MerkleProofError> {
+        index
+            .checked_sub(self.offsets[depth])
+            .and_then(|position| self.layers[depth].get(position as usize))
+            .copied()
+            .ok_or(MerkleProofError::NodeNotAvailable { depth, index })
+    }
+}
+
+fn max_leaf_count() -> u64 {
+    1 << DEPOSIT_TREE_DEPTH
+}
+
+fn length_node(deposit_count: u64) -> H256 {
+    let mut node = H256::zero();
+    node.as_bytes_mut()[..8].copy_from_slice(&deposit_count.to_le_bytes());
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::verify_merkle_proof;
+
+    use super::*;
+
+    fn leaf(index: u64) -> H256 {
+        H256::from_low_u64_be(index + 1)
+    }
+
+    fn tree_with_leaves(count: u64) -> DepositTree {
+        let mut tree = DepositTree::new();
+        for index in 0..count {
+            tree.push(leaf(index)).expect("tree is not full");
+        }
+        tree
+    }
+
+    // Computes the root the slow way, padding every layer with zero hashes.
+    fn naive_root(leaves: &[H256]) -> H256 {
+        let mut layer = leaves.to_vec();
+        for depth in 0..DEPOSIT_TREE_DEPTH {
+            if layer.len() % 2 == 1 {
+                layer.push(ZERO_HASHES[depth]);
+            }
+            layer = layer
+                .chunks(2)
+                .map(|pair| hash_and_concat(pair[0], pair[1]))
+                .collect();
+        }
+        let root = layer
+            .first()
+            .copied()
+            .unwrap_or(ZERO_HASHES[DEPOSIT_TREE_DEPTH]);
+        hash_and_concat(root, length_node(leaves.len() as u64))
+    }
+
+    #[test]
+    fn empty_tree_has_root_of_zero_hashes() {
+        assert_eq!(
+            DepositTree::new().root(),
+            hash_and_concat(ZERO_HASHES[DEPOSIT_TREE_DEPTH], H256::zero()),
+        );
+    }
+
+    #[test]
+    fn roots_at_every_count_match_naive_roots() -> Result<(), MerkleProofError> {
+        let leaves = (0..20).map(leaf).collect::<Vec<_>>();
+        let tree = tree_with_leaves(20);
+        for count in 0..=20 {
+            assert_eq!(tree.root_at(count)?, naive_root(&leaves[..count as usize]));
+        }
+        assert_eq!(tree.root(), naive_root(&leaves));
+        Ok(())
+    }
+
+    #[test]
+    fn proofs_verify_against_roots_at_every_count() -> Result<(), MerkleProofError> {
+        let tree = tree_with_leaves(13);
+        for count in 1..=13 {
+            let root = tree.root_at(count)?;
+            for index in 0..count {
+                let proof = tree.proof(index, count)?;
+                let generalized_index = (1 << (DEPOSIT_TREE_DEPTH + 1)) + index as usize;
+                assert!(verify_merkle_proof(
+                    leaf(index),
+                    &proof,
+                    DEPOSIT_TREE_DEPTH + 1,
+                    generalized_index,
+                    root,
+                )?);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn proof_rejects_index_not_in_tree() {
+        let tree = tree_with_leaves(5);
+        assert_eq!(
+            tree.proof(5, 5),
+            Err(MerkleProofError::IndexOutOfRange {
+                index: 5,
+                deposit_count: 5,
+            }),
+        );
+        assert_eq!(
+            tree.proof(0, 6),
+            Err(MerkleProofError::CountOutOfRange {
+                deposit_count: 6,
+                leaf_count: 5,
+            }),
+        );
+    }
+
+    #[test]
+    fn restored_tree_continues_like_original() -> Result<(), MerkleProofError> {
+        let mut original = tree_with_leaves(21);
+        let snapshot = original.snapshot(13)?;
+        assert_eq!(snapshot.finalized.len(), 3);
+
+        let mut restored = DepositTree::from_snapshot(&snapshot)?;
+        for index in 13..21 {
+            restored.push(leaf(index))?;
+        }
+        for index in 21..40 {
+            original.push(leaf(index))?;
+            restored.push(leaf(index))?;
+        }
+
+        for count in 13..=40 {
+            assert_eq!(restored.root_at(count)?, original.root_at(count)?);
+            for index in 13..count {
+                assert_eq!(restored.proof(index, count)?, original.proof(index, count)?);
+            }
+        }
+
+        assert!(restored.proof(0, 40).is_err());
+        assert!(restored.proof(11, 40).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_snapshot_rejects_wrong_root() -> Result<(), MerkleProofError> {
+        let mut snapshot = tree_with_leaves(6).snapshot(6)?;
+        snapshot.deposit_root = H256::zero();
+        assert!(DepositTree::from_snapshot(&snapshot).is_err());
+        Ok(())
+    }
+}
@@ -4,10 +4,26 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 
+pub use crate::deposit_tree::{DepositTree, DepositTreeSnapshot, DEPOSIT_TREE_DEPTH};
+
+mod deposit_tree;
+
 #[derive(Debug, PartialEq)]
 pub enum MerkleProofError {
     /// Params of not equal length were given
     InvalidParamLength { len_first: usize, len_second: usize },
+    /// The deposit tree already contains the maximum number of leaves
+    TreeFull,
+    /// More deposits were requested than the deposit tree contains
+    CountOutOfRange { deposit_count: u64, leaf_count: u64 },
+    /// A proof was requested for a leaf not included in the requested deposit count
+    IndexOutOfRange { index: u64, deposit_count: u64 },
+    /// A node was pruned when the deposit tree was restored from a snapshot
+    NodeNotAvailable { depth: usize, index: u64 },
+    /// The root of a deposit tree snapshot does not match its nodes
+    RootMismatch { expected: H256, actual: H256 },
+    /// The leaves and the proof of a multiproof do not determine the root
+    RootNotDerivable,
 }
 
 #[macro_use]
@@ -132,8 +148,12 @@ fn get_next_power_of_two(x: usize) -> usize {
 }
 
 // length of path
+// Computed with integer operations because `f32` cannot represent large generalized indices.
 fn get_generalized_index_length(index: usize) -> usize {
-    log_of!(index, 2., usize)
+    if index == 0 {
+        return 0;
+    }
+    (0_usize.count_zeros() - index.leading_zeros() - 1) as usize
 }
 
 const fn get_generalized_index_bit(index: usize, position: usize) -> bool {
@@ -178,7 +198,9 @@ fn get_path_indices(tree_index: usize) -> Vec<usize> {
 }
 
 //get all indices of all indices needed for the proof
-fn get_helper_indices(indices: &[usize]) -> Vec<usize> {
+// The result includes 0 (the "sibling" of the root), which is not a node and is ignored by
+// `verify_merkle_multiproof`. Proof generators should skip it.
+pub fn get_helper_indices(indices: &[usize]) -> Vec<usize> {
     let mut all_helper_indices: Vec<usize> = vec![];
     let mut all_path_indices: Vec<usize> = vec![];
     for index in indices.iter() {
@@ -275,7 +297,18 @@ fn calculate_multi_merkle_root(
         });
     }
 
-    let helper_indices = get_helper_indices(indices);
+    // Proofs do not contain a node for index 0. See `get_helper_indices`.
+    let helper_indices = get_helper_indices(indices)
+        .into_iter()
+        .filter(|index| *index != 0)
+        .collect::<Vec<_>>();
+
+    if proof.len() != helper_indices.len() {
+        return Err(MerkleProofError::InvalidParamLength {
+            len_first: proof.len(),
+            len_second: helper_indices.len(),
+        });
+    }
 
     for (index, leave) in indices.iter().zip(leaves.iter()) {
         index_leave_map.insert(*index, *leave);
@@ -287,36 +320,21 @@ fn calculate_multi_merkle_root(
 
     index_leave_map.extend(helper_proof_map);
 
-    let mut keys: Vec<usize> = vec![];
-
-    for key in index_leave_map.keys() {
-        keys.push(key.clone());
-    }
-
-    keys.sort();
-    keys = reverse_vector(&keys);
-    let mut biggest: usize = *keys.get(0_usize).clone().expect("No keys");
-
-    while biggest > 0 {
-        if !keys.contains(&biggest) {
-            keys.push(biggest);
-        }
-        biggest -= 1;
-    }
-
+    // Based on `calculate_multi_merkle_root` in the specification. Only parents of known nodes are
+    // visited, which keeps this fast for the large generalized indices of `BeaconState` fields.
+    let mut keys: Vec<usize> = index_leave_map.keys().cloned().collect();
     keys.sort();
     keys = reverse_vector(&keys);
 
-    let mut position = 1_usize;
+    let mut position = 0_usize;
 
     while position < keys.len() {
-        // Safe because keys vector is filled above.
         let k = keys[position];
         let contains_itself: bool = index_leave_map.contains_key(&k);
         let contains_sibling: bool = index_leave_map.contains_key(&(k ^ 1));
         let contains_parent: bool = index_leave_map.contains_key(&(k / 2));
 
-        if contains_itself && contains_sibling && !contains_parent {
+        if k > 1 && contains_itself && contains_sibling && !contains_parent {
             let index_first: usize = (k | 1) ^ 1; //right
             let index_second: usize = k | 1; //left
 
@@ -327,13 +345,15 @@ fn calculate_multi_merkle_root(
                     index_leave_map[&index_second],
                 ),
             );
+            keys.push(k / 2);
         }
         position += 1;
     }
 
-    // Safe because keys vector is full and value is inserted in those indeces.
-    // index_leave_map.remove(&1usize);
-    Ok(index_leave_map[&1_usize])
+    index_leave_map
+        .get(&1_usize)
+        .copied()
+        .ok_or(MerkleProofError::RootNotDerivable)
 }
 
 #[cfg(test)]
@@ -696,13 +716,8 @@ mod tests {
         let root = get_merkle_root(&[fourth, fifth, sixth, seventh], 4);
 
         assert_eq!(
-            verify_merkle_multiproof(
-                &[fourth, fifth, seventh],
-                &[sixth, second],
-                &[4, 5, 7],
-                root
-            )
-            .expect("verification of multiproof failed!"),
+            verify_merkle_multiproof(&[fourth, fifth, seventh], &[sixth], &[4, 5, 7], root)
+                .expect("verification of multiproof failed!"),
             true
         );
 
@@ -713,39 +728,45 @@ mod tests {
         );
 
         assert_eq!(
-            verify_merkle_multiproof(&[fourth, fifth, sixth], &[sixth, second], &[4, 5, 7], root)
+            verify_merkle_multiproof(&[fourth, fifth, sixth], &[sixth], &[4, 5, 7], root)
                 .expect("verification of multiproof failed!"),
             false
         );
 
         assert_eq!(
-            verify_merkle_multiproof(
-                &[fourth, sixth, fifth],
-                &[seventh, second],
-                &[4, 5, 6],
-                root
-            )
-            .expect("verification of multiproof failed!"),
+            verify_merkle_multiproof(&[fourth, sixth, fifth], &[seventh], &[4, 5, 6], root)
+                .expect("verification of multiproof failed!"),
             false
         );
 
         assert_eq!(
-            verify_merkle_multiproof(
-                &[fourth, fifth, sixth],
-                &[seventh, second],
-                &[4, 5, 6],
-                root
-            )
-            .expect("verification of multiproof failed!"),
+            verify_merkle_multiproof(&[fourth, fifth, sixth], &[seventh], &[4, 5, 6], root)
+                .expect("verification of multiproof failed!"),
             true
         );
 
         assert_eq!(
-            verify_merkle_multiproof(&[fourth, fifth], &[second, second], &[4, 5], root)
+            verify_merkle_multiproof(&[fourth, fifth], &[second], &[4, 5], root)
                 .expect("verification of multiproof failed!"),
             true
         );
 
+        assert_eq!(
+            verify_merkle_multiproof(&[fourth, fifth], &[second, second], &[4, 5], root),
+            Err(MerkleProofError::InvalidParamLength {
+                len_first: 2,
+                len_second: 1
+            })
+        );
+
+        assert_eq!(
+            verify_merkle_multiproof(&[fourth], &[fifth], &[4], root),
+            Err(MerkleProofError::InvalidParamLength {
+                len_first: 1,
+                len_second: 2
+            })
+        );
+
         assert_eq!(
             verify_merkle_multiproof(&[fourth], &[fifth, second], &[4], root)
                 .expect("verification of multiproof failed!"),